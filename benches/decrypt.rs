@@ -0,0 +1,29 @@
+use std::io::Cursor;
+
+use arq::object_encryption::EncryptedObject;
+use arq::test_utils::{encrypt, master_keys};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn decrypt(c: &mut Criterion) {
+    let keys = master_keys();
+    let mut group = c.benchmark_group("EncryptedObject::decrypt");
+    for size in [4 * 1024, 1024 * 1024] {
+        let object = EncryptedObject::new(Cursor::new(encrypt(&vec![7; size], &keys))).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &object, |b, object| {
+            b.iter(|| object.decrypt(&keys[0]).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn validate(c: &mut Criterion) {
+    let keys = master_keys();
+    let object = EncryptedObject::new(Cursor::new(encrypt(&[7; 1024 * 1024], &keys))).unwrap();
+    c.bench_function("EncryptedObject::validate", |b| {
+        b.iter(|| object.validate(&keys[1]).unwrap())
+    });
+}
+
+criterion_group!(benches, decrypt, validate);
+criterion_main!(benches);
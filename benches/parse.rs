@@ -0,0 +1,76 @@
+use std::io::Cursor;
+
+use arq::compression::CompressionType;
+use arq::packset::Pack;
+use arq::test_utils::{
+    commit_bytes, encrypt, lz4_compress, master_keys, node_bytes, pack_bytes, tree_bytes,
+};
+use arq::tree::{Commit, Tree};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// A tree of `nodes` files, each made of a couple of blobs.
+fn tree_content(nodes: usize) -> Vec<u8> {
+    let names: Vec<String> = (0..nodes)
+        .map(|index| format!("file-{index:05}.txt"))
+        .collect();
+    let nodes: Vec<(&str, Vec<u8>)> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let sha1s: Vec<String> = (0..1 + index % 3)
+                .map(|blob| format!("{index:020x}{blob:020x}"))
+                .collect();
+            let sha1s: Vec<&str> = sha1s.iter().map(String::as_str).collect();
+            let size = (sha1s.len() * 65536) as u64;
+            (name.as_str(), node_bytes(false, &sha1s, size, 0o100644))
+        })
+        .collect();
+    tree_bytes(&nodes)
+}
+
+fn tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Tree::new");
+    for nodes in [10, 1000] {
+        let content = tree_content(nodes);
+        let compressed = lz4_compress(&content);
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_with_input(BenchmarkId::new("none", nodes), &content, |b, content| {
+            b.iter(|| Tree::new(content, CompressionType::None).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("lz4", nodes), &compressed, |b, content| {
+            b.iter(|| Tree::new(content, CompressionType::LZ4).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn commit(c: &mut Criterion) {
+    let content = commit_bytes(&"1".repeat(40), Some(&"2".repeat(40)));
+    c.bench_function("Commit::new", |b| {
+        b.iter(|| Commit::new(Cursor::new(&content)).unwrap())
+    });
+}
+
+/// The trailing SHA1 is checked while parsing (through `Sha1Reader`), so besides the
+/// objects themselves no pack-sized buffer is allocated: verifying the 10 x 1 MiB pack
+/// used to copy all 10 MiB of it just to hash them.
+fn pack(c: &mut Criterion) {
+    let keys = master_keys();
+    let mut group = c.benchmark_group("Pack::new");
+    for (count, size) in [(100, 4 * 1024), (10, 1024 * 1024)] {
+        let objects: Vec<Vec<u8>> = (0..count)
+            .map(|index| encrypt(&vec![index as u8; size], &keys))
+            .collect();
+        let (content, _) = pack_bytes(&objects);
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{count}x{size}")),
+            &content,
+            |b, content| b.iter(|| Pack::new(Cursor::new(content)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tree, commit, pack);
+criterion_main!(benches);
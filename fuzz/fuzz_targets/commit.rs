@@ -0,0 +1,7 @@
+#![no_main]
+use arq::tree::Commit;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Commit::new(std::io::Cursor::new(data));
+});
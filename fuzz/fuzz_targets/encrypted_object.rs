@@ -0,0 +1,7 @@
+#![no_main]
+use arq::object_encryption::EncryptedObject;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = EncryptedObject::new(std::io::Cursor::new(data));
+});
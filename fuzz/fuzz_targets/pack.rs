@@ -0,0 +1,7 @@
+#![no_main]
+use arq::packset::Pack;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Pack::new(std::io::Cursor::new(data));
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use arq::packset::PackIndex;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PackIndex::new(std::io::Cursor::new(data));
+});
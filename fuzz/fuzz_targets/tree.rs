@@ -0,0 +1,9 @@
+#![no_main]
+use arq::compression::CompressionType;
+use arq::tree::Tree;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Tree::new(data, CompressionType::None);
+    let _ = Tree::new(data, CompressionType::LZ4);
+});
@@ -0,0 +1,833 @@
+//! Backup sets
+//! -----------
+//!
+//! A backup set is everything Arq stores for one computer at a destination:
+//!
+//! ```ascii
+//! /<computer_uuid>/
+//!     computerinfo                            plist, see [ComputerInfo]
+//!     encryptionv3.dat                        see [EncryptionDat]
+//!     buckets/<folder_uuid>                   one per folder, see [Folder]
+//!     bucketdata/<folder_uuid>/               refs, see [BucketData]
+//!     packsets/<folder_uuid>-blobs/           packs of file contents
+//!     packsets/<folder_uuid>-trees/           packs of commits and trees
+//! ```
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::bucketdata::{self, BucketData};
+use crate::compression::CompressionType;
+use crate::computer::ComputerInfo;
+use crate::error::{Error, Result};
+use crate::folder::{self, Folder};
+#[cfg(feature = "reqwest")]
+use crate::http::HttpResolver;
+use crate::object_encryption::EncryptionDat;
+#[cfg(feature = "reqwest")]
+use crate::object_encryption::MasterKeys;
+use crate::packset::{Pack, PackIndex, PackSet};
+use crate::resolver::BlobResolver;
+#[cfg(feature = "reqwest")]
+use crate::resolver::CachedResolver;
+use crate::tree::{Commit, Tree};
+
+/// The backup set of a single computer.
+pub struct BackupSet {
+    path: PathBuf,
+    pub computer_info: ComputerInfo,
+    /// UUIDs of the folders backed up, sorted.
+    pub folder_uuids: Vec<String>,
+    #[cfg(feature = "reqwest")]
+    remote: Option<Remote>,
+}
+
+/// Where a backup set opened with [BackupSet::open_remote] is served from.
+#[cfg(feature = "reqwest")]
+struct Remote {
+    client: reqwest::blocking::Client,
+    base_url: String,
+}
+
+/// Summary of a backup set, see [BackupSet::stats].
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct BackupSetStats {
+    pub folders: Vec<FolderStats>,
+}
+
+/// Summary of a single folder in a backup set.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FolderStats {
+    pub uuid: String,
+    /// Number of packs, blobs and trees together
+    pub packs: usize,
+    /// Size of those packs, in bytes
+    pub pack_bytes: u64,
+    /// When the master ref was last updated, i.e. when the latest commit was made. `None`
+    /// if the folder has no commits.
+    pub latest_commit: Option<SystemTime>,
+}
+
+/// How much space deduplication saves, see [BackupSet::dedup_report].
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DedupReport {
+    /// Sum of the sizes of all files in the latest backup of each folder.
+    pub logical_bytes: u64,
+    /// Sum of the stored (encrypted) sizes of the distinct blobs those files are made of.
+    pub physical_bytes: u64,
+    /// Distinct blobs that aren't in any blobs packset index, and so aren't counted in
+    /// `physical_bytes`.
+    pub unindexed_blobs: usize,
+}
+
+impl DedupReport {
+    /// `logical_bytes / physical_bytes`, e.g. 2.0 when every file is stored twice. 1.0 if
+    /// nothing is stored.
+    pub fn ratio(&self) -> f64 {
+        if self.physical_bytes == 0 {
+            return 1.0;
+        }
+        self.logical_bytes as f64 / self.physical_bytes as f64
+    }
+}
+
+/// The kinds of checks making up a [ValidationReport].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckKind {
+    /// `encryptionv3.dat` decrypts with the password and its HMAC matches.
+    EncryptionDat,
+    /// A pack index matches its trailing SHA1.
+    PackIndex,
+    /// A pack matches its trailing SHA1.
+    Pack,
+    /// An object in a pack matches its HMAC.
+    Object,
+    /// A commit, tree or blob in a folder's history resolves (and parses, for commits and
+    /// trees).
+    Reference,
+}
+
+/// The outcome of a single check made by [BackupSet::validate_all].
+#[derive(Debug)]
+pub struct ValidationCheck {
+    pub kind: CheckKind,
+    /// What was checked: a file's path, `<pack path>#<n>` for the `n`th object of a pack,
+    /// or the SHA1 of a referenced object.
+    pub subject: String,
+    /// Why the check failed, `None` if it passed.
+    pub error: Option<Error>,
+}
+
+/// Result of [BackupSet::validate_all].
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub checks: Vec<ValidationCheck>,
+}
+
+impl ValidationReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ValidationCheck> {
+        self.checks.iter().filter(|check| check.error.is_some())
+    }
+
+    /// Number of checks of `kind` that passed and failed, in that order.
+    pub fn count(&self, kind: CheckKind) -> (usize, usize) {
+        let checks = self.checks.iter().filter(|check| check.kind == kind);
+        checks.fold((0, 0), |(passed, failed), check| match check.error {
+            None => (passed + 1, failed),
+            Some(_) => (passed, failed + 1),
+        })
+    }
+
+    fn push<T>(&mut self, kind: CheckKind, subject: String, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.checks.push(ValidationCheck {
+                    kind,
+                    subject,
+                    error: None,
+                });
+                Some(value)
+            }
+            Err(err) => {
+                self.checks.push(ValidationCheck {
+                    kind,
+                    subject,
+                    error: Some(err),
+                });
+                None
+            }
+        }
+    }
+}
+
+impl BackupSetStats {
+    pub fn total_packs(&self) -> usize {
+        self.folders.iter().map(|folder| folder.packs).sum()
+    }
+
+    pub fn total_pack_bytes(&self) -> u64 {
+        self.folders.iter().map(|folder| folder.pack_bytes).sum()
+    }
+}
+
+impl BackupSet {
+    /// Opens the backup set at `path` (`/<computer_uuid>`), reading its computer info and
+    /// listing its folders.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<BackupSet> {
+        let path = path.as_ref().to_path_buf();
+        let uuid = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let computer_info =
+            ComputerInfo::new(BufReader::new(File::open(path.join("computerinfo"))?), uuid)?;
+
+        let mut folder_uuids = Vec::new();
+        for entry in fs::read_dir(path.join("buckets"))? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                folder_uuids.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        folder_uuids.sort();
+
+        Ok(BackupSet {
+            path,
+            computer_info,
+            folder_uuids,
+            #[cfg(feature = "reqwest")]
+            remote: None,
+        })
+    }
+
+    /// Opens the backup set served over HTTP at `base_url`
+    /// (`https://<destination>/<computer_uuid>`), so it can be browsed without
+    /// downloading it first.
+    ///
+    /// The computer info and `encryptionv3.dat` are fetched straight away, the latter to
+    /// check `password`. After that the backup set reads its files (folders, refs,
+    /// `encryptionv3.dat`) from the destination as [BackupSet::open] reads them from disk,
+    /// and objects are resolved through [BackupSet::remote_resolver].
+    ///
+    /// HTTP can't list directories, so `folder_uuids` starts out empty: add the UUIDs of
+    /// the folders to browse to it. For the same reason, methods that work on the backup
+    /// set's directory ([BackupSet::stats], [BackupSet::validate_all], ...) fail with an
+    /// `ErrorKind::Unsupported` I/O error.
+    #[cfg(feature = "reqwest")]
+    pub fn open_remote(base_url: &str, password: &str) -> Result<BackupSet> {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let uuid = base_url.rsplit('/').next().unwrap_or_default().to_string();
+        let remote = Remote {
+            client: reqwest::blocking::Client::new(),
+            base_url,
+        };
+        let computer_info = ComputerInfo::new(
+            Cursor::new(crate::http::fetch(
+                &remote.client,
+                &format!("{}/computerinfo", remote.base_url),
+            )?),
+            uuid,
+        )?;
+
+        let backup_set = BackupSet {
+            path: PathBuf::from(&remote.base_url),
+            computer_info,
+            folder_uuids: Vec::new(),
+            remote: Some(remote),
+        };
+        backup_set.encryption_dat(password)?;
+        Ok(backup_set)
+    }
+
+    /// A resolver for the trees and commits of the remote folder `folder_uuid`, reading
+    /// the packs `pack_sha1s` of its trees packset over HTTP.
+    ///
+    /// HTTP can't list directories, so the packs have to be known up front, e.g. from the
+    /// destination's own listing API. Objects are looked up first in the local tree cache
+    /// under `cache_root` (see [PackSet::open_tree_cache]), which is created if it doesn't
+    /// exist yet, so trees already cached aren't fetched again.
+    ///
+    /// Fails with `Error::PathNotFound` for backup sets opened with [BackupSet::open].
+    #[cfg(feature = "reqwest")]
+    pub fn remote_resolver<P: AsRef<Path>>(
+        &self,
+        folder_uuid: &str,
+        pack_sha1s: &[&str],
+        master_keys: &[Vec<u8>],
+        cache_root: P,
+    ) -> Result<CachedResolver<PackSet, HttpResolver>> {
+        let resolver =
+            self.remote_packset(&format!("{folder_uuid}-trees"), pack_sha1s, master_keys)?;
+
+        fs::create_dir_all(
+            cache_root
+                .as_ref()
+                .join("treepacks")
+                .join(format!("{folder_uuid}-trees")),
+        )?;
+        let cache = PackSet::open_tree_cache(cache_root, folder_uuid, master_keys)?;
+        Ok(CachedResolver::new(cache, resolver))
+    }
+
+    /// A resolver for the file contents of the remote folder `folder_uuid`, reading the
+    /// packs `pack_sha1s` of its blobs packset over HTTP. Like Arq, blobs aren't cached.
+    ///
+    /// Fails with `Error::PathNotFound` for backup sets opened with [BackupSet::open].
+    #[cfg(feature = "reqwest")]
+    pub fn remote_blobs_resolver(
+        &self,
+        folder_uuid: &str,
+        pack_sha1s: &[&str],
+        master_keys: &[Vec<u8>],
+    ) -> Result<HttpResolver> {
+        self.remote_packset(&format!("{folder_uuid}-blobs"), pack_sha1s, master_keys)
+    }
+
+    /// An [HttpResolver] for the packs `pack_sha1s` of the remote packset `name`.
+    #[cfg(feature = "reqwest")]
+    fn remote_packset(
+        &self,
+        name: &str,
+        pack_sha1s: &[&str],
+        master_keys: &[Vec<u8>],
+    ) -> Result<HttpResolver> {
+        let remote = self
+            .remote
+            .as_ref()
+            .ok_or_else(|| Error::PathNotFound(self.path.clone()))?;
+        let mut resolver = HttpResolver::new(
+            &format!("{}/packsets/{name}", remote.base_url),
+            &MasterKeys::from_slices(master_keys)?,
+        );
+        for pack_sha1 in pack_sha1s {
+            resolver.add_pack(pack_sha1)?;
+        }
+        Ok(resolver)
+    }
+
+    /// Reads the file at `relative` (e.g. `buckets/<folder_uuid>`), from the destination
+    /// for backup sets opened with [BackupSet::open_remote].
+    fn read_file(&self, relative: &str) -> Result<Vec<u8>> {
+        #[cfg(feature = "reqwest")]
+        if let Some(remote) = &self.remote {
+            let url = format!("{}/{relative}", remote.base_url);
+            return crate::http::fetch(&remote.client, &url);
+        }
+        Ok(fs::read(self.path.join(relative))?)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The backup set's directory, failing with an `ErrorKind::Unsupported` I/O error for
+    /// backup sets opened with [BackupSet::open_remote], which have none to list or stat.
+    fn local_path(&self) -> Result<&Path> {
+        #[cfg(feature = "reqwest")]
+        if self.remote.is_some() {
+            let message = format!("{} is a remote backup set", self.path.display());
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, message).into());
+        }
+        Ok(&self.path)
+    }
+
+    /// The computer's UUID, which is also the name of the backup set's directory.
+    pub fn uuid(&self) -> &str {
+        &self.computer_info.uuid
+    }
+
+    /// Reads and decrypts `encryptionv3.dat` with the user's password.
+    pub fn encryption_dat(&self, password: &str) -> Result<EncryptionDat> {
+        EncryptionDat::new(Cursor::new(self.read_file("encryptionv3.dat")?), password)
+    }
+
+    /// Reads and decrypts the folder `folder_uuid`.
+    pub fn folder(&self, folder_uuid: &str, master_keys: &[Vec<u8>]) -> Result<Folder> {
+        Folder::new(
+            Cursor::new(self.read_file(&format!("buckets/{folder_uuid}"))?),
+            master_keys,
+        )
+    }
+
+    /// Reads the SHA1 of the most recent commit of `folder_uuid`, like
+    /// [BucketData::master_ref] but also for remote backup sets.
+    pub fn master_ref(&self, folder_uuid: &str) -> Result<String> {
+        let content = self.read_file(&format!("bucketdata/{folder_uuid}/refs/heads/master"))?;
+        Ok(bucketdata::parse_ref(std::str::from_utf8(&content)?))
+    }
+
+    /// The bucket data of `folder_uuid`. Fails for remote backup sets, see
+    /// [BackupSet::master_ref] for those.
+    pub fn bucketdata(&self, folder_uuid: &str) -> Result<BucketData> {
+        Ok(BucketData::new(self.local_path()?, folder_uuid))
+    }
+
+    /// Directory of the packset holding a folder's file contents. Fails for remote backup
+    /// sets, see [BackupSet::remote_blobs_resolver] for those.
+    pub fn blobs_path(&self, folder_uuid: &str) -> Result<PathBuf> {
+        Ok(self
+            .local_path()?
+            .join("packsets")
+            .join(format!("{folder_uuid}-blobs")))
+    }
+
+    /// Directory of the packset holding a folder's commits and trees. Fails for remote
+    /// backup sets, see [BackupSet::remote_resolver] for those.
+    pub fn trees_path(&self, folder_uuid: &str) -> Result<PathBuf> {
+        Ok(self
+            .local_path()?
+            .join("packsets")
+            .join(format!("{folder_uuid}-trees")))
+    }
+
+    /// Summarizes the backup set from its directory listing alone, without reading or
+    /// decrypting any object. Fails for remote backup sets.
+    pub fn stats(&self) -> Result<BackupSetStats> {
+        self.local_path()?;
+        let mut folders = Vec::with_capacity(self.folder_uuids.len());
+        for uuid in &self.folder_uuids {
+            let mut packs = 0;
+            let mut pack_bytes = 0;
+            for packset in [self.blobs_path(uuid)?, self.trees_path(uuid)?] {
+                let entries = match fs::read_dir(&packset) {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                for entry in entries {
+                    let path = entry?.path();
+                    if path.extension() == Some("pack".as_ref()) {
+                        packs += 1;
+                        pack_bytes += fs::metadata(&path)?.len();
+                    }
+                }
+            }
+
+            let latest_commit = match fs::metadata(self.bucketdata(uuid)?.master_ref_path()) {
+                Ok(metadata) => Some(metadata.modified()?),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err.into()),
+            };
+
+            folders.push(FolderStats {
+                uuid: uuid.clone(),
+                packs,
+                pack_bytes,
+                latest_commit,
+            });
+        }
+        Ok(BackupSetStats { folders })
+    }
+
+    /// Compares the size of the files in each folder's latest backup with the space
+    /// their contents take up in the blobs packsets.
+    ///
+    /// Trees are resolved through `resolver`, while blob sizes come from the pack
+    /// indexes, so blobs are never fetched. A blob shared by several files (or folders)
+    /// only counts once towards `physical_bytes`. Folders without commits are skipped.
+    /// Fails for remote backup sets.
+    pub fn dedup_report<B: BlobResolver>(&self, resolver: &B) -> Result<DedupReport> {
+        self.local_path()?;
+        let mut report = DedupReport::default();
+        let mut seen = HashSet::new();
+        for uuid in &self.folder_uuids {
+            let tree = match folder::latest_tree(&self.bucketdata(uuid)?, resolver) {
+                Ok(tree) => tree,
+                Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            // Only the indexes are read, so no keys are needed.
+            let blobs = match PackSet::open(self.blobs_path(uuid)?, &[]) {
+                Ok(blobs) => Some(blobs),
+                Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            };
+
+            tree.walk(resolver, |_, node| {
+                if node.is_tree {
+                    return Ok(());
+                }
+                report.logical_bytes += node.data_size;
+                for sha1 in node.data_sha1s() {
+                    if !seen.insert(sha1.to_string()) {
+                        continue;
+                    }
+                    match blobs.as_ref().and_then(|blobs| blobs.location(sha1)) {
+                        Some(location) => report.physical_bytes += location.data_len as u64,
+                        None => report.unindexed_blobs += 1,
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(report)
+    }
+
+    /// Checks every layer of the backup set: `encryptionv3.dat` against `password`, the
+    /// trailing SHA1 of every pack and pack index, the HMAC of every object in the packs,
+    /// and that every commit, tree and blob in each folder's history can be resolved
+    /// through `resolver`.
+    ///
+    /// Each check is reported on its own, so one failure doesn't stop the others. Objects
+    /// can only be checked if `encryptionv3.dat` could be read. Only errors listing the
+    /// backup set's directories are returned as errors, which is always the case for
+    /// remote backup sets.
+    pub fn validate_all<B: BlobResolver>(
+        &self,
+        password: &str,
+        resolver: &B,
+    ) -> Result<ValidationReport> {
+        self.local_path()?;
+        let mut report = ValidationReport::default();
+        let master_keys = report
+            .push(
+                CheckKind::EncryptionDat,
+                self.path.join("encryptionv3.dat").display().to_string(),
+                self.encryption_dat(password),
+            )
+            .map(EncryptionDat::into_master_keys);
+
+        for uuid in &self.folder_uuids {
+            for packset in [self.blobs_path(uuid)?, self.trees_path(uuid)?] {
+                let master_keys = master_keys.as_deref().map(Vec::as_slice);
+                validate_packset(&packset, master_keys, &mut report)?;
+            }
+
+            let head = match self.bucketdata(uuid)?.master_ref() {
+                Ok(head) => head,
+                Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut seen = HashSet::new();
+            let mut pending = vec![head];
+            while let Some(sha1) = pending.pop() {
+                if !seen.insert(sha1.clone()) {
+                    continue;
+                }
+                let commit = resolver
+                    .resolve(&sha1)
+                    .and_then(|content| Commit::new(Cursor::new(content)));
+                if let Some(commit) = report.push(CheckKind::Reference, sha1, commit) {
+                    let mut parents: Vec<String> = commit.parent_commits.into_keys().collect();
+                    parents.sort();
+                    pending.extend(parents);
+                    validate_tree(
+                        &commit.tree_sha1,
+                        commit.tree_compression_type,
+                        resolver,
+                        &mut seen,
+                        &mut report,
+                    );
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Checks the packs and indexes in `path`, and the objects in them if `master_keys` are
+/// available. A missing packset has nothing to check.
+fn validate_packset(
+    path: &Path,
+    master_keys: Option<&[Vec<u8>]>,
+    report: &mut ValidationReport,
+) -> Result<()> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+
+    for path in paths {
+        let subject = path.display().to_string();
+        if path.extension() == Some("index".as_ref()) {
+            let index = File::open(&path)
+                .map_err(Error::from)
+                .and_then(|file| PackIndex::new(BufReader::new(file)));
+            report.push(CheckKind::PackIndex, subject, index);
+        } else if path.extension() == Some("pack".as_ref()) {
+            let pack = File::open(&path)
+                .map_err(Error::from)
+                .and_then(|file| Pack::new(BufReader::new(file)));
+            let (Some(pack), Some(master_keys)) = (
+                report.push(CheckKind::Pack, subject.clone(), pack),
+                master_keys,
+            ) else {
+                continue;
+            };
+            for (n, object) in pack.objects.iter().enumerate() {
+                report.push(
+                    CheckKind::Object,
+                    format!("{subject}#{n}"),
+                    object.data.verify_only(&master_keys[1]),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the tree `sha1` and everything it references resolve, skipping objects
+/// already in `seen`.
+fn validate_tree<B: BlobResolver>(
+    sha1: &str,
+    compression_type: CompressionType,
+    resolver: &B,
+    seen: &mut HashSet<String>,
+    report: &mut ValidationReport,
+) {
+    if !seen.insert(sha1.to_string()) {
+        return;
+    }
+    let tree = resolver
+        .resolve(sha1)
+        .and_then(|content| Tree::new(&content, compression_type));
+    let Some(tree) = report.push(CheckKind::Reference, sha1.to_string(), tree) else {
+        return;
+    };
+
+    let mut names: Vec<&String> = tree.nodes.keys().collect();
+    names.sort();
+    for name in names {
+        let node = &tree.nodes[name];
+        if node.is_tree {
+            if let Some(blob_key) = node.data_blob_keys.first() {
+                validate_tree(
+                    &blob_key.sha1,
+                    node.data_compression_type.clone(),
+                    resolver,
+                    seen,
+                    report,
+                );
+            }
+        }
+        let data_sha1s = if node.is_tree {
+            Vec::new()
+        } else {
+            node.data_sha1s()
+        };
+        let blob_sha1s = data_sha1s.into_iter().chain(
+            [&node.xattrs_blob_key, &node.acl_blob_key]
+                .into_iter()
+                .flatten()
+                .map(|blob_key| blob_key.sha1.as_str()),
+        );
+        for blob_sha1 in blob_sha1s {
+            if seen.insert(blob_sha1.to_string()) {
+                let result = resolver.resolve(blob_sha1);
+                report.push(CheckKind::Reference, blob_sha1.to_string(), result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::CachedResolver;
+    use crate::test_utils::{commit_bytes, master_keys, node_bytes, tree_bytes, write_pack};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let computer = dir.path().join("COMPUTER-UUID");
+        fs::create_dir_all(computer.join("buckets")).unwrap();
+        fs::write(
+            computer.join("computerinfo"),
+            "<plist version=\"1.0\"><dict>\
+               <key>userName</key><string>someuser</string>\
+               <key>computerName</key><string>somecomputer</string>\
+             </dict></plist>",
+        )
+        .unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-A"), "").unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-B"), "").unwrap();
+
+        let backupset = BackupSet::open(&computer).unwrap();
+        assert_eq!(backupset.uuid(), "COMPUTER-UUID");
+        assert_eq!(backupset.folder_uuids, vec!["FOLDER-A", "FOLDER-B"]);
+
+        let keys = master_keys();
+        write_pack(
+            &backupset.blobs_path("FOLDER-A").unwrap(),
+            &[b"one", b"two"],
+            &keys,
+        );
+        write_pack(
+            &backupset.blobs_path("FOLDER-A").unwrap(),
+            &[b"three"],
+            &keys,
+        );
+        write_pack(
+            &backupset.trees_path("FOLDER-A").unwrap(),
+            &[b"tree"],
+            &keys,
+        );
+        let master_ref = backupset.bucketdata("FOLDER-A").unwrap().master_ref_path();
+        fs::create_dir_all(master_ref.parent().unwrap()).unwrap();
+        fs::write(&master_ref, "c0571537d57d9488164303950dfded5cb6cfcd20Y").unwrap();
+
+        let stats = backupset.stats().unwrap();
+        assert_eq!(stats.folders.len(), 2);
+        let folder_a = &stats.folders[0];
+        assert_eq!(folder_a.packs, 3);
+        assert!(folder_a.pack_bytes > 0);
+        assert_eq!(
+            folder_a.latest_commit,
+            Some(fs::metadata(&master_ref).unwrap().modified().unwrap())
+        );
+        assert_eq!(stats.folders[1].packs, 0);
+        assert_eq!(stats.folders[1].latest_commit, None);
+        assert_eq!(stats.total_packs(), 3);
+        assert_eq!(stats.total_pack_bytes(), folder_a.pack_bytes);
+    }
+
+    #[test]
+    fn test_dedup_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let computer = dir.path().join("COMPUTER-UUID");
+        fs::create_dir_all(computer.join("buckets")).unwrap();
+        fs::write(
+            computer.join("computerinfo"),
+            "<plist version=\"1.0\"><dict>\
+               <key>userName</key><string>someuser</string>\
+               <key>computerName</key><string>somecomputer</string>\
+             </dict></plist>",
+        )
+        .unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-A"), "").unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-B"), "").unwrap();
+        let backupset = BackupSet::open(&computer).unwrap();
+
+        let keys = master_keys();
+        let sha1s = write_pack(
+            &backupset.blobs_path("FOLDER-A").unwrap(),
+            &[b"ten bytes!", b"five!"],
+            &keys,
+        );
+        // "a" and "copy" have the same contents, so they share a blob.
+        let root = tree_bytes(&[
+            ("a", node_bytes(false, &[&sha1s[0]], 10, 0o100644)),
+            ("b", node_bytes(false, &[&sha1s[1]], 5, 0o100644)),
+            ("copy", node_bytes(false, &[&sha1s[0]], 10, 0o100644)),
+            ("gone", node_bytes(false, &["missing"], 3, 0o100644)),
+        ]);
+        let mut objects = HashMap::new();
+        objects.insert("roottree".to_string(), root);
+        objects.insert("head".to_string(), commit_bytes("roottree", None));
+
+        let master_ref = backupset.bucketdata("FOLDER-A").unwrap().master_ref_path();
+        fs::create_dir_all(master_ref.parent().unwrap()).unwrap();
+        fs::write(&master_ref, "headY").unwrap();
+
+        let blobs = PackSet::open(backupset.blobs_path("FOLDER-A").unwrap(), &keys).unwrap();
+        let physical: u64 = sha1s
+            .iter()
+            .map(|sha1| blobs.location(sha1).unwrap().data_len as u64)
+            .sum();
+
+        let report = backupset.dedup_report(&objects).unwrap();
+        assert_eq!(report.logical_bytes, 28);
+        assert_eq!(report.physical_bytes, physical);
+        assert_eq!(report.unindexed_blobs, 1);
+        assert_eq!(report.ratio(), 28.0 / physical as f64);
+
+        assert_eq!(DedupReport::default().ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_validate_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let computer = dir.path().join("COMPUTER-UUID");
+        fs::create_dir_all(computer.join("buckets")).unwrap();
+        fs::write(
+            computer.join("computerinfo"),
+            "<plist version=\"1.0\"><dict>\
+               <key>userName</key><string>someuser</string>\
+               <key>computerName</key><string>somecomputer</string>\
+             </dict></plist>",
+        )
+        .unwrap();
+        fs::copy(
+            "fixtures/AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D/encryptionv3.dat",
+            computer.join("encryptionv3.dat"),
+        )
+        .unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-A"), "").unwrap();
+        fs::write(computer.join("buckets").join("FOLDER-B"), "").unwrap();
+        let backupset = BackupSet::open(&computer).unwrap();
+        let keys = backupset.encryption_dat("evu").unwrap().into_master_keys();
+
+        let blobs = write_pack(
+            &backupset.blobs_path("FOLDER-A").unwrap(),
+            &[b"ten bytes!", b"five!"],
+            &keys,
+        );
+        let root = tree_bytes(&[
+            ("a", node_bytes(false, &[&blobs[0]], 10, 0o100644)),
+            ("b", node_bytes(false, &[&blobs[1]], 5, 0o100644)),
+        ]);
+        let trees = write_pack(&backupset.trees_path("FOLDER-A").unwrap(), &[&root], &keys);
+        let commit = commit_bytes(&trees[0], None);
+        let head = write_pack(
+            &backupset.trees_path("FOLDER-A").unwrap(),
+            &[&commit],
+            &keys,
+        );
+        let master_ref = backupset.bucketdata("FOLDER-A").unwrap().master_ref_path();
+        fs::create_dir_all(master_ref.parent().unwrap()).unwrap();
+        fs::write(&master_ref, format!("{}Y", head[0])).unwrap();
+
+        let resolver = || {
+            CachedResolver::new(
+                PackSet::open(backupset.trees_path("FOLDER-A").unwrap(), &keys).unwrap(),
+                PackSet::open(backupset.blobs_path("FOLDER-A").unwrap(), &keys).unwrap(),
+            )
+        };
+        let report = backupset.validate_all("evu", &resolver()).unwrap();
+        assert!(report.is_healthy(), "{report:?}");
+        assert_eq!(report.count(CheckKind::EncryptionDat), (1, 0));
+        assert_eq!(report.count(CheckKind::PackIndex), (3, 0));
+        assert_eq!(report.count(CheckKind::Pack), (3, 0));
+        assert_eq!(report.count(CheckKind::Object), (4, 0));
+        // The commit, its tree and both blobs.
+        assert_eq!(report.count(CheckKind::Reference), (4, 0));
+
+        // Corrupting the blobs pack breaks its checksum and the blobs in it.
+        let pack_path = fs::read_dir(backupset.blobs_path("FOLDER-A").unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension() == Some("pack".as_ref()))
+            .unwrap();
+        let mut pack = fs::read(&pack_path).unwrap();
+        let last = pack.len() - 21;
+        pack[last] ^= 0xff;
+        fs::write(&pack_path, pack).unwrap();
+
+        let report = backupset.validate_all("evu", &resolver()).unwrap();
+        assert!(!report.is_healthy());
+        assert_eq!(report.count(CheckKind::Pack), (2, 1));
+        assert_eq!(report.count(CheckKind::Object), (2, 0));
+        assert_eq!(report.count(CheckKind::Reference), (3, 1));
+        let failures: Vec<&ValidationCheck> = report.failures().collect();
+        assert!(matches!(failures[0].error, Some(Error::ChecksumMismatch)));
+        assert_eq!(failures[1].subject, blobs[1]);
+        assert!(matches!(failures[1].error, Some(Error::HmacMismatch)));
+
+        let report = backupset.validate_all("wrong", &resolver()).unwrap();
+        assert_eq!(report.count(CheckKind::EncryptionDat), (0, 1));
+        assert_eq!(report.count(CheckKind::Object), (0, 0));
+    }
+}
@@ -1,11 +1,18 @@
+use std::io::Write;
+
 use crate::date::Date;
-use crate::error::Result;
-use crate::type_utils::ArqRead;
+use crate::error::{Error, Result};
+use crate::folder::StorageType;
+use crate::type_utils::{ArqRead, ArqWrite};
+
+/// Storage type Arq writes in null BlobKeys.
+const NULL_KEY_STORAGE_TYPE: u32 = 1;
 
 /// BlobKey
 ///
 /// BlobKeys are used as an auxiliary data structure and there is *probably* no need to
 /// interact with this directly unless you're working within this library.
+#[derive(Clone, PartialEq, Debug)]
 pub struct BlobKey {
     pub sha1: String,
     pub is_encryption_key_stretched: bool, /* only present for Tree version 14 or later, Commit version 4 or later */
@@ -39,4 +46,121 @@ impl BlobKey {
             archive_upload_date,
         }))
     }
+
+    /// Checks that the archive fields agree with the storage type: Glacier keys point at
+    /// an archive, so they need an `archive_id`, while S3 keys mustn't have one.
+    ///
+    /// Returns `Error::ParseError` for inconsistent keys and unknown storage types.
+    pub fn validate(&self) -> Result<()> {
+        match (
+            StorageType::from(self.storage_type),
+            self.archive_id.is_empty(),
+        ) {
+            (StorageType::S3, true) | (StorageType::Glacier, false) => Ok(()),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    /// Writes `key` the way [BlobKey::new] reads it.
+    ///
+    /// `None` is written as a null key: an empty SHA1 followed by the remaining fields,
+    /// which [BlobKey::new] reads and discards, set the way Arq sets them (S3 storage type,
+    /// everything else empty or zero).
+    pub fn write<W: Write>(key: Option<&BlobKey>, writer: &mut W) -> Result<()> {
+        match key {
+            Some(key) => {
+                writer.write_arq_string(&key.sha1)?;
+                writer.write_arq_bool(key.is_encryption_key_stretched)?;
+                writer.write_arq_u32(key.storage_type)?;
+                writer.write_arq_string(&key.archive_id)?;
+                writer.write_arq_u64(key.archive_size)?;
+                writer.write_arq_date(&key.archive_upload_date)
+            }
+            None => {
+                writer.write_arq_string("")?;
+                writer.write_arq_bool(false)?;
+                writer.write_arq_u32(NULL_KEY_STORAGE_TYPE)?;
+                writer.write_arq_string("")?;
+                writer.write_arq_u64(0)?;
+                writer.write_arq_date(&Date {
+                    milliseconds_since_epoch: 0,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::push_blob_key;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_null_key_round_trip() {
+        let mut raw = Vec::new();
+        push_blob_key(&mut raw, None);
+        assert_eq!(BlobKey::new(Cursor::new(&raw)).unwrap(), None);
+
+        let mut written = Vec::new();
+        BlobKey::write(None, &mut written).unwrap();
+        assert_eq!(written, raw);
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        let key = BlobKey {
+            sha1: "c0571537d57d9488164303950dfded5cb6cfcd20".to_string(),
+            is_encryption_key_stretched: true,
+            storage_type: 2,
+            archive_id: "some-archive".to_string(),
+            archive_size: 1234,
+            archive_upload_date: Date {
+                milliseconds_since_epoch: 1_550_000_000_000,
+            },
+        };
+        let mut written = Vec::new();
+        BlobKey::write(Some(&key), &mut written).unwrap();
+        assert_eq!(BlobKey::new(Cursor::new(&written)).unwrap(), Some(key));
+    }
+
+    #[test]
+    fn test_validate() {
+        let s3 = BlobKey {
+            sha1: "c0571537d57d9488164303950dfded5cb6cfcd20".to_string(),
+            is_encryption_key_stretched: true,
+            storage_type: 1,
+            archive_id: String::new(),
+            archive_size: 0,
+            archive_upload_date: Date {
+                milliseconds_since_epoch: 0,
+            },
+        };
+        s3.validate().unwrap();
+
+        let glacier = BlobKey {
+            storage_type: 2,
+            archive_id: "some-archive".to_string(),
+            archive_size: 1234,
+            ..s3.clone()
+        };
+        glacier.validate().unwrap();
+
+        for inconsistent in [
+            BlobKey {
+                archive_id: "some-archive".to_string(),
+                ..s3.clone()
+            },
+            BlobKey {
+                archive_id: String::new(),
+                ..glacier.clone()
+            },
+            BlobKey {
+                storage_type: 3,
+                ..s3
+            },
+        ] {
+            assert!(matches!(inconsistent.validate(), Err(Error::ParseError)));
+        }
+    }
 }
@@ -0,0 +1,178 @@
+//! Bucket data
+//! -----------
+//!
+//! Besides its packs, every folder has a `bucketdata` directory holding its refs:
+//!
+//! ```ascii
+//! /<computer_uuid>/bucketdata/<folder_uuid>/
+//!     refs/heads/master           SHA1 of the most recent Commit, followed by a "Y"
+//!     refs/logs/master/<timestamp> one plist (see [FolderData]) per Commit created
+//! ```
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::error::{Error, Result};
+use crate::folder::FolderData;
+
+/// The `bucketdata` directory of a folder.
+pub struct BucketData {
+    root: PathBuf,
+}
+
+impl BucketData {
+    /// Bucket data for `folder_uuid`, given the directory of the computer it belongs to
+    /// (`/<computer_uuid>`).
+    pub fn new<P: AsRef<Path>>(computer_path: P, folder_uuid: &str) -> Self {
+        BucketData::open(computer_path.as_ref().join("bucketdata").join(folder_uuid))
+    }
+
+    /// Bucket data stored directly at `path` (`/<computer_uuid>/bucketdata/<folder_uuid>`).
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        BucketData {
+            root: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of the file holding the SHA1 of the most recent Commit.
+    pub fn master_ref_path(&self) -> PathBuf {
+        self.root.join("refs").join("heads").join("master")
+    }
+
+    /// Path of the directory holding one entry per Commit created.
+    pub fn reflog_path(&self) -> PathBuf {
+        self.root.join("refs").join("logs").join("master")
+    }
+
+    /// Reads the SHA1 of the most recent Commit, without the trailing "Y".
+    pub fn master_ref(&self) -> Result<String> {
+        Ok(parse_ref(&fs::read_to_string(self.master_ref_path())?))
+    }
+
+    /// Reads every reflog entry, oldest first.
+    ///
+    /// Entries are named after the time they were written, so they're ordered by name.
+    pub fn reflog(&self) -> Result<Vec<FolderData>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.reflog_path())? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name());
+            }
+        }
+        names.sort();
+
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            let content = fs::read(self.reflog_path().join(name))?;
+            entries.push(FolderData::new(Cursor::new(content), &[])?);
+        }
+        Ok(entries)
+    }
+}
+
+/// The SHA1 a ref file points at, without the trailing "Y" (and newline) Arq writes.
+pub(crate) fn parse_ref(content: &str) -> String {
+    let content = content.trim_end();
+    content.strip_suffix('Y').unwrap_or(content).to_string()
+}
+
+/// The name of the reflog entry for a Commit created at `when`: its milliseconds since the
+/// epoch.
+pub fn reflog_filename(when: DateTime<Utc>) -> String {
+    when.timestamp_millis().to_string()
+}
+
+/// The time a reflog entry named `name` was written, the inverse of [reflog_filename].
+pub fn parse_reflog_filename(name: &str) -> Result<DateTime<Utc>> {
+    if name.starts_with('+') {
+        return Err(Error::ParseError);
+    }
+    DateTime::from_timestamp_millis(name.parse()?).ok_or(Error::ParseError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD: &str = "c0571537d57d9488164303950dfded5cb6cfcd20";
+    const PREVIOUS: &str = "da8a00357643d481b5b46c9dc9c41277b35b9e85";
+
+    fn reflog_entry(old_head: &str, new_head: &str) -> String {
+        format!(
+            "<plist version=\"1.0\"><dict>\
+               <key>oldHeadSHA1</key><string>{old_head}</string>\
+               <key>newHeadSHA1</key><string>{new_head}</string>\
+             </dict></plist>"
+        )
+    }
+
+    fn bucketdata_fixture(computer: &Path) -> BucketData {
+        let bucketdata = BucketData::new(computer, "FOLDER-UUID");
+        fs::create_dir_all(bucketdata.master_ref_path().parent().unwrap()).unwrap();
+        fs::create_dir_all(bucketdata.reflog_path()).unwrap();
+
+        fs::write(bucketdata.master_ref_path(), format!("{HEAD}Y")).unwrap();
+        fs::write(
+            bucketdata.reflog_path().join("1550000001000"),
+            reflog_entry(PREVIOUS, HEAD),
+        )
+        .unwrap();
+        fs::write(
+            bucketdata.reflog_path().join("1550000000000"),
+            reflog_entry("", PREVIOUS),
+        )
+        .unwrap();
+        bucketdata
+    }
+
+    #[test]
+    fn test_paths() {
+        let bucketdata = BucketData::new("/COMPUTER-UUID", "FOLDER-UUID");
+        assert_eq!(
+            bucketdata.master_ref_path(),
+            PathBuf::from("/COMPUTER-UUID/bucketdata/FOLDER-UUID/refs/heads/master")
+        );
+        assert_eq!(
+            bucketdata.reflog_path(),
+            PathBuf::from("/COMPUTER-UUID/bucketdata/FOLDER-UUID/refs/logs/master")
+        );
+    }
+
+    #[test]
+    fn test_reflog_filename() {
+        let when = DateTime::from_timestamp_millis(1_550_000_001_000).unwrap();
+        assert_eq!(reflog_filename(when), "1550000001000");
+        assert_eq!(parse_reflog_filename("1550000001000").unwrap(), when);
+
+        let when = DateTime::from_timestamp_millis(1_712_345_678_901).unwrap();
+        assert_eq!(parse_reflog_filename(&reflog_filename(when)).unwrap(), when);
+
+        for name in ["", "master", "+1550000001000", "1550000001000.plist"] {
+            assert!(matches!(
+                parse_reflog_filename(name),
+                Err(Error::ParseError)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_master_ref_and_reflog() {
+        let computer = tempfile::tempdir().unwrap();
+        let bucketdata = bucketdata_fixture(computer.path());
+
+        assert_eq!(bucketdata.master_ref().unwrap(), HEAD);
+
+        let reflog = bucketdata.reflog().unwrap();
+        assert_eq!(reflog.len(), 2);
+        assert_eq!(reflog[0].new_head_sha1, PREVIOUS);
+        assert_eq!(reflog[1].old_head_sha1, PREVIOUS);
+        assert_eq!(reflog[1].new_head_sha1, bucketdata.master_ref().unwrap());
+    }
+}
@@ -1,4 +1,5 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::gzip;
 use crate::lz4;
 use crate::type_utils::ArqRead;
 
@@ -9,6 +10,33 @@ pub enum CompressionType {
     LZ4,
 }
 
+/// How data with the `Gzip` compression type is wrapped.
+///
+/// Arq calls it gzip, but depending on the version that wrote it the DEFLATE stream may
+/// have a gzip header, a zlib header or no header at all.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum GzipFormat {
+    Gzip,
+    Zlib,
+    Deflate,
+}
+
+impl GzipFormat {
+    /// Guesses the format from the first bytes of `compressed`: the gzip magic number, a
+    /// valid zlib header, or else raw DEFLATE.
+    pub fn detect(compressed: &[u8]) -> GzipFormat {
+        match compressed {
+            [0x1f, 0x8b, ..] => GzipFormat::Gzip,
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+            {
+                GzipFormat::Zlib
+            }
+            _ => GzipFormat::Deflate,
+        }
+    }
+}
+
 impl CompressionType {
     pub fn new<R: ArqRead>(mut reader: R) -> Result<CompressionType> {
         let c = reader.read_arq_i32()?;
@@ -17,15 +45,44 @@ impl CompressionType {
             0 => CompressionType::None,
             1 => CompressionType::Gzip,
             2 => CompressionType::LZ4,
-            _ => panic!("Compression type '{}' unknown", c),
+            _ => return Err(Error::ParseError),
         })
     }
 
+    /// Writes the compression type the way [CompressionType::new] reads it.
+    pub fn write<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        let c: i32 = match self {
+            CompressionType::None => 0,
+            CompressionType::Gzip => 1,
+            CompressionType::LZ4 => 2,
+        };
+        writer.write_all(&c.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Decompresses `compressed`. Gzip data can be in any [GzipFormat], which is detected.
     pub fn decompress(compressed: &[u8], compression_type: CompressionType) -> Result<Vec<u8>> {
         Ok(match compression_type {
             CompressionType::LZ4 => lz4::decompress(compressed)?,
-            CompressionType::Gzip => unimplemented!(),
+            CompressionType::Gzip => gzip::decompress(compressed, GzipFormat::detect(compressed))?,
             CompressionType::None => compressed.to_owned(),
         })
     }
+
+    /// Decompresses data whose compression type isn't known (or can't be trusted): data
+    /// starting with the gzip magic number is gunzipped, anything else is returned as is.
+    ///
+    /// Zlib and raw DEFLATE streams, and LZ4 data, can't be told apart from uncompressed
+    /// data reliably, so they aren't detected.
+    pub fn decompress_auto(data: &[u8]) -> Result<Vec<u8>> {
+        match GzipFormat::detect(data) {
+            GzipFormat::Gzip => gzip::decompress(data, GzipFormat::Gzip),
+            _ => Ok(data.to_owned()),
+        }
+    }
+
+    /// Decompresses gzip data known to be in `format`, for when detection guesses wrong.
+    pub fn decompress_gzip(compressed: &[u8], format: GzipFormat) -> Result<Vec<u8>> {
+        gzip::decompress(compressed, format)
+    }
 }
@@ -1,13 +1,17 @@
-use std::io::{BufRead, Seek};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::Path;
 
 use crate::error::Result;
+use crate::utils::is_uuid;
 use plist;
 
 /// Contains metadata information with user name and computer name.
 ///
 /// This is so that you can identify which backup set is which when you browse the backup
 /// set in your cloud storage account.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ComputerInfo {
     pub user_name: String,
@@ -15,10 +19,13 @@ pub struct ComputerInfo {
     /// uuid is optional (when deserializing) but we set it to whatever the user provides
     #[serde(skip)]
     pub uuid: String,
+    /// Any keys present in the plist that we don't explicitly model
+    #[serde(flatten)]
+    extra: BTreeMap<String, plist::Value>,
 }
 
 impl ComputerInfo {
-    /// Deserialize reader content (plist format) into a `ComputerInfo`.
+    /// Deserialize reader content (XML or binary plist) into a `ComputerInfo`.
     /// ## Examples
     ///
     /// Reading a computer info entry:
@@ -43,6 +50,41 @@ impl ComputerInfo {
         computer_info.uuid = uuid;
         Ok(computer_info)
     }
+
+    /// Deserialize JSON content (with the same keys as the plist) into a `ComputerInfo`.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json<T: std::io::Read>(reader: T, uuid: String) -> Result<ComputerInfo> {
+        let mut computer_info: ComputerInfo = serde_json::from_reader(reader)?;
+        computer_info.uuid = uuid;
+        Ok(computer_info)
+    }
+
+    /// Keys found in the computerinfo plist other than `userName` and `computerName`.
+    pub fn extra(&self) -> &BTreeMap<String, plist::Value> {
+        &self.extra
+    }
+}
+
+/// Lists the computers backed up to the destination at `root`.
+///
+/// Every directory named after a UUID and holding a `computerinfo` file is a computer;
+/// anything else in `root` is skipped. Computers are returned sorted by UUID.
+pub fn list_computers(root: &Path) -> Result<Vec<ComputerInfo>> {
+    let mut computers = Vec::new();
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let uuid = entry.file_name().to_string_lossy().into_owned();
+        let computerinfo = entry.path().join("computerinfo");
+        if !entry.file_type()?.is_dir() || !is_uuid(&uuid) || !computerinfo.is_file() {
+            continue;
+        }
+        computers.push(ComputerInfo::new(
+            BufReader::new(File::open(computerinfo)?),
+            uuid,
+        )?);
+    }
+    computers.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+    Ok(computers)
 }
 
 #[cfg(test)]
@@ -68,6 +110,29 @@ mod tests {
         assert_eq!(info.uuid, "someuuid");
     }
 
+    #[test]
+    fn test_extra_keys() {
+        let raw = "
+<plist> \
+  <dict> \
+    <key>userName</key> \
+    <string>SOMEUSER</string> \
+    <key>computerName</key> \
+    <string>SOMECOMPUTER</string> \
+    <key>someNewKey</key> \
+    <string>somevalue</string> \
+  </dict> \
+</plist> \
+";
+        let info = ComputerInfo::new(Cursor::new(raw.as_bytes()), "someuuid".to_string()).unwrap();
+        assert_eq!(info.user_name, "SOMEUSER");
+        assert_eq!(info.extra().len(), 1);
+        assert_eq!(
+            info.extra()["someNewKey"],
+            plist::Value::String("somevalue".to_string())
+        );
+    }
+
     #[test]
     #[should_panic]
     fn test_invalid_reader_content() {
@@ -83,9 +148,66 @@ mod tests {
         ComputerInfo::new(Cursor::new(raw.as_bytes()), "someuuid".to_string()).unwrap();
     }
 
+    #[test]
+    fn test_malformed_plist_error() {
+        let raw = "<plist><dict><key>userName</key><string>SOMEUSER</string></dict></plist>";
+        let err = ComputerInfo::new(Cursor::new(raw.as_bytes()), "someuuid".to_string())
+            .err()
+            .unwrap();
+        assert!(matches!(err, crate::error::Error::PlistError(_)));
+        assert!(format!("{err}").contains("computerName"), "{err}");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_from_json() {
+        let raw = r#"{"userName": "SOMEUSER", "computerName": "SOMECOMPUTER", "other": 1}"#;
+        let info = ComputerInfo::from_json(raw.as_bytes(), "someuuid".to_string()).unwrap();
+        assert_eq!(info.user_name, "SOMEUSER");
+        assert_eq!(info.computer_name, "SOMECOMPUTER");
+        assert_eq!(info.uuid, "someuuid");
+        assert!(info.extra().contains_key("other"));
+    }
+
     #[test]
     #[should_panic]
     fn test_empty_computer_info() {
         ComputerInfo::new(Cursor::new("".as_bytes()), "someuuid".to_string()).unwrap();
     }
+
+    #[test]
+    fn test_list_computers() {
+        let root = tempfile::tempdir().unwrap();
+        let plist = |name: &str| {
+            format!(
+                "<plist><dict><key>userName</key><string>someuser</string> \
+                 <key>computerName</key><string>{name}</string></dict></plist>"
+            )
+        };
+        for (dir, name) in [
+            ("F0000000-0000-0000-0000-000000000000", "second"),
+            ("A0000000-0000-0000-0000-000000000000", "first"),
+            ("not-a-computer", "ignored"),
+        ] {
+            std::fs::create_dir(root.path().join(dir)).unwrap();
+            std::fs::write(root.path().join(dir).join("computerinfo"), plist(name)).unwrap();
+        }
+        // A UUID directory without computer info isn't a computer either.
+        std::fs::create_dir(root.path().join("B0000000-0000-0000-0000-000000000000")).unwrap();
+        std::fs::write(root.path().join("C0000000-0000-0000-0000-000000000000"), "").unwrap();
+
+        let computers = list_computers(root.path()).unwrap();
+        let names: Vec<(&str, &str)> = computers
+            .iter()
+            .map(|computer| (computer.uuid.as_str(), computer.computer_name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("A0000000-0000-0000-0000-000000000000", "first"),
+                ("F0000000-0000-0000-0000-000000000000", "second"),
+            ]
+        );
+    }
 }
@@ -1,8 +1,11 @@
-use chrono::prelude::{DateTime, NaiveDateTime, Utc};
+use std::io::Write;
 
-use crate::error::Result;
+use chrono::prelude::{DateTime, Local, SecondsFormat, TimeZone, Utc};
+
+use crate::error::{Error, Result};
 use crate::type_utils::ArqRead;
 
+#[derive(Clone, PartialEq, Debug)]
 pub struct Date {
     pub milliseconds_since_epoch: u64,
 }
@@ -20,15 +23,214 @@ impl Date {
             milliseconds_since_epoch,
         })
     }
+
+    /// A date stored as (possibly fractional) seconds since the Unix epoch, as in plist
+    /// `<real>` timestamps like a folder's "VaultCreatedTime".
+    ///
+    /// The value is rounded to the nearest millisecond. Negative or non-finite values give
+    /// a zero (absent) date.
+    pub fn from_seconds_f64(secs: f64) -> Date {
+        let milliseconds = (secs * 1000.0).round();
+        let milliseconds_since_epoch = if milliseconds.is_finite() && milliseconds > 0.0 {
+            milliseconds as u64
+        } else {
+            0
+        };
+        Date {
+            milliseconds_since_epoch,
+        }
+    }
+
+    /// The date as a UTC `DateTime`, or `None` if it's past what chrono can represent
+    /// (which only corrupt data would be).
+    pub fn to_utc_datetime(&self) -> Option<DateTime<Utc>> {
+        i64::try_from(self.milliseconds_since_epoch)
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+    }
+
+    /// Like [Date::to_utc_datetime], but dates past what chrono can represent are clamped
+    /// to `DateTime::<Utc>::MAX_UTC`.
+    pub fn to_utc_datetime_saturating(&self) -> DateTime<Utc> {
+        self.to_utc_datetime().unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// The date as an RFC 3339 (ISO 8601) UTC string with millisecond precision, e.g.
+    /// `2019-02-12T19:33:20.500Z`.
+    ///
+    /// Returns `None` for a zero date, which is how Arq stores an absent one, and for dates
+    /// chrono can't represent.
+    pub fn to_rfc3339(&self) -> Option<String> {
+        if self.milliseconds_since_epoch == 0 {
+            return None;
+        }
+        self.to_utc_datetime()
+            .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+
+    /// Parses an RFC 3339 string, in any timezone, truncating it to the millisecond.
+    ///
+    /// Fails with `Error::ParseError` for malformed strings and dates before the epoch,
+    /// which can't be stored.
+    pub fn from_rfc3339(value: &str) -> Result<Date> {
+        let datetime = DateTime::parse_from_rfc3339(value).map_err(|_| Error::ParseError)?;
+        let milliseconds_since_epoch =
+            u64::try_from(datetime.timestamp_millis()).map_err(|_| Error::ParseError)?;
+        Ok(Date {
+            milliseconds_since_epoch,
+        })
+    }
+
+    /// The date in the given timezone (e.g. a `chrono::FixedOffset`), or `None` if chrono
+    /// can't represent it.
+    pub fn to_datetime<Tz: TimeZone>(&self, timezone: &Tz) -> Option<DateTime<Tz>> {
+        self.to_utc_datetime()
+            .map(|datetime| datetime.with_timezone(timezone))
+    }
+
+    /// The date in the system's timezone, for display, or `None` if chrono can't represent
+    /// it. `Display` always uses UTC.
+    pub fn to_local_datetime(&self) -> Option<DateTime<Local>> {
+        self.to_datetime(&Local)
+    }
+
+    /// Writes the date the way [Date::new] reads it. A zero date is written as absent.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.milliseconds_since_epoch == 0 {
+            writer.write_all(&[0x00])?;
+        } else {
+            writer.write_all(&[0x01])?;
+            writer.write_all(&self.milliseconds_since_epoch.to_be_bytes())?;
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for Date {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Date is in milliseconds elapsed since epoch
-        let naive_datetime =
-            NaiveDateTime::from_timestamp_opt((self.milliseconds_since_epoch / 1000) as i64, 0)
-                .unwrap();
-        let datetime_again: DateTime<Utc> = DateTime::from_utc(naive_datetime, Utc);
-        write!(f, "{}", datetime_again)
+        let seconds = i64::try_from(self.milliseconds_since_epoch / 1000).ok();
+        match seconds.and_then(|seconds| DateTime::<Utc>::from_timestamp(seconds, 0)) {
+            Some(datetime) => write!(f, "{}", datetime),
+            None => write!(
+                f,
+                "invalid date ({} ms since epoch)",
+                self.milliseconds_since_epoch
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn test_to_datetime() {
+        let date = Date {
+            milliseconds_since_epoch: 1_550_000_000_500,
+        };
+        assert_eq!(
+            date.to_utc_datetime().unwrap().to_rfc3339(),
+            "2019-02-12T19:33:20.500+00:00"
+        );
+
+        let plus_one = FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(
+            date.to_datetime(&plus_one).unwrap().to_rfc3339(),
+            "2019-02-12T20:33:20.500+01:00"
+        );
+        assert_eq!(
+            date.to_local_datetime().unwrap().with_timezone(&Utc),
+            date.to_utc_datetime().unwrap()
+        );
+        assert_eq!(format!("{}", date), "2019-02-12 19:33:20 UTC");
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let date = Date {
+            milliseconds_since_epoch: u64::MAX,
+        };
+        assert_eq!(date.to_utc_datetime(), None);
+        assert_eq!(
+            date.to_datetime(&FixedOffset::east_opt(3600).unwrap()),
+            None
+        );
+        assert_eq!(date.to_local_datetime(), None);
+        assert_eq!(date.to_utc_datetime_saturating(), DateTime::<Utc>::MAX_UTC);
+        assert_eq!(
+            format!("{}", date),
+            "invalid date (18446744073709551615 ms since epoch)"
+        );
+
+        // Fits in an i64, but not in chrono's range.
+        let date = Date {
+            milliseconds_since_epoch: i64::MAX as u64,
+        };
+        assert_eq!(date.to_utc_datetime(), None);
+        assert!(format!("{}", date).starts_with("invalid date"));
+
+        let date = Date {
+            milliseconds_since_epoch: 1_550_000_000_500,
+        };
+        assert_eq!(
+            date.to_utc_datetime(),
+            Some(date.to_utc_datetime_saturating())
+        );
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let date = Date {
+            milliseconds_since_epoch: 1_550_000_000_500,
+        };
+        assert_eq!(
+            date.to_rfc3339().as_deref(),
+            Some("2019-02-12T19:33:20.500Z")
+        );
+        assert_eq!(
+            Date::from_rfc3339("2019-02-12T19:33:20.500Z").unwrap(),
+            date
+        );
+        assert_eq!(
+            Date::from_rfc3339("2019-02-12T20:33:20.5+01:00").unwrap(),
+            date
+        );
+
+        let zero = Date {
+            milliseconds_since_epoch: 0,
+        };
+        assert_eq!(zero.to_rfc3339(), None);
+        let huge = Date {
+            milliseconds_since_epoch: u64::MAX,
+        };
+        assert_eq!(huge.to_rfc3339(), None);
+
+        assert!(matches!(
+            Date::from_rfc3339("1969-12-31T23:59:59Z"),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(
+            Date::from_rfc3339("2019-02-12"),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_from_seconds_f64() {
+        let date = Date::from_seconds_f64(1_550_000_000.250_5);
+        assert_eq!(date.milliseconds_since_epoch, 1_550_000_000_251);
+        assert_eq!(
+            date.to_utc_datetime(),
+            Some(
+                Utc.with_ymd_and_hms(2019, 2, 12, 19, 33, 20).unwrap()
+                    + chrono::Duration::milliseconds(251)
+            )
+        );
+
+        assert_eq!(Date::from_seconds_f64(-1.0).milliseconds_since_epoch, 0);
+        assert_eq!(Date::from_seconds_f64(f64::NAN).milliseconds_since_epoch, 0);
     }
 }
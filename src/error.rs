@@ -2,17 +2,44 @@ use std::num::TryFromIntError;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Kept by hand: rustfmt would put every struct variant on several lines as soon as one
+// variant has an attribute.
+#[rustfmt::skip]
 #[derive(Debug)]
 pub enum Error {
     WrongPassword,
+    HmacMismatch,
+    /// A pack or pack index doesn't match its trailing SHA1.
+    ChecksumMismatch,
     CryptoError,
+    InvalidKeyOrIvLength,
     CipherError,
     BlockModeError,
     ParseError,
+    PlistError(plist::Error),
     ConversionError(std::str::Utf8Error),
     IoError(std::io::Error),
     DecompressionError(lz4_flex::block::DecompressError),
     DecompressionDataLengthOutOfBounds,
+    NegativeDecompressedLength(i32),
+    ObjectNotFound(String),
+    ParseAt { offset: u64, kind: Box<Error> },
+    TruncatedPack { expected: u64, available: usize },
+    TruncatedTree { expected: u32, got: u32 },
+    PathNotFound(std::path::PathBuf),
+    NotADirectory(std::path::PathBuf),
+    IsADirectory(std::path::PathBuf),
+    /// A tree node whose name isn't a plain file name, e.g. `..` or `a/b`.
+    InvalidNodeName(String),
+    SizeMismatch { expected: u64, actual: u64 },
+    UnexpectedEof { while_reading: &'static str },
+    UnsupportedVersion { while_reading: &'static str, version: u32 },
+    #[cfg(feature = "serde_json")]
+    JsonError(serde_json::Error),
+    #[cfg(feature = "reqwest")]
+    HttpError(reqwest::Error),
+    #[cfg(feature = "rusqlite")]
+    SqliteError(rusqlite::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -20,6 +47,27 @@ impl std::fmt::Display for Error {
         match *self {
             Error::ConversionError(ref err) => write!(f, "{err}"),
             Error::DecompressionError(ref err) => write!(f, "{err}"),
+            Error::PlistError(ref err) => write!(f, "{err}"),
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(ref err) => write!(f, "{err}"),
+            #[cfg(feature = "reqwest")]
+            Error::HttpError(ref err) => write!(f, "{err}"),
+            #[cfg(feature = "rusqlite")]
+            Error::SqliteError(ref err) => write!(f, "{err}"),
+            Error::ParseAt { offset, ref kind } => write!(f, "{kind} at offset {offset}"),
+            Error::NegativeDecompressedLength(length) => {
+                write!(f, "lz4 declared length is negative: {length}")
+            }
+            Error::TruncatedTree { expected, got } => {
+                write!(f, "tree declares {expected} nodes but ends after {got}")
+            }
+            Error::UnexpectedEof { while_reading } => {
+                write!(f, "unexpected end of input while reading {while_reading}")
+            }
+            Error::UnsupportedVersion {
+                while_reading,
+                version,
+            } => write!(f, "unsupported {while_reading} version {version}"),
             _ => write!(f, "{:#?}", self),
         }
     }
@@ -35,14 +83,25 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             Error::ConversionError(ref err) => Some(err),
+            Error::PlistError(ref err) => Some(err),
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(ref err) => Some(err),
+            #[cfg(feature = "reqwest")]
+            Error::HttpError(ref err) => Some(err),
+            #[cfg(feature = "rusqlite")]
+            Error::SqliteError(ref err) => Some(err),
+            Error::ParseAt { ref kind, .. } => Some(kind.as_ref()),
             _ => None,
         }
     }
 }
 
+// `digest::InvalidLength` is the same type as `aes::cipher::InvalidLength`. HMAC accepts
+// keys of any length, so in practice this only comes from initialising AES-CBC with a key
+// or IV of the wrong size.
 impl std::convert::From<digest::InvalidLength> for Error {
     fn from(_error: digest::InvalidLength) -> Error {
-        Error::CryptoError
+        Error::InvalidKeyOrIvLength
     }
 }
 
@@ -53,8 +112,29 @@ impl std::convert::From<aes::cipher::block_padding::UnpadError> for Error {
 }
 
 impl std::convert::From<plist::Error> for Error {
-    fn from(_error: plist::Error) -> Error {
-        Error::ParseError
+    fn from(error: plist::Error) -> Error {
+        Error::PlistError(error)
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl std::convert::From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Error {
+        Error::JsonError(error)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl std::convert::From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Error {
+        Error::HttpError(error)
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl std::convert::From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Error {
+        Error::SqliteError(error)
     }
 }
 
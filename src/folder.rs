@@ -1,12 +1,18 @@
 use std;
-use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::io::{BufRead, Cursor, Seek};
+use std::path::{Path, PathBuf};
 
 use plist;
 
-use crate::error::Result;
+use crate::bucketdata::BucketData;
+use crate::date::Date;
+use crate::error::{Error, Result};
 use crate::object_encryption;
+use crate::resolver::BlobResolver;
+use crate::tree::{Commit, CommitStatus, Tree};
 use crate::type_utils::ArqRead;
+use crate::utils::relative_path;
 
 /// FolderData contains metadata information written every time a new Commit is created.
 ///
@@ -29,23 +35,112 @@ pub struct FolderData {
 }
 
 impl FolderData {
+    /// Deserialize reader content (XML or binary plist) into a `FolderData`.
+    ///
+    /// `sha1sum` is the content of the master ref the entry is checked against. Arq
+    /// appends a "Y" to it, but an already trimmed SHA1 is accepted too. If it's not empty
+    /// and doesn't match `new_head_sha1`, `Error::ParseError` is returned.
     pub fn new<R: BufRead + Seek>(reader: R, sha1sum: &[u8]) -> Result<Self> {
         let fd: FolderData = plist::from_reader(reader)?;
 
-        if sha1sum.len() > 40 {
-            // 89 is "Y"
-            assert_eq!(sha1sum[sha1sum.len() - 1], 89);
-            assert_eq!(
-                // subtracting 1 due to the Y appended to the sha.
-                std::str::from_utf8(&sha1sum[..sha1sum.len() - 1])?,
-                fd.new_head_sha1
-            );
+        // 89 is "Y"
+        let sha1sum = match sha1sum.split_last() {
+            Some((89, sha1)) if sha1.len() == 40 => sha1,
+            _ => sha1sum,
+        };
+        if !sha1sum.is_empty() && std::str::from_utf8(sha1sum)? != fd.new_head_sha1 {
+            return Err(Error::ParseError);
         }
 
         Ok(fd)
     }
 }
 
+/// The first problem [verify_chain] ran into.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ChainBreak {
+    /// The commit couldn't be found.
+    MissingCommit(String),
+    /// The commit's content doesn't hash to the SHA1 it's referenced by.
+    Sha1Mismatch { expected: String, actual: String },
+    /// The commit was found but couldn't be parsed.
+    InvalidCommit(String),
+    /// The commit's tree couldn't be found.
+    MissingTree { commit: String, tree: String },
+    /// The commit's tree was found but couldn't be parsed.
+    InvalidTree { commit: String, tree: String },
+}
+
+/// Result of [verify_chain].
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChainReport {
+    /// The commits that were verified successfully, newest first.
+    pub commits: Vec<String>,
+    /// Those of `commits` that aren't complete snapshots and shouldn't be restored from
+    /// without care, newest first.
+    pub partial: Vec<(String, CommitStatus)>,
+    pub first_break: Option<ChainBreak>,
+}
+
+impl ChainReport {
+    pub fn is_healthy(&self) -> bool {
+        self.first_break.is_none()
+    }
+}
+
+/// The rules deciding which files in a folder are left out of backups.
+///
+/// With `match_any` set, a file is excluded if any of the conditions matches it, otherwise
+/// all of them have to. Most folders have no conditions at all.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+#[serde(default)]
+pub struct Excludes {
+    pub enabled: bool,
+    pub match_any: bool,
+    pub conditions: Vec<ExcludeCondition>,
+}
+
+/// A single exclude rule, e.g. a file name (`rule_type`) that ends with (`comparison`)
+/// ".tmp" (`value`).
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+#[serde(default)]
+pub struct ExcludeCondition {
+    #[serde(rename = "Type")]
+    pub rule_type: String,
+    pub comparison: String,
+    pub value: String,
+}
+
+/// Where a folder's (or a blob's) data is stored, as found in `storage_type` fields.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StorageType {
+    S3,
+    Glacier,
+    Unknown(u32),
+}
+
+impl From<u32> for StorageType {
+    fn from(storage_type: u32) -> Self {
+        match storage_type {
+            1 => StorageType::S3,
+            2 => StorageType::Glacier,
+            storage_type => StorageType::Unknown(storage_type),
+        }
+    }
+}
+
+impl StorageType {
+    pub fn number(self) -> u32 {
+        match self {
+            StorageType::S3 => 1,
+            StorageType::Glacier => 2,
+            StorageType::Unknown(storage_type) => storage_type,
+        }
+    }
+}
+
 /// Folder
 ///
 ///
@@ -72,7 +167,7 @@ impl FolderData {
 ///                 <key>LocalPath</key>
 ///                 <string>/Users/stefan/src/company</string>
 ///                 <key>LocalMountPoint</key>
-///                 </string>/</string>
+///                 <string>/</string>
 ///                 <key>StorageType</key>
 ///                 <integer>1</integer>
 ///                 <key>VaultName</key>
@@ -92,13 +187,17 @@ impl FolderData {
 ///         </plist>
 /// ```
 ///
-/// Only Glacier-backed folders have "VaultName" and "VaultCreatedTime" keys.
+/// Only Glacier-backed folders have "VaultName" and "VaultCreatedTime" keys, and some
+/// folders have no "LocalMountPoint".
+///
+/// Folder files that were decrypted by hand (or written by very old versions of Arq) are
+/// just the plist, without the "encrypted" header; [Folder::new] reads those as is.
 ///
 /// NOTE: The folder's UUID and name are called "BucketUUID" and "BucketName" in the
 /// plist; this is a holdover from previous iterations of Arq and is not to be confused
 /// with S3's "bucket" concept.
 ///
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Folder {
     pub bucket_name: String,
@@ -108,13 +207,20 @@ pub struct Folder {
     pub computer_uuid: String,
     pub endpoint: String,
     pub exclude_items_with_time_machine_exclude_metadata_flag: bool,
-    pub excludes: BTreeMap<String, Vec<String>>, // TODO(nlopes): this one is provably wrong
+    pub excludes: Excludes,
     pub ignored_relative_paths: Vec<String>,
-    pub local_mount_point: String,
+    /// `None` when the plist doesn't have it, see [Folder::absolute_path].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_mount_point: Option<String>,
     pub local_path: String,
     pub skip_during_backup: bool,
     pub skip_if_not_mounted: bool,
     pub storage_type: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_name: Option<String>,
+    /// Seconds since the Unix epoch, see [Folder::vault_created_date].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_created_time: Option<f64>,
 }
 
 impl Folder {
@@ -122,12 +228,669 @@ impl Folder {
         Ok(plist::from_reader(Cursor::new(content))?)
     }
 
+    /// Reads a folder file, decrypting it with `master_keys`.
+    ///
+    /// A file without the "encrypted" header is read as a plain plist, which doesn't need
+    /// any keys (see [Folder::from_plist_reader]).
     pub fn new<R: BufRead + Seek>(mut reader: R, master_keys: &[Vec<u8>]) -> Result<Self> {
-        let header = reader.read_bytes(9)?;
-        assert_eq!(header, [101, 110, 99, 114, 121, 112, 116, 101, 100]); // 'encrypted'
+        let start = reader.stream_position()?;
+        let header = reader.read_bytes(9).ok();
+        if header.as_deref() != Some(b"encrypted") {
+            reader.seek(std::io::SeekFrom::Start(start))?;
+            return Folder::from_plist_reader(reader);
+        }
+        if master_keys.len() < 2 {
+            return Err(Error::InvalidKeyOrIvLength);
+        }
 
         let obj = object_encryption::EncryptedObject::new(&mut reader)?;
         obj.validate(&master_keys[1])?;
         Folder::from_content(&obj.decrypt(&master_keys[0])?)
     }
+
+    /// Reads an unencrypted folder plist (XML or binary), e.g. one decrypted by hand.
+    pub fn from_plist_reader<R: std::io::Read + Seek>(reader: R) -> Result<Self> {
+        Ok(plist::from_reader(reader)?)
+    }
+
+    /// Deserialize already decrypted JSON content (with the same keys as the plist) into
+    /// a `Folder`.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json<R: std::io::Read>(reader: R) -> Result<Self> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// `storage_type` as a [StorageType].
+    pub fn storage_type_enum(&self) -> StorageType {
+        StorageType::from(u32::from(self.storage_type))
+    }
+
+    /// Whether the folder is backed up to Glacier, whose objects have to be retrieved
+    /// before they can be read. Either the storage type or a vault name says so.
+    pub fn is_glacier(&self) -> bool {
+        self.storage_type_enum() == StorageType::Glacier || self.vault_name.is_some()
+    }
+
+    /// When the folder's Glacier vault was created, if it has one.
+    pub fn vault_created_date(&self) -> Option<Date> {
+        self.vault_created_time.map(Date::from_seconds_f64)
+    }
+
+    /// `IgnoredRelativePaths` as paths relative to the folder's root tree, for use with
+    /// [crate::tree::Tree::walk_ignoring] and [crate::snapshot::Snapshot::with_ignored_paths].
+    pub fn ignored_paths(&self) -> Vec<PathBuf> {
+        self.ignored_relative_paths
+            .iter()
+            .map(|path| relative_path(Path::new(path)))
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect()
+    }
+
+    /// Where a path relative to this folder's root tree lived on the backed up computer.
+    ///
+    /// `LocalPath` is normally absolute already (it includes the volume's mount point), in
+    /// which case the relative path is simply appended to it. Otherwise it's taken to be
+    /// relative to `LocalMountPoint`, or to `/` for folders without one.
+    pub fn absolute_path(&self, relative: &Path) -> PathBuf {
+        let relative = relative_path(relative);
+
+        let local_path = Path::new(&self.local_path);
+        if local_path.is_absolute() {
+            local_path.join(relative)
+        } else {
+            Path::new(self.local_mount_point.as_deref().unwrap_or("/"))
+                .join(local_path)
+                .join(relative)
+        }
+    }
+
+    /// The root tree of the folder's most recent backup.
+    ///
+    /// Follows the master ref in the folder's bucket data under `computer_path` (the
+    /// `/<computer_uuid>` directory) to its commit, then resolves and parses the commit's
+    /// tree. `resolver` takes care of decrypting both objects.
+    pub fn latest_tree<P: AsRef<Path>, B: BlobResolver>(
+        &self,
+        computer_path: P,
+        resolver: &B,
+    ) -> Result<Tree> {
+        latest_tree(&BucketData::new(computer_path, &self.bucket_uuid), resolver)
+    }
+}
+
+/// Checks the integrity of a folder's history.
+///
+/// Starting from the master ref in `bucketdata`, every commit is resolved, its content
+/// checked against the SHA1 it's referenced by (salted with `master_keys[2]`) and its
+/// tree resolved and parsed, then its parents are followed. The walk stops at the first
+/// problem found, which is reported in [ChainReport::first_break]. Commits that are
+/// incomplete or have missing nodes don't break the chain, but are listed in
+/// [ChainReport::partial].
+///
+/// Errors other than missing or unparsable objects (e.g. I/O errors) are returned as is.
+/// Fails with `Error::InvalidKeyOrIvLength` if `master_keys` has no salt key, as is the
+/// case for encryptionv2.dat files.
+pub fn verify_chain<B: BlobResolver>(
+    bucketdata: &BucketData,
+    resolver: &B,
+    master_keys: &[Vec<u8>],
+) -> Result<ChainReport> {
+    let salt_key = master_keys.get(2).ok_or(Error::InvalidKeyOrIvLength)?;
+    let mut report = ChainReport {
+        commits: Vec::new(),
+        partial: Vec::new(),
+        first_break: None,
+    };
+    let mut seen = HashSet::new();
+    let mut pending = vec![bucketdata.master_ref()?];
+
+    while let Some(sha1) = pending.pop() {
+        if !seen.insert(sha1.clone()) {
+            continue;
+        }
+        match verify_commit(&sha1, resolver, salt_key)? {
+            Ok(commit) => {
+                if commit.status() != CommitStatus::Complete {
+                    report.partial.push((sha1.clone(), commit.status()));
+                }
+                let mut parents: Vec<String> = commit.parent_commits.into_keys().collect();
+                parents.sort();
+                pending.extend(parents);
+                report.commits.push(sha1);
+            }
+            Err(chain_break) => {
+                report.first_break = Some(chain_break);
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The root tree of the most recent backup recorded in `bucketdata`, see
+/// [Folder::latest_tree].
+pub(crate) fn latest_tree<B: BlobResolver>(bucketdata: &BucketData, resolver: &B) -> Result<Tree> {
+    let commit = Commit::new(Cursor::new(resolver.resolve(&bucketdata.master_ref()?)?))?;
+    Tree::new(
+        &resolver.resolve(&commit.tree_sha1)?,
+        commit.tree_compression_type,
+    )
+}
+
+/// Verifies a single commit and its tree, returning the commit if it's sound.
+///
+/// Problems with the commit itself are returned as the inner `Err`, other errors (e.g.
+/// I/O errors) as the outer one.
+fn verify_commit<B: BlobResolver>(
+    sha1: &str,
+    resolver: &B,
+    salt_key: &[u8],
+) -> Result<std::result::Result<Commit, ChainBreak>> {
+    let content = match resolver.resolve(sha1) {
+        Ok(content) => content,
+        Err(Error::ObjectNotFound(_)) => return Ok(Err(ChainBreak::MissingCommit(sha1.into()))),
+        Err(err) => return Err(err),
+    };
+
+    let actual = object_encryption::content_address(&content, salt_key);
+    if actual != sha1 {
+        return Ok(Err(ChainBreak::Sha1Mismatch {
+            expected: sha1.into(),
+            actual,
+        }));
+    }
+
+    let commit = match Commit::new(Cursor::new(content)) {
+        Ok(commit) => commit,
+        Err(_) => return Ok(Err(ChainBreak::InvalidCommit(sha1.into()))),
+    };
+
+    let tree = commit.tree_sha1.clone();
+    match resolver.resolve(&tree) {
+        Ok(content) if Tree::new(&content, commit.tree_compression_type.clone()).is_ok() => {
+            Ok(Ok(commit))
+        }
+        Ok(_) => Ok(Err(ChainBreak::InvalidTree {
+            commit: sha1.into(),
+            tree,
+        })),
+        Err(Error::ObjectNotFound(_)) => Ok(Err(ChainBreak::MissingTree {
+            commit: sha1.into(),
+            tree,
+        })),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionType;
+    use crate::object_encryption::content_address;
+    use crate::test_utils::{
+        commit_bytes, commit_bytes_with_flags, encrypt, fixture_objects, master_keys,
+    };
+    use chrono::{TimeZone, Utc};
+    use std::collections::HashMap;
+
+    const HEAD: &str = "c0571537d57d9488164303950dfded5cb6cfcd20";
+
+    fn folder_data_plist() -> String {
+        format!(
+            "<plist version=\"1.0\"> \
+  <dict> \
+    <key>oldHeadSHA1</key> \
+    <string>da8a00357643d481b5b46c9dc9c41277b35b9e85</string> \
+    <key>newHeadSHA1</key> \
+    <string>{HEAD}</string> \
+    <key>isRewrite</key> \
+    <false/> \
+  </dict> \
+</plist>"
+        )
+    }
+
+    fn make_folder(local_path: &str, local_mount_point: &str) -> Folder {
+        Folder {
+            bucket_name: "company".to_string(),
+            bucket_uuid: "408E376B-ECF7-4688-902A-1E7671BC5B9A".to_string(),
+            computer_uuid: "600150F6-70BB-47C6-A538-6F3A2258D524".to_string(),
+            endpoint: String::new(),
+            exclude_items_with_time_machine_exclude_metadata_flag: false,
+            excludes: Excludes::default(),
+            ignored_relative_paths: Vec::new(),
+            local_mount_point: Some(local_mount_point.to_string()),
+            local_path: local_path.to_string(),
+            skip_during_backup: false,
+            skip_if_not_mounted: false,
+            storage_type: 1,
+            vault_name: None,
+            vault_created_time: None,
+        }
+    }
+
+    #[test]
+    fn test_fixture_folder() {
+        let dat = std::fs::read("fixtures/AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D/encryptionv3.dat")
+            .unwrap();
+        let keys = object_encryption::EncryptionDat::new(Cursor::new(dat), "evu")
+            .unwrap()
+            .into_master_keys();
+        let file = std::fs::read(
+            "fixtures/AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D/buckets/7C19E8AF-FFE9-4952-B1E1-8D5181012BB1",
+        )
+        .unwrap();
+        let folder = Folder::new(Cursor::new(file), &keys).unwrap();
+        assert_eq!(folder.bucket_name, "arq 5");
+        assert_eq!(folder.bucket_uuid, "7C19E8AF-FFE9-4952-B1E1-8D5181012BB1");
+        assert_eq!(folder.computer_uuid, "AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D");
+        assert_eq!(
+            folder.local_path,
+            "/Users/nlopes/Repos/Personal/rust/evu/fixtures/arq 5"
+        );
+        assert_eq!(folder.local_mount_point.as_deref(), Some("/"));
+        assert_eq!(folder.storage_type, 1);
+    }
+
+    #[test]
+    fn test_excludes() {
+        let raw = "<plist version=\"1.0\"><dict> \
+            <key>Enabled</key><true/> \
+            <key>MatchAny</key><true/> \
+            <key>Conditions</key><array> \
+              <dict> \
+                <key>Type</key><string>FileName</string> \
+                <key>Comparison</key><string>EndsWith</string> \
+                <key>Value</key><string>.tmp</string> \
+              </dict> \
+              <dict> \
+                <key>Type</key><string>Path</string> \
+                <key>Comparison</key><string>Contains</string> \
+                <key>Value</key><string>node_modules</string> \
+              </dict> \
+            </array> \
+          </dict></plist>";
+        let excludes: Excludes = plist::from_reader(Cursor::new(raw)).unwrap();
+        assert!(excludes.enabled);
+        assert!(excludes.match_any);
+        assert_eq!(
+            excludes.conditions,
+            vec![
+                ExcludeCondition {
+                    rule_type: "FileName".to_string(),
+                    comparison: "EndsWith".to_string(),
+                    value: ".tmp".to_string(),
+                },
+                ExcludeCondition {
+                    rule_type: "Path".to_string(),
+                    comparison: "Contains".to_string(),
+                    value: "node_modules".to_string(),
+                },
+            ]
+        );
+
+        let raw = "<plist version=\"1.0\"><dict> \
+            <key>Enabled</key><false/> \
+            <key>MatchAny</key><true/> \
+            <key>Conditions</key><array></array> \
+          </dict></plist>";
+        let excludes: Excludes = plist::from_reader(Cursor::new(raw)).unwrap();
+        assert!(!excludes.enabled);
+        assert!(excludes.conditions.is_empty());
+    }
+
+    #[test]
+    fn test_vault_created_time() {
+        let raw = "<plist version=\"1.0\"><dict> \
+            <key>BucketName</key><string>company</string> \
+            <key>BucketUUID</key><string>408E376B-ECF7-4688-902A-1E7671BC5B9A</string> \
+            <key>ComputerUUID</key><string>600150F6-70BB-47C6-A538-6F3A2258D524</string> \
+            <key>Endpoint</key><string></string> \
+            <key>ExcludeItemsWithTimeMachineExcludeMetadataFlag</key><false/> \
+            <key>Excludes</key><dict></dict> \
+            <key>IgnoredRelativePaths</key><array></array> \
+            <key>LocalMountPoint</key><string>/</string> \
+            <key>LocalPath</key><string>/Users/stefan/src/company</string> \
+            <key>SkipDuringBackup</key><false/> \
+            <key>SkipIfNotMounted</key><false/> \
+            <key>StorageType</key><integer>1</integer> \
+            <key>VaultName</key><string>arq_408E376B-ECF7-4688-902A-1E7671BC5B9A</string> \
+            <key>VaultCreatedTime</key><real>12345678.5</real> \
+          </dict></plist>";
+        let folder = Folder::from_content(raw.as_bytes()).unwrap();
+        assert_eq!(
+            folder.vault_name.as_deref(),
+            Some("arq_408E376B-ECF7-4688-902A-1E7671BC5B9A")
+        );
+        assert_eq!(
+            folder.vault_created_date().unwrap().to_utc_datetime(),
+            Some(
+                Utc.with_ymd_and_hms(1970, 5, 23, 21, 21, 18).unwrap()
+                    + chrono::Duration::milliseconds(500)
+            )
+        );
+
+        assert!(make_folder("/", "/").vault_created_date().is_none());
+    }
+
+    #[test]
+    fn test_without_local_mount_point() {
+        let raw = "<plist version=\"1.0\"><dict> \
+            <key>BucketName</key><string>company</string> \
+            <key>BucketUUID</key><string>408E376B-ECF7-4688-902A-1E7671BC5B9A</string> \
+            <key>ComputerUUID</key><string>600150F6-70BB-47C6-A538-6F3A2258D524</string> \
+            <key>Endpoint</key><string></string> \
+            <key>ExcludeItemsWithTimeMachineExcludeMetadataFlag</key><false/> \
+            <key>Excludes</key><dict></dict> \
+            <key>IgnoredRelativePaths</key><array></array> \
+            <key>LocalPath</key><string>src/company</string> \
+            <key>SkipDuringBackup</key><false/> \
+            <key>SkipIfNotMounted</key><false/> \
+            <key>StorageType</key><integer>1</integer> \
+          </dict></plist>";
+        let folder = Folder::from_content(raw.as_bytes()).unwrap();
+        assert_eq!(folder.local_mount_point, None);
+        assert_eq!(
+            folder.absolute_path(Path::new("lib/main.rs")),
+            PathBuf::from("/src/company/lib/main.rs")
+        );
+    }
+
+    #[test]
+    fn test_ignored_paths() {
+        let mut folder = make_folder("/Users/stefan/src/company", "/");
+        folder.ignored_relative_paths = vec![
+            "/target".to_string(),
+            "docs/build".to_string(),
+            "/".to_string(),
+        ];
+        assert_eq!(
+            folder.ignored_paths(),
+            vec![PathBuf::from("target"), PathBuf::from("docs/build")]
+        );
+    }
+
+    #[test]
+    fn test_absolute_path() {
+        let folder = make_folder("/Users/stefan/src/company", "/");
+        assert_eq!(
+            folder.absolute_path(Path::new("lib/src/main.rs")),
+            PathBuf::from("/Users/stefan/src/company/lib/src/main.rs")
+        );
+        assert_eq!(
+            folder.absolute_path(Path::new("/README.md")),
+            PathBuf::from("/Users/stefan/src/company/README.md")
+        );
+        assert_eq!(
+            folder.absolute_path(Path::new("")),
+            PathBuf::from("/Users/stefan/src/company")
+        );
+    }
+
+    #[test]
+    fn test_storage_type_enum() {
+        let mut folder = make_folder("/Users/stefan/src/company", "/");
+        for (storage_type, expected) in [
+            (1, StorageType::S3),
+            (2, StorageType::Glacier),
+            (7, StorageType::Unknown(7)),
+        ] {
+            folder.storage_type = storage_type;
+            assert_eq!(folder.storage_type_enum(), expected);
+            assert_eq!(expected.number(), u32::from(storage_type));
+        }
+    }
+
+    #[test]
+    fn test_is_glacier() {
+        let mut folder = make_folder("/Users/stefan/src/company", "/");
+        assert!(!folder.is_glacier());
+
+        folder.storage_type = 2;
+        assert!(folder.is_glacier());
+
+        folder.storage_type = 1;
+        folder.vault_name = Some("arq_vault".to_string());
+        assert!(folder.is_glacier());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_folder_json_round_trip() {
+        let folder = make_folder("/Users/stefan/src/company", "/");
+        let json = serde_json::to_string(&folder).unwrap();
+        assert!(json.contains("\"BucketUUID\":\"408E376B-ECF7-4688-902A-1E7671BC5B9A\""));
+
+        let parsed = Folder::from_json(Cursor::new(json.as_bytes())).unwrap();
+        assert_eq!(parsed.bucket_uuid, folder.bucket_uuid);
+        assert_eq!(parsed.local_path, folder.local_path);
+        assert_eq!(parsed.storage_type, 1);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+
+        assert!(matches!(
+            Folder::from_json(Cursor::new("{\"BucketName\": \"company\"}")),
+            Err(Error::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_absolute_path_relative_to_mount_point() {
+        let folder = make_folder("photos", "/Volumes/External");
+        assert_eq!(
+            folder.absolute_path(Path::new("2019/img.jpg")),
+            PathBuf::from("/Volumes/External/photos/2019/img.jpg")
+        );
+
+        let folder = make_folder("photos", "/");
+        assert_eq!(
+            folder.absolute_path(Path::new("2019/img.jpg")),
+            PathBuf::from("/photos/2019/img.jpg")
+        );
+    }
+
+    #[test]
+    fn test_folder_data_with_trailing_y() {
+        let sha1sum = format!("{HEAD}Y");
+        let fd = FolderData::new(Cursor::new(folder_data_plist()), sha1sum.as_bytes()).unwrap();
+        assert_eq!(fd.new_head_sha1, HEAD);
+        assert!(!fd.is_rewrite);
+    }
+
+    #[test]
+    fn test_folder_data_without_trailing_y() {
+        let fd = FolderData::new(Cursor::new(folder_data_plist()), HEAD.as_bytes()).unwrap();
+        assert_eq!(fd.new_head_sha1, HEAD);
+
+        let fd = FolderData::new(Cursor::new(folder_data_plist()), &[]).unwrap();
+        assert_eq!(fd.new_head_sha1, HEAD);
+    }
+
+    #[test]
+    fn test_binary_plists() {
+        let keys = master_keys();
+        let folder = make_folder("/Users/stefan/src/company", "/");
+        let mut binary = Vec::new();
+        plist::to_writer_binary(&mut binary, &folder).unwrap();
+        assert!(binary.starts_with(b"bplist00"));
+
+        let mut raw = b"encrypted".to_vec();
+        raw.extend(encrypt(&binary, &keys));
+        let parsed = Folder::new(Cursor::new(raw), &keys).unwrap();
+        assert_eq!(parsed.bucket_uuid, folder.bucket_uuid);
+        assert_eq!(parsed.local_path, folder.local_path);
+
+        let value: plist::Value = plist::from_reader(Cursor::new(folder_data_plist())).unwrap();
+        let mut binary = Vec::new();
+        value.to_writer_binary(&mut binary).unwrap();
+        let fd = FolderData::new(Cursor::new(binary), HEAD.as_bytes()).unwrap();
+        assert_eq!(fd.new_head_sha1, HEAD);
+    }
+
+    #[test]
+    fn test_plain_folder_plist() {
+        let folder = make_folder("/Users/stefan/src/company", "/");
+        let mut xml = Vec::new();
+        plist::to_writer_xml(&mut xml, &folder).unwrap();
+
+        let parsed = Folder::from_plist_reader(Cursor::new(&xml)).unwrap();
+        assert_eq!(parsed.bucket_uuid, folder.bucket_uuid);
+        assert_eq!(parsed.local_path, folder.local_path);
+
+        // Folder::new notices the missing header, and needs no keys for it.
+        let parsed = Folder::new(Cursor::new(&xml), &[]).unwrap();
+        assert_eq!(parsed.computer_uuid, folder.computer_uuid);
+        assert_eq!(parsed.storage_type_enum(), StorageType::S3);
+
+        assert!(matches!(
+            Folder::new(Cursor::new(b"encrypted".to_vec()), &[]),
+            Err(Error::InvalidKeyOrIvLength)
+        ));
+        assert!(matches!(
+            Folder::from_plist_reader(Cursor::new(b"not a plist")),
+            Err(Error::PlistError(_))
+        ));
+    }
+
+    #[test]
+    fn test_folder_data_mismatching_sha1() {
+        let sha1sum = b"0000000000000000000000000000000000000000Y";
+        assert!(matches!(
+            FolderData::new(Cursor::new(folder_data_plist()), sha1sum),
+            Err(Error::ParseError)
+        ));
+    }
+
+    /// Stores a commit of the fixture tree with the given parent, returning its SHA1.
+    fn add_commit(
+        objects: &mut HashMap<String, Vec<u8>>,
+        keys: &[Vec<u8>],
+        parent: Option<&str>,
+    ) -> String {
+        let commit = commit_bytes("roottree", parent);
+        let sha1 = content_address(&commit, &keys[2]);
+        objects.insert(sha1.clone(), commit);
+        sha1
+    }
+
+    fn bucketdata_with_head(dir: &Path, head: &str) -> BucketData {
+        let bucketdata = BucketData::new(dir, "408E376B-ECF7-4688-902A-1E7671BC5B9A");
+        std::fs::create_dir_all(bucketdata.master_ref_path().parent().unwrap()).unwrap();
+        std::fs::write(bucketdata.master_ref_path(), format!("{head}Y")).unwrap();
+        bucketdata
+    }
+
+    #[test]
+    fn test_latest_tree() {
+        let keys = master_keys();
+        let (root, mut objects) = fixture_objects();
+        let parent = add_commit(&mut objects, &keys, None);
+        let head = add_commit(&mut objects, &keys, Some(&parent));
+
+        let dir = tempfile::tempdir().unwrap();
+        bucketdata_with_head(dir.path(), &head);
+        let folder = make_folder("/Users/someuser/src", "/");
+
+        let tree = folder.latest_tree(dir.path(), &objects).unwrap();
+        let expected = Tree::new(&root, CompressionType::None).unwrap();
+        assert_eq!(tree.nodes.len(), 2);
+        assert_eq!(tree.to_bytes().unwrap(), expected.to_bytes().unwrap());
+
+        objects.remove(&head);
+        assert!(matches!(
+            folder.latest_tree(dir.path(), &objects),
+            Err(Error::ObjectNotFound(sha1)) if sha1 == head
+        ));
+
+        // A folder without bucket data of its own.
+        let mut other = make_folder("/Users/someuser/other", "/");
+        other.bucket_uuid = "00000000-0000-0000-0000-000000000000".to_string();
+        assert!(matches!(
+            other.latest_tree(dir.path(), &objects),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_chain_healthy() {
+        let keys = master_keys();
+        let (_, mut objects) = fixture_objects();
+        let first = add_commit(&mut objects, &keys, None);
+        let second = add_commit(&mut objects, &keys, Some(&first));
+        let head = add_commit(&mut objects, &keys, Some(&second));
+
+        let dir = tempfile::tempdir().unwrap();
+        let bucketdata = bucketdata_with_head(dir.path(), &head);
+        let report = verify_chain(&bucketdata, &objects, &keys).unwrap();
+
+        assert!(report.is_healthy());
+        assert_eq!(report.commits, vec![head, second, first]);
+    }
+
+    #[test]
+    fn test_verify_chain_partial_commits() {
+        let keys = master_keys();
+        let (_, mut objects) = fixture_objects();
+        let first = add_commit(&mut objects, &keys, None);
+        let commit = commit_bytes_with_flags(12, "roottree", Some(&first), true, true);
+        let second = content_address(&commit, &keys[2]);
+        objects.insert(second.clone(), commit);
+        let commit = commit_bytes_with_flags(12, "roottree", Some(&second), false, false);
+        let head = content_address(&commit, &keys[2]);
+        objects.insert(head.clone(), commit);
+
+        let dir = tempfile::tempdir().unwrap();
+        let bucketdata = bucketdata_with_head(dir.path(), &head);
+        let report = verify_chain(&bucketdata, &objects, &keys).unwrap();
+
+        assert!(report.is_healthy());
+        assert_eq!(report.commits, vec![head.clone(), second.clone(), first]);
+        assert_eq!(
+            report.partial,
+            vec![
+                (head, CommitStatus::Incomplete),
+                (second, CommitStatus::HasMissingNodes)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_chain_broken_parent() {
+        let keys = master_keys();
+        let (_, mut objects) = fixture_objects();
+        let missing = "0123456789012345678901234567890123456789";
+        let parent = add_commit(&mut objects, &keys, Some(missing));
+        let head = add_commit(&mut objects, &keys, Some(&parent));
+
+        let dir = tempfile::tempdir().unwrap();
+        let bucketdata = bucketdata_with_head(dir.path(), &head);
+        let report = verify_chain(&bucketdata, &objects, &keys).unwrap();
+        assert!(!report.is_healthy());
+        assert_eq!(report.commits, vec![head.clone(), parent.clone()]);
+        assert_eq!(
+            report.first_break,
+            Some(ChainBreak::MissingCommit(missing.to_string()))
+        );
+
+        // Tampering with a commit is caught too.
+        objects.get_mut(&parent).unwrap().push(0);
+        let report = verify_chain(&bucketdata, &objects, &keys).unwrap();
+        assert!(matches!(
+            report.first_break,
+            Some(ChainBreak::Sha1Mismatch { ref expected, .. }) if *expected == parent
+        ));
+    }
+
+    #[test]
+    fn test_verify_chain_without_salt_key() {
+        let keys = master_keys();
+        let (_, mut objects) = fixture_objects();
+        let head = add_commit(&mut objects, &keys, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let bucketdata = bucketdata_with_head(dir.path(), &head);
+        assert!(matches!(
+            verify_chain(&bucketdata, &objects, &keys[..2]),
+            Err(Error::InvalidKeyOrIvLength)
+        ));
+    }
 }
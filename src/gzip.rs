@@ -0,0 +1,77 @@
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::compression::GzipFormat;
+use crate::error::Result;
+
+/// Decompresses `src`, a DEFLATE stream wrapped according to `format`.
+pub fn decompress(src: &[u8], format: GzipFormat) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    match format {
+        GzipFormat::Gzip => GzDecoder::new(src).read_to_end(&mut decompressed)?,
+        GzipFormat::Zlib => ZlibDecoder::new(src).read_to_end(&mut decompressed)?,
+        GzipFormat::Deflate => DeflateDecoder::new(src).read_to_end(&mut decompressed)?,
+    };
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionType;
+    use crate::error::Error;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const PAYLOAD: &[u8] = b"the same payload, compressed three different ways";
+
+    fn compressed() -> Vec<(GzipFormat, Vec<u8>)> {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(PAYLOAD).unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(PAYLOAD).unwrap();
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(PAYLOAD).unwrap();
+        vec![
+            (GzipFormat::Gzip, gzip.finish().unwrap()),
+            (GzipFormat::Zlib, zlib.finish().unwrap()),
+            (GzipFormat::Deflate, deflate.finish().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_gzip_formats() {
+        for (format, data) in compressed() {
+            assert_eq!(GzipFormat::detect(&data), format);
+            assert_eq!(decompress(&data, format).unwrap(), PAYLOAD);
+            assert_eq!(
+                CompressionType::decompress(&data, CompressionType::Gzip).unwrap(),
+                PAYLOAD
+            );
+        }
+    }
+
+    #[test]
+    fn test_decompress_auto() {
+        for (format, data) in compressed() {
+            let expected = match format {
+                GzipFormat::Gzip => PAYLOAD.to_vec(),
+                _ => data.clone(),
+            };
+            assert_eq!(CompressionType::decompress_auto(&data).unwrap(), expected);
+        }
+        assert_eq!(CompressionType::decompress_auto(PAYLOAD).unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_gzip_wrong_format() {
+        let (_, gzip) = &compressed()[0];
+        assert!(matches!(
+            CompressionType::decompress_gzip(gzip, GzipFormat::Zlib),
+            Err(Error::IoError(_))
+        ));
+        assert_eq!(GzipFormat::detect(&[]), GzipFormat::Deflate);
+    }
+}
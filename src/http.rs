@@ -0,0 +1,325 @@
+//! Resolving objects over HTTP
+//! ---------------------------
+//!
+//! Arq destinations like S3, B2 or WebDAV servers can all serve a packset's files over
+//! HTTP. An [HttpResolver] reads pack indexes from such a destination and then fetches
+//! only the byte range of each object it resolves, so a backup can be browsed (or
+//! restored from) without mirroring it locally first.
+//! [crate::backupset::BackupSet::open_remote] opens a whole backup set this way.
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+
+use crate::error::{Error, Result};
+use crate::object_encryption::MasterKeys;
+use crate::packset::{PackIndex, PackObject};
+use crate::resolver::BlobResolver;
+
+/// Extra bytes fetched with each object to cover the mimetype and name stored before its
+/// data in the pack. Objects with longer ones are fetched again without an upper bound.
+const OBJECT_HEADER_ALLOWANCE: usize = 1024;
+
+type AuthHook = Box<dyn Fn(RequestBuilder) -> RequestBuilder>;
+
+/// A [BlobResolver] fetching objects from a packset served over HTTP.
+///
+/// `base_url` is the URL of the packset directory, e.g.
+/// `https://bucket.example.com/<computer_uuid>/packsets/<folder_uuid>-blobs`. HTTP has no
+/// standard way of listing a directory, so the packs to read are added by name with
+/// [HttpResolver::add_pack].
+pub struct HttpResolver {
+    client: Client,
+    base_url: String,
+    master_keys: MasterKeys,
+    auth: Option<AuthHook>,
+    objects: HashMap<String, RemoteLocation>,
+}
+
+/// Where an object is stored within a remote packset.
+struct RemoteLocation {
+    pack_sha1: String,
+    offset: usize,
+    data_len: usize,
+}
+
+impl HttpResolver {
+    /// Creates a resolver for the packset at `base_url`. The master keys are used to
+    /// decrypt objects when resolving them.
+    pub fn new(base_url: &str, master_keys: &MasterKeys) -> HttpResolver {
+        HttpResolver {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            master_keys: master_keys.clone(),
+            auth: None,
+            objects: HashMap::new(),
+        }
+    }
+
+    /// Calls `auth` on every request before it's sent, to add credentials (e.g. with
+    /// `RequestBuilder::basic_auth` or an `Authorization` header).
+    pub fn with_auth<F>(mut self, auth: F) -> HttpResolver
+    where
+        F: Fn(RequestBuilder) -> RequestBuilder + 'static,
+    {
+        self.auth = Some(Box::new(auth));
+        self
+    }
+
+    /// Fetches the index of the pack `pack_sha1` so its objects can be resolved, returning
+    /// how many objects it holds.
+    ///
+    /// Fails with `Error::ObjectNotFound` if the destination doesn't have the index.
+    pub fn add_pack(&mut self, pack_sha1: &str) -> Result<usize> {
+        let response = self.get(&format!("{pack_sha1}.index"), None, pack_sha1)?;
+        let content = response.bytes()?;
+        let index = PackIndex::new(Cursor::new(content))?;
+        let count = index.objects.len();
+        for object in index.objects {
+            self.objects.insert(
+                object.sha1,
+                RemoteLocation {
+                    pack_sha1: pack_sha1.to_string(),
+                    offset: object.offset,
+                    data_len: object.data_len,
+                },
+            );
+        }
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn contains(&self, sha1: &str) -> bool {
+        self.objects.contains_key(sha1)
+    }
+
+    /// Fetches the (still encrypted) object `sha1` from the pack holding it.
+    pub fn object(&self, sha1: &str) -> Result<PackObject> {
+        let location = self
+            .objects
+            .get(sha1)
+            .ok_or_else(|| Error::ObjectNotFound(sha1.to_string()))?;
+        let pack = format!("{}.pack", location.pack_sha1);
+
+        let end = location.offset + location.data_len + OBJECT_HEADER_ALLOWANCE - 1;
+        let content = self.get_range(&pack, location.offset, Some(end), sha1)?;
+        match PackObject::new(Cursor::new(&content)) {
+            Err(Error::TruncatedPack { .. }) => {}
+            Err(Error::IoError(ref err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            result => return result,
+        }
+
+        let content = self.get_range(&pack, location.offset, None, sha1)?;
+        PackObject::new(Cursor::new(content))
+    }
+
+    /// GETs bytes `start..=end` of `name`, or everything from `start` if there's no `end`.
+    ///
+    /// Servers that don't support ranges answer with the whole file and a 200 instead of
+    /// a 206; its content is cut down to the requested range so it's never parsed from
+    /// the wrong offset.
+    fn get_range(
+        &self,
+        name: &str,
+        start: usize,
+        end: Option<usize>,
+        sha1: &str,
+    ) -> Result<Vec<u8>> {
+        let range = match end {
+            Some(end) => format!("bytes={start}-{end}"),
+            None => format!("bytes={start}-"),
+        };
+        let response = self.get(name, Some(&range), sha1)?;
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let mut content = response.bytes()?.to_vec();
+        if !partial {
+            if let Some(end) = end {
+                content.truncate(end + 1);
+            }
+            content.drain(..start.min(content.len()));
+        }
+        Ok(content)
+    }
+
+    /// GETs `name` (relative to the base URL), reporting a 404 as `sha1` not being found
+    /// and any other error status as `Error::HttpError`.
+    fn get(&self, name: &str, range: Option<&str>, sha1: &str) -> Result<Response> {
+        let mut request = self.client.get(format!("{}/{}", self.base_url, name));
+        if let Some(range) = range {
+            request = request.header(RANGE, range);
+        }
+        if let Some(auth) = &self.auth {
+            request = auth(request);
+        }
+
+        let response: Response = request.send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Error::ObjectNotFound(sha1.to_string()));
+        }
+        Ok(response.error_for_status()?)
+    }
+}
+
+/// GETs `url`, reporting a 404 as an `ErrorKind::NotFound` I/O error, the same as a
+/// missing local file.
+pub(crate) fn fetch(client: &Client, url: &str) -> Result<Vec<u8>> {
+    let response: Response = client.get(url).send()?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, url.to_string()).into());
+    }
+    Ok(response.error_for_status()?.bytes()?.to_vec())
+}
+
+impl BlobResolver for HttpResolver {
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>> {
+        let object = self.object(sha1)?;
+        object.data.validate_with(&self.master_keys)?;
+        object.data.decrypt_with(&self.master_keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{master_keys, write_pack};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    /// Serves the files in `dir` over HTTP/1.1, honouring `Range` headers unless `ranges`
+    /// is false and requiring basic auth as `user:secret`. Returns the base URL.
+    fn serve(dir: PathBuf, ranges: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+
+                let mut range = None;
+                let mut authorized = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(": ").unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "range" => range = Some(value.to_string()),
+                        // "user:secret"
+                        "authorization" => authorized = value == "Basic dXNlcjpzZWNyZXQ=",
+                        _ => {}
+                    }
+                }
+
+                let file = dir.join(path.trim_start_matches("/packs/"));
+                let (status, body) = if !authorized {
+                    ("401 Unauthorized", Vec::new())
+                } else if let Ok(content) = std::fs::read(&file) {
+                    match range.filter(|_| ranges) {
+                        Some(range) => {
+                            let (start, end) =
+                                range.trim_start_matches("bytes=").split_once('-').unwrap();
+                            let start: usize = start.parse().unwrap();
+                            let end = end
+                                .parse::<usize>()
+                                .map_or(content.len(), |end| (end + 1).min(content.len()));
+                            ("206 Partial Content", content[start..end].to_vec())
+                        }
+                        None => ("200 OK", content),
+                    }
+                } else {
+                    ("404 Not Found", Vec::new())
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        format!("http://{addr}/packs/")
+    }
+
+    fn pack_sha1(dir: &std::path::Path) -> String {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension() == Some("pack".as_ref()))
+            .unwrap()
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_http_resolver() {
+        let keys = master_keys();
+        let dir = tempfile::tempdir().unwrap();
+        let large = vec![7; 3000];
+        let sha1s = write_pack(dir.path(), &[b"a tree", &large, b"a blob"], &keys);
+        let pack_sha1 = pack_sha1(dir.path());
+
+        let keys = MasterKeys::from_slices(&keys).unwrap();
+        let base_url = serve(dir.path().to_path_buf(), true);
+        let mut resolver = HttpResolver::new(&base_url, &keys)
+            .with_auth(|request| request.basic_auth("user", Some("secret")));
+        assert_eq!(resolver.add_pack(&pack_sha1).unwrap(), 3);
+        assert!(resolver.contains(&sha1s[1]));
+
+        assert_eq!(resolver.resolve(&sha1s[0]).unwrap(), b"a tree");
+        assert_eq!(resolver.resolve(&sha1s[1]).unwrap(), large);
+        assert_eq!(resolver.resolve(&sha1s[2]).unwrap(), b"a blob");
+        assert!(matches!(
+            resolver.resolve("0000000000000000000000000000000000000000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+        assert!(matches!(
+            resolver.add_pack("0000000000000000000000000000000000000000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+
+        let mut unauthorized = HttpResolver::new(&base_url, &keys);
+        assert!(matches!(
+            unauthorized.add_pack(&pack_sha1),
+            Err(Error::HttpError(_))
+        ));
+    }
+
+    #[test]
+    fn test_http_resolver_without_range_support() {
+        let keys = master_keys();
+        let dir = tempfile::tempdir().unwrap();
+        let large = vec![7; 3000];
+        let sha1s = write_pack(dir.path(), &[b"a tree", &large, b"a blob"], &keys);
+        let pack_sha1 = pack_sha1(dir.path());
+
+        let keys = MasterKeys::from_slices(&keys).unwrap();
+        let base_url = serve(dir.path().to_path_buf(), false);
+        let mut resolver = HttpResolver::new(&base_url, &keys)
+            .with_auth(|request| request.basic_auth("user", Some("secret")));
+        resolver.add_pack(&pack_sha1).unwrap();
+
+        assert_eq!(resolver.resolve(&sha1s[0]).unwrap(), b"a tree");
+        assert_eq!(resolver.resolve(&sha1s[1]).unwrap(), large);
+        assert_eq!(resolver.resolve(&sha1s[2]).unwrap(), b"a blob");
+    }
+}
@@ -0,0 +1,164 @@
+//! Caching pack indexes
+//! --------------------
+//!
+//! Opening a packset means parsing every `<sha1>.index` in it, which gets slow once a
+//! folder has accumulated thousands of packs. An [IndexCache] keeps what those indexes
+//! contain in a SQLite file, so [crate::packset::PackSet::open_with_cache] only has to
+//! parse the ones it hasn't seen before.
+//!
+//! Packs are named after the SHA1 of their contents and never change once written, so
+//! entries are keyed by index file name and never need invalidating. One cache can be
+//! shared by any number of packsets.
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::error::{Error, Result};
+use crate::packset::PackIndexObject;
+
+/// A SQLite file holding the objects listed in pack indexes.
+pub struct IndexCache {
+    connection: Connection,
+}
+
+impl IndexCache {
+    /// Opens the cache at `path`, creating it if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<IndexCache> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS indexes (
+                 name TEXT PRIMARY KEY
+             );
+             CREATE TABLE IF NOT EXISTS objects (
+                 index_name TEXT NOT NULL,
+                 sha1 TEXT NOT NULL,
+                 offset INTEGER NOT NULL,
+                 data_len INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS objects_by_index ON objects (index_name);",
+        )?;
+        Ok(IndexCache { connection })
+    }
+
+    /// Number of pack indexes in the cache.
+    pub fn len(&self) -> Result<usize> {
+        let count: i64 = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM indexes", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The objects listed in the pack index at `index_path`, from the cache if it's there
+    /// and otherwise from `read`, whose result is then cached.
+    pub fn get_or_insert_with<F>(&self, index_path: &Path, read: F) -> Result<Vec<PackIndexObject>>
+    where
+        F: FnOnce(&Path) -> Result<Vec<PackIndexObject>>,
+    {
+        let name = index_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::PathNotFound(index_path.to_path_buf()))?;
+        if let Some(objects) = self.get(name)? {
+            return Ok(objects);
+        }
+
+        let objects = read(index_path)?;
+        self.insert(name, &objects)?;
+        Ok(objects)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<PackIndexObject>>> {
+        let cached = self
+            .connection
+            .query_row("SELECT 1 FROM indexes WHERE name = ?1", [name], |_| Ok(()))
+            .optional()?;
+        if cached.is_none() {
+            return Ok(None);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT sha1, offset, data_len FROM objects WHERE index_name = ?1")?;
+        let objects = statement
+            .query_map([name], |row| {
+                Ok(PackIndexObject {
+                    sha1: row.get(0)?,
+                    offset: row.get::<_, i64>(1)? as usize,
+                    data_len: row.get::<_, i64>(2)? as usize,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(objects))
+    }
+
+    fn insert(&self, name: &str, objects: &[PackIndexObject]) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO objects (index_name, sha1, offset, data_len) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for object in objects {
+                statement.execute(params![
+                    name,
+                    object.sha1,
+                    object.offset as i64,
+                    object.data_len as i64
+                ])?;
+            }
+        }
+        transaction.execute("INSERT INTO indexes (name) VALUES (?1)", [name])?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packset::PackSet;
+    use crate::resolver::BlobResolver;
+    use crate::test_utils::{master_keys, write_pack};
+
+    #[test]
+    fn test_open_with_cache() {
+        let keys = master_keys();
+        let dir = tempfile::tempdir().unwrap();
+        let packset = dir.path().join("FOLDER-blobs");
+        let first = write_pack(&packset, &[b"one", b"two"], &keys);
+        let cache_path = dir.path().join("index-cache.sqlite");
+
+        let cache = IndexCache::open(&cache_path).unwrap();
+        assert!(cache.is_empty().unwrap());
+        let opened = PackSet::open_with_cache(&packset, &keys, &cache).unwrap();
+        assert_eq!(opened.len(), 2);
+        assert_eq!(cache.len().unwrap(), 1);
+        drop(cache);
+
+        // Break the index on disk: reopening only works if it's read from the cache.
+        let index_path = std::fs::read_dir(&packset)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension() == Some("index".as_ref()))
+            .unwrap();
+        std::fs::write(&index_path, b"not an index").unwrap();
+        let second = write_pack(&packset, &[b"three"], &keys);
+
+        let cache = IndexCache::open(&cache_path).unwrap();
+        let reopened = PackSet::open_with_cache(&packset, &keys, &cache).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(cache.len().unwrap(), 2);
+        assert_eq!(reopened.resolve(&first[1]).unwrap(), b"two");
+        assert_eq!(reopened.resolve(&second[0]).unwrap(), b"three");
+        let (cached, parsed) = (
+            reopened.location(&first[0]).unwrap(),
+            opened.location(&first[0]).unwrap(),
+        );
+        assert_eq!(cached.pack_path, parsed.pack_path);
+        assert_eq!(cached.offset, parsed.offset);
+        assert_eq!(cached.data_len, parsed.data_len);
+    }
+}
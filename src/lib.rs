@@ -19,6 +19,23 @@
 //! arq = "0.1"
 //! ```
 //!
+//! ## Features
+//!
+//! - `serde_json`: adds `from_json` constructors to the metadata types, for tools that keep
+//!   decrypted metadata around as JSON, and `Tree::to_json_value` for scripting.
+//! - `tar`: adds `Snapshot::export_tar`, to restore a snapshot as a tar archive.
+//! - `reqwest`: adds `http::HttpResolver`, to read objects straight from a destination
+//!   served over HTTP(S) instead of a local copy.
+//! - `rusqlite`: adds `index_cache::IndexCache`, a SQLite file remembering the contents of
+//!   pack indexes so opening a packset again doesn't parse every `.index` file.
+//!
+//! ## AES backend
+//!
+//! Hardware AES is used when the CPU has it, and the `aes` crate's software implementation
+//! otherwise. The software implementation is slower, but constant-time without relying on
+//! the hardware; to always use it, build with `RUSTFLAGS="--cfg aes_force_soft"`. The
+//! `aes` crate only offers this choice as a `cfg` flag, so there's no Cargo feature for it.
+//!
 //! ## Examples
 //!
 //! 1. Reading a computer info entry:
@@ -54,27 +71,49 @@
 extern crate aes;
 extern crate byteorder;
 extern crate chrono;
+extern crate flate2;
 #[cfg_attr(test, macro_use)]
 extern crate hex_literal;
 extern crate hmac;
 extern crate plist;
+#[cfg(feature = "reqwest")]
+extern crate reqwest;
 extern crate ring;
+#[cfg(feature = "rusqlite")]
+extern crate rusqlite;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
 extern crate sha1;
 extern crate sha2;
+#[cfg(feature = "tar")]
+extern crate tar;
 
+pub mod backupset;
+pub mod bucketdata;
 pub mod compression;
 pub mod computer;
 pub mod error;
 pub mod folder;
+#[cfg(feature = "reqwest")]
+pub mod http;
+#[cfg(feature = "rusqlite")]
+pub mod index_cache;
+pub mod object;
 pub mod object_encryption;
 pub mod packset;
+pub mod resolver;
+pub mod snapshot;
 pub mod tree;
 pub mod type_utils;
 
 mod blob;
 mod date;
+mod gzip;
 mod lz4;
+#[cfg(any(test, feature = "test-utils"))]
+#[doc(hidden)]
+pub mod test_utils;
 mod utils;
@@ -1,20 +1,29 @@
 use std::convert::TryInto;
 use std::io::Cursor;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::type_utils::ArqRead;
 
-#[cfg(test)]
-fn compress(src: &[u8]) -> Result<Vec<u8>> {
+#[cfg(any(test, feature = "test-utils"))]
+pub fn compress(src: &[u8]) -> Result<Vec<u8>> {
     let length: [u8; 4] = (src.len() as i32).to_be_bytes();
     let compressed_data = lz4_flex::compress(src);
     let all = [&length[..], &compressed_data].concat();
     Ok(all)
 }
 
+/// Decompresses `src`, a 4-byte big endian length followed by an LZ4 block.
 pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    if src.len() < 4 {
+        return Err(Error::UnexpectedEof {
+            while_reading: "lz4 length",
+        });
+    }
     let mut reader = Cursor::new(src);
     let original_len = reader.read_arq_i32()?;
+    if original_len < 0 {
+        return Err(Error::NegativeDecompressedLength(original_len));
+    }
     Ok(lz4_flex::decompress(&src[4..], original_len.try_into()?)?)
 }
 
@@ -31,4 +40,23 @@ mod tests {
         // with zeros
         assert_eq!(test[..], decompressed[..test.len()]);
     }
+
+    #[test]
+    fn test_negative_length() {
+        let mut compressed = compress(b"some data").unwrap();
+        compressed[..4].copy_from_slice(&(-2i32).to_be_bytes());
+        let err = decompress(&compressed).unwrap_err();
+        assert!(matches!(err, Error::NegativeDecompressedLength(-2)));
+        assert_eq!(err.to_string(), "lz4 declared length is negative: -2");
+    }
+
+    #[test]
+    fn test_short_input() {
+        assert!(matches!(
+            decompress(&[0, 1]),
+            Err(Error::UnexpectedEof {
+                while_reading: "lz4 length"
+            })
+        ));
+    }
 }
@@ -0,0 +1,72 @@
+//! Parsing objects without knowing their type upfront
+//!
+//! Objects pulled out of a pack are just bytes: they could be a commit, a tree or any
+//! other blob (file contents, xattrs, ACLs). [parse_object] looks at the header of the
+//! decompressed content to figure out which one it is.
+use std::io::Cursor;
+
+use crate::compression::CompressionType;
+use crate::error::Result;
+use crate::tree::{Commit, Tree};
+
+/// An object parsed according to its header.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Object {
+    Commit(Commit),
+    Tree(Tree),
+    Blob(Vec<u8>),
+}
+
+/// Decompresses `bytes` and parses them as a Commit or a Tree if they start with the
+/// matching header. Anything else is returned as a blob.
+pub fn parse_object(bytes: &[u8], compression_type: CompressionType) -> Result<Object> {
+    let content = CompressionType::decompress(bytes, compression_type)?;
+
+    Ok(if content.starts_with(b"CommitV") {
+        Object::Commit(Commit::new(Cursor::new(content))?)
+    } else if content.starts_with(b"TreeV") {
+        Object::Tree(Tree::new(&content, CompressionType::None)?)
+    } else {
+        Object::Blob(content)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_bytes, fixture_objects};
+
+    #[test]
+    fn test_parse_commit() {
+        let bytes = commit_bytes("subtree", None);
+        match parse_object(&bytes, CompressionType::None).unwrap() {
+            Object::Commit(commit) => assert_eq!(commit.tree_sha1, "subtree"),
+            other => panic!("expected a commit, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let (root, _) = fixture_objects();
+        match parse_object(&root, CompressionType::None).unwrap() {
+            Object::Tree(tree) => {
+                assert_eq!(tree.version, 22);
+                assert_eq!(tree.nodes.len(), 2);
+            }
+            other => panic!("expected a tree, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_blob() {
+        let bytes = b"Tree but not really a tree".to_vec();
+        assert_eq!(
+            parse_object(&bytes, CompressionType::None).unwrap(),
+            Object::Blob(bytes)
+        );
+        assert_eq!(
+            parse_object(&[], CompressionType::None).unwrap(),
+            Object::Blob(Vec::new())
+        );
+    }
+}
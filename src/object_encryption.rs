@@ -17,15 +17,36 @@ use ring::{
 };
 use sha1::{Digest, Sha1};
 use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
 
+use crate::compression::CompressionType;
 use crate::error::{Error, Result};
 use crate::type_utils::ArqRead;
+use crate::utils::convert_to_hex_string;
 
+// `aes::Aes256` picks its backend itself: AES-NI (or ARMv8) when the CPU has it, the
+// constant-time software implementation otherwise, or always the latter when built with
+// `--cfg aes_force_soft` (see the crate docs).
 type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
 type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
 
 const ENCRYPTION_V2_HEADER: [u8; 12] = [69, 78, 67, 82, 89, 80, 84, 73, 79, 78, 86, 50]; // ENCRYPTIONV2
 
+/// How many objects Arq encrypts with the same session key before generating a new one.
+pub const SESSION_KEY_MAX_USES: usize = 256;
+
+/// Encrypts `data` with AES256-CBC and PKCS#7 padding.
+fn cbc_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = vec![0; data.len() + 16];
+    buf[..data.len()].copy_from_slice(data);
+    let len = Aes256CbcEnc::new_from_slices(key, iv)?
+        .encrypt_padded_mut::<Pkcs7>(&mut buf, data.len())
+        .map_err(|_| Error::CipherError)?
+        .len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
 fn calculate_hmacsha256(secret: &[u8], message: &[u8]) -> Result<Vec<u8>> {
     let mut mac = Hmac::<Sha256>::new_from_slice(secret)?;
     mac.update(message);
@@ -38,6 +59,24 @@ pub fn calculate_sha1sum(message: &[u8]) -> Vec<u8> {
     sha.finalize().to_vec()
 }
 
+/// Calculates the SHA1 Arq uses to name (and de-duplicate) an object.
+///
+/// It's the SHA1 of the object's plaintext data concatenated with `salt_key`, which is the
+/// third master key (or the computer UUID for sets with an encryptionv2.dat file).
+pub fn content_address(data: &[u8], salt_key: &[u8]) -> String {
+    convert_to_hex_string(&calculate_sha1sum(&[data, salt_key].concat()))
+}
+
+/// Checks whether `data` would be stored under `expected_sha1`.
+///
+/// Fails with `Error::ParseError` if `expected_sha1` isn't a 40 character hex string.
+pub fn verify_dedup(data: &[u8], salt_key: &[u8], expected_sha1: &str) -> Result<bool> {
+    if expected_sha1.len() != 40 || !expected_sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::ParseError);
+    }
+    Ok(content_address(data, salt_key).eq_ignore_ascii_case(expected_sha1))
+}
+
 pub trait Validation {
     fn validate(&self, _: usize, _: &str);
 }
@@ -104,7 +143,7 @@ impl Validation for Header {
 /// 5. Encrypt the master keys with AES256-CBC using the first 32 bytes of the derived key from step 4 and IV from step 2.
 /// 6. Calculate the HMAC-SHA256 of (IV + encrypted master keys) using the second 32 bytes of the derived key from step 4.
 /// 7. Concatenate the items as described in the file format shown above.
-//
+///
 /// To get the 3 "master keys":
 /// 1. Copy salt from the 8 bytes after the header.
 /// 2. Derive 64-byte encryption key from user-supplied encryption password using PBKDF2/HMACSHA1 (200000 rounds) and the salt from step 1.
@@ -122,32 +161,37 @@ impl Validation for Header {
 /// calculating the SHA1 hash (see "Content-Addressable Storage" above). Arq changed to
 /// using a third secret key for salting the hash instead of a known value to address a
 /// privacy issue.
+///
+/// The master keys are the only copy of the key material an `EncryptionDat` keeps, and
+/// they're zeroized when it's dropped. Use [EncryptionDat::into_master_keys] to take them
+/// out of it.
 #[derive(Debug)]
 pub struct EncryptionDat {
     pub master_keys: Vec<Vec<u8>>,
 }
 
 impl EncryptionDat {
-    fn parse_master_keys(master_keys: Vec<u8>) -> Vec<Vec<u8>> {
-        let master_key_1 = &master_keys[0..32];
-        let master_key_2 = &master_keys[32..64];
-        let master_key_3 = &master_keys[64..96];
+    /// Splits the decrypted key material into 32-byte master keys.
+    ///
+    /// encryptionv3.dat holds 3 master keys (96 bytes) and encryptionv2.dat holds 2 (64
+    /// bytes); anything else means the file is corrupt.
+    fn parse_master_keys(master_keys: &[u8]) -> Result<Vec<Vec<u8>>> {
+        if master_keys.len() != 96 && master_keys.len() != 64 {
+            return Err(Error::ParseError);
+        }
 
-        vec![
-            master_key_1.to_vec(),
-            master_key_2.to_vec(),
-            master_key_3.to_vec(),
-        ]
+        Ok(master_keys.chunks(32).map(|key| key.to_vec()).collect())
     }
 
-    fn derive_encryption_key(password: &[u8], salt: &[u8], result: &mut [u8]) {
-        pbkdf2::derive(
-            pbkdf2::PBKDF2_HMAC_SHA1,
-            std::num::NonZeroU32::new(200_000).unwrap(), // this unwrap will always succeed
-            salt,
-            password,
-            result,
-        );
+    /// Builds an `EncryptionDat` from raw key material, e.g. the keys exported from the
+    /// macOS keychain, skipping the password.
+    ///
+    /// `keys` is the 3 master keys (96 bytes) one after the other, or 2 (64 bytes) for
+    /// backups with an encryptionv2.dat. Any other length is an `Error::ParseError`.
+    pub fn from_master_keys(keys: &[u8]) -> Result<EncryptionDat> {
+        Ok(EncryptionDat {
+            master_keys: Self::parse_master_keys(keys)?,
+        })
     }
 
     /// Generate EncryptionDat given a user-supplied password
@@ -158,6 +202,19 @@ impl EncryptionDat {
     /// let mut reader = std::io::Cursor::new(&enc_dat[..]);
     /// let e_d = EncryptionDat::new(&mut reader, "nor").unwrap();
     pub fn generate(password: &str) -> Result<Vec<u8>> {
+        Self::generate_with_keys(password, 3)
+    }
+
+    /// Generates an encryptionv2.dat, holding only 2 master keys, for tools that need to
+    /// produce backup sets in the older layout.
+    ///
+    /// Such a set has no salt key: the SHA1s of its objects are salted with the computer's
+    /// UUID instead (see [content_address]).
+    pub fn generate_v2(password: &str) -> Result<Vec<u8>> {
+        Self::generate_with_keys(password, 2)
+    }
+
+    fn generate_with_keys(password: &str, key_count: usize) -> Result<Vec<u8>> {
         let randomiser = SystemRandom::new();
         // 1. Generate a random salt.
         let mut salt = [0u8; 8];
@@ -165,61 +222,198 @@ impl EncryptionDat {
         // 2. Generate a random IV.
         let mut iv = [0u8; 16];
         randomiser.fill(&mut iv).unwrap();
-        // 3. Generate 3 random 32-byte "master keys" (96 bytes total).
-        let mut master_keys_buf = [0u8; 96];
+        // 3. Generate 3 (or 2, for encryptionv2.dat) random 32-byte "master keys".
+        let mut master_keys_buf = vec![0u8; key_count * 32];
         randomiser.fill(&mut master_keys_buf).unwrap();
         // 4. Derive 64-byte encryption key from user-supplied encryption password using
         // PBKDF2/HMACSHA1 (200000 rounds) and the salt from step 1.
-        let mut encryption_key: [u8; 64] = [0; 64];
-        Self::derive_encryption_key(password.as_bytes(), &salt, &mut encryption_key);
+        let derived_key = DerivedKey::derive(password.as_bytes(), &salt);
         // 5. Encrypt the master keys with AES256-CBC using the first 32 bytes of the
         // derived key from step 4 and IV from step 2.
-        let mut buf = [0; 160];
-        //buf.copy_from_slice(&[&master_keys_buf[..], &[0; 16][..]].concat());
-        buf[..master_keys_buf.len()].copy_from_slice(&master_keys_buf);
-        let encrypted = Aes256CbcEnc::new_from_slices(&encryption_key[..32], &iv)?
-            .encrypt_padded_mut::<Pkcs7>(&mut buf, 96)
-            .unwrap();
+        let encrypted = cbc_encrypt(derived_key.encryption_half(), &iv, &master_keys_buf)?;
         // 6. Calculate the HMAC-SHA256 of (IV + encrypted master keys) using the second
         // 32 bytes of the derived key from step 4.
-        assert_eq!(encrypted.len(), 112);
+        assert_eq!(encrypted.len(), master_keys_buf.len() + 16);
+        master_keys_buf.zeroize();
         let hmac_sha256 =
-            calculate_hmacsha256(&encryption_key[32..], &[&iv[..], encrypted].concat())?;
+            calculate_hmacsha256(derived_key.hmac_half(), &[&iv[..], &encrypted].concat())?;
         assert_eq!(hmac_sha256.len(), 32);
         Ok([
             &ENCRYPTION_V2_HEADER,
             &salt[..],
             &hmac_sha256,
             &iv,
-            encrypted,
+            &encrypted,
         ]
         .concat())
     }
 
+    /// Reads an encryptionv3.dat or, going by how many master keys it decrypts to, an
+    /// encryptionv2.dat.
     pub fn new<R: BufRead + Seek>(mut reader: R, password: &str) -> Result<EncryptionDat> {
         let header = reader.read_bytes(12)?;
-        assert_eq!(header, ENCRYPTION_V2_HEADER);
+        if header != ENCRYPTION_V2_HEADER {
+            return Err(Error::ParseError);
+        }
         let salt = reader.read_bytes(8)?;
         let hmacsha256 = reader.read_bytes(32)?;
         let iv = reader.read_bytes(16)?;
-        let mut encrypted_master_keys = reader.read_bytes(112)?;
+        let mut encrypted_master_keys = Vec::new();
+        reader.read_to_end(&mut encrypted_master_keys)?;
 
-        let mut encryption_key: [u8; 64] = [0u8; 64];
-        Self::derive_encryption_key(password.as_bytes(), &salt[..], &mut encryption_key);
+        let derived_key = DerivedKey::derive(password.as_bytes(), &salt);
 
         let iv_and_keys = [&iv[..], &encrypted_master_keys[..]].concat();
-        let calculated_hmacsha256 = calculate_hmacsha256(&encryption_key[32..64], &iv_and_keys)?;
+        let calculated_hmacsha256 = calculate_hmacsha256(derived_key.hmac_half(), &iv_and_keys)?;
         if calculated_hmacsha256 != hmacsha256 {
             return Err(Error::WrongPassword);
         }
 
-        let pt = Aes256CbcDec::new_from_slices(&encryption_key[0..32], &iv[..])?
-            .decrypt_padded_mut::<Pkcs7>(&mut encrypted_master_keys)?;
-
+        // The keys are decrypted in place, so the buffer is wiped whether or not that
+        // works out.
+        let master_keys = Self::decrypt_master_keys(&derived_key, &iv, &mut encrypted_master_keys);
+        encrypted_master_keys.zeroize();
         Ok(EncryptionDat {
-            master_keys: Self::parse_master_keys(pt.to_vec()),
+            master_keys: master_keys?,
         })
     }
+
+    fn decrypt_master_keys(
+        derived_key: &DerivedKey,
+        iv: &[u8],
+        encrypted_master_keys: &mut [u8],
+    ) -> Result<Vec<Vec<u8>>> {
+        let pt = Aes256CbcDec::new_from_slices(derived_key.encryption_half(), iv)?
+            .decrypt_padded_mut::<Pkcs7>(encrypted_master_keys)?;
+        Self::parse_master_keys(pt)
+    }
+
+    /// Takes the master keys out, leaving nothing to zeroize behind. They're zeroized in
+    /// turn when the returned value is dropped.
+    pub fn into_master_keys(mut self) -> Zeroizing<Vec<Vec<u8>>> {
+        Zeroizing::new(std::mem::take(&mut self.master_keys))
+    }
+}
+
+impl Drop for EncryptionDat {
+    fn drop(&mut self) {
+        self.master_keys.zeroize();
+    }
+}
+
+/// The 64-byte key derived from the encryption password with PBKDF2/HMACSHA1 (200000
+/// rounds), which protects the master keys in an [EncryptionDat].
+///
+/// Its first half is the AES-256 key the master keys are encrypted with, and its second
+/// half the key of the HMAC-SHA256 that authenticates them.
+pub struct DerivedKey([u8; 64]);
+
+impl DerivedKey {
+    pub fn derive(password: &[u8], salt: &[u8]) -> DerivedKey {
+        let mut key = [0u8; 64];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA1,
+            std::num::NonZeroU32::new(200_000).unwrap(), // this unwrap will always succeed
+            salt,
+            password,
+            &mut key,
+        );
+        DerivedKey(key)
+    }
+
+    /// Wraps an already derived key.
+    pub fn from_bytes(key: [u8; 64]) -> DerivedKey {
+        DerivedKey(key)
+    }
+
+    /// The key the master keys are encrypted with (bytes 0 to 32).
+    pub fn encryption_half(&self) -> &[u8] {
+        &self.0[..32]
+    }
+
+    /// The key the HMAC over the IV and encrypted master keys is keyed with (bytes 32 to
+    /// 64).
+    pub fn hmac_half(&self) -> &[u8] {
+        &self.0[32..]
+    }
+}
+
+impl Drop for DerivedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for DerivedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DerivedKey { .. }")
+    }
+}
+
+/// The master keys held by an [EncryptionDat], by role.
+///
+/// Passing the raw keys around means remembering that index 0 is the encryption key,
+/// index 1 the HMAC key and index 2 the salt used for SHA1s; mixing them up only shows
+/// up as failed validations. `MasterKeys` names them instead.
+#[derive(Clone, PartialEq)]
+pub struct MasterKeys {
+    pub encryption: [u8; 32],
+    pub hmac: [u8; 32],
+    pub salt: [u8; 32],
+}
+
+impl MasterKeys {
+    /// Builds the keys from `[encryption, hmac, salt]`, as found in
+    /// `EncryptionDat::master_keys`.
+    ///
+    /// Returns `Error::ParseError` unless there are 3 keys of 32 bytes each, which means
+    /// encryptionv2.dat files (which have no salt key) aren't supported.
+    pub fn from_slices(keys: &[Vec<u8>]) -> Result<MasterKeys> {
+        let key = |index: usize| -> Result<[u8; 32]> {
+            keys.get(index)
+                .and_then(|key| key.as_slice().try_into().ok())
+                .ok_or(Error::ParseError)
+        };
+        if keys.len() != 3 {
+            return Err(Error::ParseError);
+        }
+        Ok(MasterKeys {
+            encryption: key(0)?,
+            hmac: key(1)?,
+            salt: key(2)?,
+        })
+    }
+
+    /// The keys in the order the slice-based APIs expect them.
+    pub fn to_vec(&self) -> Vec<Vec<u8>> {
+        vec![
+            self.encryption.to_vec(),
+            self.hmac.to_vec(),
+            self.salt.to_vec(),
+        ]
+    }
+}
+
+impl TryFrom<&EncryptionDat> for MasterKeys {
+    type Error = Error;
+
+    fn try_from(encryption_dat: &EncryptionDat) -> Result<MasterKeys> {
+        MasterKeys::from_slices(&encryption_dat.master_keys)
+    }
+}
+
+impl Drop for MasterKeys {
+    fn drop(&mut self) {
+        self.encryption.zeroize();
+        self.hmac.zeroize();
+        self.salt.zeroize();
+    }
+}
+
+impl std::fmt::Debug for MasterKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKeys { .. }")
+    }
 }
 
 /// EncryptedObject
@@ -256,12 +450,13 @@ impl EncryptionDat {
 /// 5. Encrypt (data IV + session key) with AES/CBC using the first "master key" from the Encryption Dat File and the "master IV".
 /// 4. Calculate HMAC-SHA256 of (master IV + "encrypted data IV + session key" + ciphertext) using the second 256-bit "master key".
 /// 7. Assemble the data in the format shown above.
-
+///
 /// To get the plaintext:
 /// 1. Calculate HMAC-SHA256 of (master IV + "encrypted data IV + session key" + ciphertext) and verify against HMAC-SHA256 in the file using the second "master key" from the Encryption Dat File.
 /// 2. Ensure the calculated HMAC-SHA256 matches the value in the object header.
 /// 3. Decrypt "encrypted data IV + session key" using the first "master key" from the Encryption Dat File and the "master IV".
 /// 4. Decrypt the ciphertext using the session key and data IV.
+#[derive(Clone, PartialEq, Debug)]
 pub struct EncryptedObject {
     hmac_sha256: Vec<u8>, //TODO: can we make this [u8; size?]
     master_iv: Vec<u8>,
@@ -272,7 +467,10 @@ pub struct EncryptedObject {
 impl EncryptedObject {
     pub fn new<R: ArqRead + BufRead>(mut reader: R) -> Result<EncryptedObject> {
         let header = reader.read_bytes(4)?.to_vec();
-        assert_eq!(header, [65, 82, 81, 79]); // ARQO
+        if header != [65, 82, 81, 79] {
+            // ARQO
+            return Err(Error::ParseError);
+        }
         let hmac_sha256 = reader.read_bytes(32)?.to_vec();
         let master_iv = reader.read_bytes(16)?.to_vec();
         let encrypted_data_iv_session = reader.read_bytes(64)?.to_vec();
@@ -287,15 +485,72 @@ impl EncryptedObject {
         })
     }
 
+    /// Serializes the object back into the format described above.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            &[65, 82, 81, 79][..], // ARQO
+            &self.hmac_sha256,
+            &self.master_iv,
+            &self.encrypted_data_iv_session,
+            &self.ciphertext,
+        ]
+        .concat()
+    }
+
+    /// Size of the encrypted payload, which is what decrypting will allocate (give or take
+    /// up to 16 bytes of padding), before any decompression.
+    pub fn ciphertext_len(&self) -> usize {
+        self.ciphertext.len()
+    }
+
+    /// Checks the object's HMAC-SHA256 with the second master key, returning
+    /// `Error::HmacMismatch` if the object was tampered with or the key is wrong.
     pub fn validate(&self, master_key: &[u8]) -> Result<()> {
-        let mut master_iv_and_data = self.master_iv.clone();
-        master_iv_and_data.append(&mut self.encrypted_data_iv_session.clone());
-        master_iv_and_data.append(&mut self.ciphertext.clone());
-        let calculated_hmacsha256 = calculate_hmacsha256(master_key, &master_iv_and_data)?;
-        assert_eq!(calculated_hmacsha256, self.hmac_sha256);
-        Ok(())
+        self.verify_only(master_key)
+    }
+
+    /// Checks the object's integrity without decrypting it, which only needs the HMAC key
+    /// (the second master key) and is much cheaper than decrypting.
+    pub fn verify_only(&self, hmac_key: &[u8]) -> Result<()> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(hmac_key)?;
+        mac.update(&self.master_iv);
+        mac.update(&self.encrypted_data_iv_session);
+        mac.update(&self.ciphertext);
+        mac.verify_slice(&self.hmac_sha256)
+            .map_err(|_| Error::HmacMismatch)
+    }
+
+    /// Validates the object's HMAC with `master_keys.hmac`.
+    pub fn validate_with(&self, master_keys: &MasterKeys) -> Result<()> {
+        self.validate(&master_keys.hmac)
+    }
+
+    /// Decrypts the object with `master_keys.encryption`.
+    pub fn decrypt_with(&self, master_keys: &MasterKeys) -> Result<Vec<u8>> {
+        self.decrypt(&master_keys.encryption)
+    }
+
+    /// Like [EncryptedObject::decode], taking the keys by role.
+    pub fn decode_with(
+        &self,
+        master_keys: &MasterKeys,
+        compression_type: CompressionType,
+    ) -> Result<Vec<u8>> {
+        self.decode(&master_keys.encryption, compression_type)
+    }
+
+    /// Decrypts the object and then decompresses the result.
+    pub fn decode(&self, master_key: &[u8], compression_type: CompressionType) -> Result<Vec<u8>> {
+        let decrypted = self.decrypt(master_key)?;
+        CompressionType::decompress(&decrypted, compression_type)
     }
 
+    /// Decrypts the object with the first master key.
+    ///
+    /// This doesn't check the object's HMAC, so it works without the second master key
+    /// (e.g. when recovering from a partial set of keys), but tampered or corrupt data goes
+    /// unnoticed or comes out as a padding error. Call [EncryptedObject::validate] first
+    /// when the HMAC key is available.
     pub fn decrypt(&self, master_key: &[u8]) -> Result<Vec<u8>> {
         let mut enc_data_iv_session = self.encrypted_data_iv_session.clone();
         let master_iv = self.master_iv.clone();
@@ -310,11 +565,176 @@ impl EncryptedObject {
             .decrypt_padded_mut::<Pkcs7>(&mut ciphertext)?;
         Ok(content.to_owned())
     }
+
+    /// Encrypts `plaintext` with `session_key` under fresh random IVs, protecting the
+    /// session key with the first master key and the object with an HMAC keyed with the
+    /// second one.
+    ///
+    /// Use an [ObjectEncryptor] to have session keys generated and rotated the way Arq
+    /// does.
+    pub fn encrypt(
+        plaintext: &[u8],
+        master_keys: &[Vec<u8>],
+        session_key: &[u8; 32],
+    ) -> Result<EncryptedObject> {
+        if master_keys.len() < 2 {
+            return Err(Error::InvalidKeyOrIvLength);
+        }
+        let randomiser = SystemRandom::new();
+        let mut data_iv = [0u8; 16];
+        let mut master_iv = [0u8; 16];
+        randomiser
+            .fill(&mut data_iv)
+            .and_then(|_| randomiser.fill(&mut master_iv))
+            .map_err(|_| Error::CryptoError)?;
+
+        let ciphertext = cbc_encrypt(session_key, &data_iv, plaintext)?;
+        let encrypted_data_iv_session = cbc_encrypt(
+            &master_keys[0],
+            &master_iv,
+            &[&data_iv[..], &session_key[..]].concat(),
+        )?;
+        let hmac_sha256 = calculate_hmacsha256(
+            &master_keys[1],
+            &[&master_iv[..], &encrypted_data_iv_session, &ciphertext].concat(),
+        )?;
+
+        Ok(EncryptedObject {
+            hmac_sha256,
+            master_iv: master_iv.to_vec(),
+            encrypted_data_iv_session,
+            ciphertext,
+        })
+    }
+}
+
+/// Encrypts objects the way Arq does: a random session key is generated and reused for
+/// up to [SESSION_KEY_MAX_USES] objects, then replaced by a new one.
+pub struct ObjectEncryptor {
+    master_keys: Zeroizing<Vec<Vec<u8>>>,
+    randomiser: SystemRandom,
+    session_key: Option<[u8; 32]>,
+    session_key_uses: usize,
+}
+
+impl ObjectEncryptor {
+    pub fn new(master_keys: &[Vec<u8>]) -> ObjectEncryptor {
+        ObjectEncryptor {
+            master_keys: Zeroizing::new(master_keys.to_vec()),
+            randomiser: SystemRandom::new(),
+            session_key: None,
+            session_key_uses: 0,
+        }
+    }
+
+    /// Number of objects encrypted with the current session key, up to
+    /// [SESSION_KEY_MAX_USES].
+    pub fn session_key_uses(&self) -> usize {
+        self.session_key_uses
+    }
+
+    /// Encrypts `plaintext`, generating a new session key first if the current one has
+    /// been used [SESSION_KEY_MAX_USES] times.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedObject> {
+        let session_key = match self.session_key {
+            Some(session_key) if self.session_key_uses < SESSION_KEY_MAX_USES => session_key,
+            _ => {
+                let mut session_key = [0; 32];
+                self.randomiser
+                    .fill(&mut session_key)
+                    .map_err(|_| Error::CryptoError)?;
+                self.session_key = Some(session_key);
+                self.session_key_uses = 0;
+                session_key
+            }
+        };
+        let object = EncryptedObject::encrypt(plaintext, &self.master_keys, &session_key)?;
+        self.session_key_uses += 1;
+        Ok(object)
+    }
+}
+
+impl Drop for ObjectEncryptor {
+    fn drop(&mut self) {
+        self.session_key.zeroize();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lz4;
+    use crate::test_utils::{encrypt, master_keys};
+
+    /// The session key an object was encrypted with.
+    fn session_key(object: &EncryptedObject, master_key: &[u8]) -> Vec<u8> {
+        let mut data_iv_session = object.encrypted_data_iv_session.clone();
+        Aes256CbcDec::new_from_slices(master_key, &object.master_iv)
+            .unwrap()
+            .decrypt_padded_mut::<Pkcs7>(&mut data_iv_session)
+            .unwrap()[16..48]
+            .to_vec()
+    }
+
+    #[test]
+    fn test_fixture_encrypted_object() {
+        let dat = std::fs::read("fixtures/AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D/encryptionv3.dat")
+            .unwrap();
+        let keys = EncryptionDat::new(std::io::Cursor::new(dat), "evu")
+            .unwrap()
+            .into_master_keys();
+        assert_eq!(keys.len(), 3);
+
+        let folder = std::fs::read(
+            "fixtures/AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D/buckets/7C19E8AF-FFE9-4952-B1E1-8D5181012BB1",
+        )
+        .unwrap();
+        assert_eq!(&folder[..9], b"encrypted");
+        let object = EncryptedObject::new(&folder[9..]).unwrap();
+        object.validate(&keys[1]).unwrap();
+        let plaintext = object.decrypt(&keys[0]).unwrap();
+        assert!(plaintext.trim_ascii_start().starts_with(b"<plist"));
+    }
+
+    #[test]
+    fn test_object_encryptor_rotates_session_key() {
+        let keys = master_keys();
+        let mut encryptor = ObjectEncryptor::new(&keys);
+        assert_eq!(encryptor.session_key_uses(), 0);
+
+        let mut session_keys = Vec::new();
+        for n in 0..SESSION_KEY_MAX_USES + 1 {
+            let plaintext = n.to_string();
+            let object = encryptor.encrypt(plaintext.as_bytes()).unwrap();
+            object.validate(&keys[1]).unwrap();
+            assert_eq!(object.decrypt(&keys[0]).unwrap(), plaintext.as_bytes());
+            session_keys.push(session_key(&object, &keys[0]));
+            if n < SESSION_KEY_MAX_USES {
+                assert_eq!(encryptor.session_key_uses(), n + 1);
+            }
+        }
+
+        assert!(session_keys[..SESSION_KEY_MAX_USES]
+            .iter()
+            .all(|key| *key == session_keys[0]));
+        assert_ne!(session_keys[SESSION_KEY_MAX_USES], session_keys[0]);
+        assert_eq!(encryptor.session_key_uses(), 1);
+
+        // Fresh IVs for every object, even with the same session key.
+        let first = encryptor.encrypt(b"same").unwrap();
+        let second = encryptor.encrypt(b"same").unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_encryption_dat_wrong_header() {
+        let mut enc_dat = EncryptionDat::generate("nor").unwrap();
+        enc_dat[0] = b'X';
+        assert!(matches!(
+            EncryptionDat::new(std::io::Cursor::new(enc_dat), "nor"),
+            Err(Error::ParseError)
+        ));
+    }
 
     #[test]
     fn test_generate_encryption_dat() {
@@ -324,6 +744,197 @@ mod tests {
         let _ = EncryptionDat::new(&mut reader, password).unwrap();
     }
 
+    #[test]
+    fn test_generate_encryption_dat_v2() {
+        let password = "nor";
+        let enc_dat = EncryptionDat::generate_v2(password).unwrap();
+        assert_eq!(enc_dat.len(), 12 + 8 + 32 + 16 + 80);
+
+        let e_d = EncryptionDat::new(std::io::Cursor::new(&enc_dat[..]), password).unwrap();
+        assert_eq!(e_d.master_keys.len(), 2);
+        assert!(e_d.master_keys.iter().all(|key| key.len() == 32));
+        assert!(MasterKeys::try_from(&e_d).is_err());
+        assert!(matches!(
+            EncryptionDat::new(std::io::Cursor::new(&enc_dat[..]), "evu"),
+            Err(Error::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn test_derived_key_halves() {
+        let mut bytes = [0u8; 64];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let key = DerivedKey::from_bytes(bytes);
+        assert_eq!(key.encryption_half(), (0..32).collect::<Vec<u8>>());
+        assert_eq!(key.hmac_half(), (32..64).collect::<Vec<u8>>());
+        assert_eq!(format!("{key:?}"), "DerivedKey { .. }");
+    }
+
+    #[test]
+    fn test_parse_master_keys() {
+        let keys = EncryptionDat::parse_master_keys(&(0..96).collect::<Vec<u8>>()).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[2], (64..96).collect::<Vec<u8>>());
+
+        let keys = EncryptionDat::parse_master_keys(&(0..64).collect::<Vec<u8>>()).unwrap();
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_from_master_keys() {
+        let raw = master_keys().concat();
+        let enc_dat = EncryptionDat::from_master_keys(&raw).unwrap();
+        assert_eq!(enc_dat.master_keys, master_keys());
+
+        let content = b"encrypted with exported keys".to_vec();
+        let obj =
+            EncryptedObject::new(std::io::Cursor::new(encrypt(&content, &master_keys()))).unwrap();
+        obj.validate(&enc_dat.master_keys[1]).unwrap();
+        assert_eq!(obj.decrypt(&enc_dat.master_keys[0]).unwrap(), content);
+
+        assert!(matches!(
+            EncryptionDat::from_master_keys(&raw[..95]),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_master_keys_after_zeroizing() {
+        let enc_dat = EncryptionDat::generate("nor").unwrap();
+        let first = EncryptionDat::new(std::io::Cursor::new(&enc_dat[..]), "nor").unwrap();
+        let second = EncryptionDat::new(std::io::Cursor::new(&enc_dat[..]), "nor").unwrap();
+        assert_eq!(first.master_keys, second.master_keys);
+        assert!(first.master_keys.iter().all(|key| key.len() == 32));
+        assert!(first
+            .master_keys
+            .iter()
+            .any(|key| key.iter().any(|b| *b != 0)));
+
+        let keys = first.into_master_keys();
+        let mut encryptor = ObjectEncryptor::new(&keys);
+        let object = encryptor.encrypt(b"still decrypts").unwrap();
+        assert_eq!(
+            object.decrypt(&second.master_keys[0]).unwrap(),
+            b"still decrypts"
+        );
+
+        let raw = master_keys().concat();
+        let from_raw = EncryptionDat::from_master_keys(&raw).unwrap();
+        assert_eq!(*from_raw.into_master_keys(), master_keys());
+    }
+
+    #[test]
+    fn test_parse_master_keys_undersized() {
+        assert!(matches!(
+            EncryptionDat::parse_master_keys(&[0; 40]),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(
+            EncryptionDat::parse_master_keys(&[]),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_decode() {
+        let keys = master_keys();
+        let content = b"some content that was compressed and then encrypted".to_vec();
+        let encrypted = encrypt(&lz4::compress(&content).unwrap(), &keys);
+        let obj = EncryptedObject::new(std::io::Cursor::new(encrypted)).unwrap();
+
+        assert_eq!(obj.decode(&keys[0], CompressionType::LZ4).unwrap(), content);
+        assert_ne!(obj.decrypt(&keys[0]).unwrap(), content);
+    }
+
+    #[test]
+    fn test_decrypt_with_only_the_encryption_key() {
+        let keys = master_keys();
+        let encrypted = encrypt(b"recovered without the HMAC key", &keys);
+        let obj = EncryptedObject::new(std::io::Cursor::new(encrypted)).unwrap();
+        let encryption_key = keys.into_iter().next().unwrap();
+        assert_eq!(
+            obj.decrypt(&encryption_key).unwrap(),
+            b"recovered without the HMAC key"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_with_short_key() {
+        let keys = master_keys();
+        let obj = EncryptedObject::new(std::io::Cursor::new(encrypt(b"content", &keys))).unwrap();
+        assert!(matches!(
+            obj.decrypt(&keys[0][..16]),
+            Err(Error::InvalidKeyOrIvLength)
+        ));
+    }
+
+    #[test]
+    fn test_master_keys() {
+        let keys = MasterKeys::try_from(&EncryptionDat {
+            master_keys: master_keys(),
+        })
+        .unwrap();
+        assert_eq!(keys.encryption, [1; 32]);
+        assert_eq!(keys.hmac, [2; 32]);
+        assert_eq!(keys.salt, [3; 32]);
+        assert_eq!(keys.to_vec(), master_keys());
+        assert_eq!(format!("{:?}", keys), "MasterKeys { .. }");
+
+        let content = b"encrypted with typed keys".to_vec();
+        let obj =
+            EncryptedObject::new(std::io::Cursor::new(encrypt(&content, &master_keys()))).unwrap();
+        obj.validate_with(&keys).unwrap();
+        assert_eq!(obj.decrypt_with(&keys).unwrap(), content);
+        assert_eq!(
+            obj.decode_with(&keys, CompressionType::None).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn test_master_keys_without_salt() {
+        assert!(matches!(
+            MasterKeys::from_slices(&master_keys()[..2]),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(
+            MasterKeys::from_slices(&[vec![1; 32], vec![2; 32], vec![3; 16]]),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[cfg(aes_force_soft)]
+    #[test]
+    fn test_software_aes() {
+        let keys = master_keys();
+        let obj = EncryptedObject::new(std::io::Cursor::new(encrypt(b"soft", &keys))).unwrap();
+        obj.validate(&keys[1]).unwrap();
+        assert_eq!(obj.decrypt(&keys[0]).unwrap(), b"soft");
+    }
+
+    #[test]
+    fn test_verify_only() {
+        let keys = master_keys();
+        let encrypted = encrypt(b"verified without decrypting", &keys);
+        let obj = EncryptedObject::new(std::io::Cursor::new(&encrypted)).unwrap();
+        obj.verify_only(&keys[1]).unwrap();
+        assert!(matches!(
+            obj.verify_only(&keys[0]),
+            Err(Error::HmacMismatch)
+        ));
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let obj = EncryptedObject::new(std::io::Cursor::new(tampered)).unwrap();
+        assert!(matches!(
+            obj.verify_only(&keys[1]),
+            Err(Error::HmacMismatch)
+        ));
+        assert!(matches!(obj.validate(&keys[1]), Err(Error::HmacMismatch)));
+    }
+
     #[test]
     fn test_calculate_hmacsha256() {
         let secret = "secret".as_bytes();
@@ -338,6 +949,29 @@ mod tests {
         assert_eq!(result, calculate_hmacsha256(secret, message).unwrap());
     }
 
+    #[test]
+    fn test_content_address() {
+        let salt = [7u8; 32];
+        let address = content_address(b"some data", &salt);
+        assert_eq!(address.len(), 40);
+        assert_eq!(address, content_address(b"some data", &salt));
+        assert_ne!(address, content_address(b"some data", &[8u8; 32]));
+    }
+
+    #[test]
+    fn test_verify_dedup() {
+        let salt = [7u8; 32];
+        let address = content_address(b"some data", &salt);
+
+        assert!(verify_dedup(b"some data", &salt, &address).unwrap());
+        assert!(verify_dedup(b"some data", &salt, &address.to_uppercase()).unwrap());
+        assert!(!verify_dedup(b"other data", &salt, &address).unwrap());
+        assert!(matches!(
+            verify_dedup(b"some data", &salt, "not-a-sha1"),
+            Err(Error::ParseError)
+        ));
+    }
+
     #[test]
     fn test_calculate_sha1sum() {
         let message = "message".as_bytes();
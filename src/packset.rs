@@ -26,13 +26,19 @@
 //!
 //! `/<computer_uuid>/packsets/<folder_uuid>-(blobs|trees)/<sha1>.index`
 use byteorder::{NetworkEndian, ReadBytesExt};
+use sha1::{Digest, Sha1};
 use std;
-use std::io::{BufRead, Cursor, Seek, SeekFrom};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
 use crate::compression::CompressionType;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::object_encryption::{calculate_sha1sum, EncryptedObject};
-use crate::type_utils::ArqRead;
+use crate::resolver::BlobResolver;
+use crate::type_utils::{read_header, ArqRead, ArqWrite};
 use crate::utils::convert_to_hex_string;
 
 ///Pack File Format
@@ -202,18 +208,135 @@ pub struct PackIndex {
 ///              xx xx xx xx
 /// alignment    00 00 00 00 (4 bytes for alignment) - we don't include this one
 /// ```
+#[derive(Clone, PartialEq, Debug)]
 pub struct PackIndexObject {
     pub offset: usize,
     pub data_len: usize,
     pub sha1: String,
 }
 
+/// An object [Pack::new_lenient] couldn't parse.
+#[derive(Debug)]
+pub struct PackParseError {
+    /// Position of the object in the pack (or in the index, sorted by offset).
+    pub position: usize,
+    /// Offset in the pack the object starts at.
+    pub offset: u64,
+    /// The object's SHA1, known only when parsing with an index.
+    pub sha1: Option<String>,
+    pub error: Error,
+}
+
+/// A reader that hashes everything read through it.
+///
+/// Packs and pack indexes end with the SHA1 of everything before it. Parsing through a
+/// `Sha1Reader` and then calling [Sha1Reader::verify_trailer] checks that in the same
+/// forward pass, without seeking back or holding the whole file in memory.
+pub struct Sha1Reader<R> {
+    inner: R,
+    hasher: Sha1,
+}
+
+impl<R: Read> Sha1Reader<R> {
+    pub fn new(inner: R) -> Sha1Reader<R> {
+        Sha1Reader {
+            inner,
+            hasher: Sha1::new(),
+        }
+    }
+
+    /// The SHA1 of the bytes read so far.
+    pub fn digest(&self) -> [u8; 20] {
+        self.hasher.clone().finalize().into()
+    }
+
+    /// Reads the rest of the input and checks its last 20 bytes against the SHA1 of
+    /// everything before them, returning whatever lay between what had already been read
+    /// and those 20 bytes.
+    ///
+    /// Fails with `Error::ChecksumMismatch` if the SHA1 doesn't match, or with an
+    /// `ErrorKind::UnexpectedEof` I/O error if there aren't 20 bytes left.
+    pub fn verify_trailer(mut self) -> Result<Vec<u8>> {
+        let mut rest = Vec::new();
+        self.inner.read_to_end(&mut rest)?;
+        if rest.len() < 20 {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+        let sha1 = rest.split_off(rest.len() - 20);
+        self.hasher.update(&rest);
+        if self.digest()[..] != sha1[..] {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(rest)
+    }
+}
+
+impl<R: Read> Read for Sha1Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.hasher.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> BufRead for Sha1Reader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        // The buffer is still filled from the fill_buf call that preceded this one, so
+        // this doesn't read anything.
+        if let Ok(buffer) = self.inner.fill_buf() {
+            self.hasher.update(&buffer[..amount.min(buffer.len())]);
+        }
+        self.inner.consume(amount);
+    }
+}
+
+/// Reads the objects listed in the pack index at `path`.
+fn read_index(path: &Path) -> Result<Vec<PackIndexObject>> {
+    Ok(PackIndex::new(BufReader::new(File::open(path)?))?.objects)
+}
+
+/// The only pack and pack index version there is.
+const PACK_VERSION: u32 = 2;
+
+/// Decodes a 4-byte big endian version number.
+fn version_number(version: &[u8]) -> u32 {
+    version
+        .iter()
+        .fold(0, |number, byte| number << 8 | u32::from(*byte))
+}
+
+fn check_version(version: &[u8], while_reading: &'static str) -> Result<()> {
+    match version_number(version) {
+        PACK_VERSION => Ok(()),
+        version => Err(Error::UnsupportedVersion {
+            while_reading,
+            version,
+        }),
+    }
+}
+
 impl PackIndex {
-    pub fn new<R: BufRead + ArqRead + Seek>(mut reader: R) -> Result<PackIndex> {
+    /// The index's version (always 2).
+    pub fn version_number(&self) -> u32 {
+        version_number(&self.version)
+    }
+
+    /// Parses an index, checking it against its trailing SHA1 as it's read (see
+    /// [Sha1Reader]).
+    pub fn new<R: BufRead + ArqRead>(reader: R) -> Result<PackIndex> {
+        let mut reader = Sha1Reader::new(reader);
         let magic_number = reader.read_bytes(4)?;
-        assert_eq!(magic_number, [255, 116, 79, 99]); // ff 74 4f 63
+        // ff 74 4f 63
+        if magic_number != [255, 116, 79, 99] {
+            return Err(Error::ParseError);
+        }
 
         let version = reader.read_bytes(4)?;
+        check_version(&version, "pack index")?;
 
         let mut fanout = Vec::new();
         while fanout.len() < 256 {
@@ -231,20 +354,13 @@ impl PackIndex {
             object_count -= 1;
         }
 
+        // Anything between the objects and the trailing SHA1 is the Glacier archive info.
+        let glacier = reader.verify_trailer()?;
         let mut glacier_archive_id_present: bool = false;
         let mut glacier_archive_id: Vec<u8> = Vec::new();
         let mut glacier_pack_size = 0;
-
-        // TODO(nlopes): This is ugly. I don't have a current position due to using a
-        // "cursor"/reader. So what I do is I try to read 21 bytes. If I can, then I know
-        // I have more than just the sha1 of the content. If I can't, then I'm back where
-        // I was and I do nothing.
-        let mut _buf = vec![0; 21];
-        if reader.read_exact(&mut _buf).is_ok() {
-            // This is a easier condition than trying to read the bytes for glacier.  If all
-            // the bytes read + 20 (for the final sha1) account for the entire length of the
-            // content, we're at the end of data and don't need to read anything related to
-            // glacier.
+        if !glacier.is_empty() {
+            let mut reader = Cursor::new(glacier);
             let glacier_archive_id_flag = reader.read_bytes(1)?;
 
             if glacier_archive_id_flag[0] == 0x01 {
@@ -257,15 +373,6 @@ impl PackIndex {
             }
         }
 
-        let sha1_checksum_start = reader.seek(SeekFrom::End(0))? - 20;
-        let mut content = vec![0; sha1_checksum_start as usize];
-
-        reader.seek(SeekFrom::Start(0))?;
-        reader.read_exact(&mut content)?;
-
-        let sha1 = reader.read_bytes(20)?;
-        assert_eq!(calculate_sha1sum(&content), sha1);
-
         Ok(PackIndex {
             version: version.to_vec(),
             fanout,
@@ -275,38 +382,158 @@ impl PackIndex {
             glacier_pack_size: glacier_pack_size as usize,
         })
     }
+
+    /// Builds the 256-entry fanout table for a set of object SHA1s.
+    ///
+    /// Entry `i` is the number of SHA1s whose first byte is less than or equal to `i`
+    /// (network-byte-order), so the last entry is the total object count.
+    pub fn build_fanout(sha1s: &[[u8; 20]]) -> Vec<[u8; 4]> {
+        let mut counts = [0u32; 256];
+        for sha1 in sha1s {
+            counts[sha1[0] as usize] += 1;
+        }
+
+        let mut total = 0;
+        counts
+            .iter()
+            .map(|count| {
+                total += count;
+                total.to_be_bytes()
+            })
+            .collect()
+    }
+
+    /// Index entries sorted by their offset in the pack.
+    ///
+    /// `objects` is in SHA1 order, which means reading them in that order jumps back and
+    /// forth through the pack. This returns them in the order they're physically laid out.
+    pub fn objects_by_offset(&self) -> Vec<&PackIndexObject> {
+        let mut objects: Vec<&PackIndexObject> = self.objects.iter().collect();
+        objects.sort_by_key(|object| object.offset);
+        objects
+    }
 }
 
 impl Pack {
-    pub fn new<R: ArqRead + BufRead + Seek>(mut reader: R) -> Result<Pack> {
-        let signature = reader.read_bytes(4)?;
-        assert_eq!(signature, [80, 65, 67, 75]);
-        let version = reader.read_bytes(4)?;
-        let mut object_count = reader.read_u64::<NetworkEndian>()? as usize;
+    /// The pack's version (always 2).
+    pub fn version_number(&self) -> u32 {
+        version_number(&self.version)
+    }
+
+    /// Parses a pack, checking it against its trailing SHA1 as it's read (see
+    /// [Sha1Reader]).
+    pub fn new<R: ArqRead + BufRead>(reader: R) -> Result<Pack> {
+        let mut reader = Sha1Reader::new(reader);
+        let (version, object_count) = Pack::read_header(&mut reader)?;
         let mut objects: Vec<PackObject> = Vec::new();
-        while object_count > 0 {
+        for _ in 0..object_count {
             objects.push(PackObject::new(&mut reader)?);
-            object_count -= 1;
         }
+        reader.verify_trailer()?;
 
-        let sha1_checksum_start = reader.seek(SeekFrom::End(0))? - 20;
-        let mut content = vec![0; sha1_checksum_start as usize];
+        Ok(Pack { version, objects })
+    }
 
-        reader.seek(SeekFrom::Start(0))?;
-        reader.read_exact(&mut content)?;
+    /// Reads the first `object_count` objects of a pack whose trailing SHA1 isn't
+    /// available, e.g. when it's streamed or fetched with a range request.
+    ///
+    /// Nothing past those objects is read, so the pack's checksum isn't verified.
+    /// `object_count` can't be more than the count in the pack's header.
+    pub fn new_without_trailer<R: ArqRead + BufRead + Seek>(
+        mut reader: R,
+        object_count: usize,
+    ) -> Result<Pack> {
+        let (version, count) = Pack::read_header(&mut reader)?;
+        if object_count > count {
+            return Err(Error::ParseError);
+        }
+        let mut objects: Vec<PackObject> = Vec::new();
+        for _ in 0..object_count {
+            objects.push(PackObject::new(&mut reader)?);
+        }
+        Ok(Pack { version, objects })
+    }
 
-        let sha1 = reader.read_bytes(20)?;
-        assert_eq!(calculate_sha1sum(&content), sha1);
+    /// Reads as many objects of a damaged pack as possible, returning them along with the
+    /// ones that failed to parse.
+    ///
+    /// With the pack's `index`, each object is read from the offset the index gives for
+    /// it, so a corrupt object doesn't affect any other. Without it, objects are read one
+    /// after the other: a corrupt object is skipped over using its data length, but if
+    /// the length itself can't be read the rest of the pack can't be located and parsing
+    /// stops there.
+    ///
+    /// The pack's checksum isn't verified. Only an unreadable header is an error.
+    pub fn new_lenient<R: ArqRead + BufRead + Seek>(
+        mut reader: R,
+        index: Option<&PackIndex>,
+    ) -> Result<(Pack, Vec<PackParseError>)> {
+        let (version, object_count) = Pack::read_header(&mut reader)?;
+        let mut objects: Vec<PackObject> = Vec::new();
+        let mut failures: Vec<PackParseError> = Vec::new();
 
-        Ok(Pack {
-            version: version.to_vec(),
-            objects,
-        })
+        match index {
+            Some(index) => {
+                for (position, entry) in index.objects_by_offset().into_iter().enumerate() {
+                    let offset = entry.offset as u64;
+                    let result = reader
+                        .seek(SeekFrom::Start(offset))
+                        .map_err(Error::from)
+                        .and_then(|_| PackObject::new(&mut reader));
+                    match result {
+                        Ok(object) => objects.push(object),
+                        Err(error) => failures.push(PackParseError {
+                            position,
+                            offset,
+                            sha1: Some(entry.sha1.clone()),
+                            error,
+                        }),
+                    }
+                }
+            }
+            None => {
+                for position in 0..object_count {
+                    let offset = reader.stream_position()?;
+                    let (result, framed) = match PackObject::read_framing(&mut reader) {
+                        Ok((mimetype, name, data)) => {
+                            (PackObject::from_framing(mimetype, name, data), true)
+                        }
+                        Err(error) => (Err(error), false),
+                    };
+                    match result {
+                        Ok(object) => objects.push(object),
+                        Err(error) => failures.push(PackParseError {
+                            position,
+                            offset,
+                            sha1: None,
+                            error,
+                        }),
+                    }
+                    if !framed {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok((Pack { version, objects }, failures))
+    }
+
+    /// Reads the signature, version and object count.
+    fn read_header<R: ArqRead + BufRead>(reader: &mut R) -> Result<(Vec<u8>, usize)> {
+        let signature = read_header(reader, 4, "pack")?;
+        if signature != [80, 65, 67, 75] {
+            return Err(Error::ParseError);
+        }
+        let version = reader.read_bytes(4)?;
+        check_version(&version, "pack")?;
+        let object_count = reader.read_u64::<NetworkEndian>()? as usize;
+        Ok((version, object_count))
     }
 }
 
 impl PackIndexObject {
-    pub fn new<R: ArqRead + BufRead + Seek>(mut reader: R) -> Result<Self> {
+    pub fn new<R: ArqRead + BufRead>(mut reader: R) -> Result<Self> {
         let offset = reader.read_u64::<NetworkEndian>()?;
         let data_len = reader.read_u64::<NetworkEndian>()?;
         let sha1 = reader.read_bytes(20)?;
@@ -321,7 +548,13 @@ impl PackIndexObject {
 }
 
 impl PackObject {
-    pub fn new<R: ArqRead + BufRead + Seek>(mut reader: R) -> Result<PackObject> {
+    pub fn new<R: ArqRead + BufRead>(mut reader: R) -> Result<PackObject> {
+        let (mimetype, name, data) = PackObject::read_framing(&mut reader)?;
+        PackObject::from_framing(mimetype, name, data)
+    }
+
+    /// Reads the mimetype, name and raw data, leaving the reader at the next object.
+    fn read_framing<R: ArqRead + BufRead>(reader: &mut R) -> Result<(String, String, Vec<u8>)> {
         // If mimetype present
         let mimetype = if reader.read_arq_bool()? {
             reader.read_arq_string()?
@@ -336,23 +569,825 @@ impl PackObject {
             String::new()
         };
 
-        let data = reader.read_arq_data()?;
-        let mut data_reader = Cursor::new(data);
+        let data_len = reader.read_arq_u64()?;
+        let mut data = Vec::new();
+        reader.by_ref().take(data_len).read_to_end(&mut data)?;
+        if (data.len() as u64) < data_len {
+            return Err(Error::TruncatedPack {
+                expected: data_len,
+                available: data.len(),
+            });
+        }
+        Ok((mimetype, name, data))
+    }
 
+    fn from_framing(mimetype: String, name: String, data: Vec<u8>) -> Result<PackObject> {
         Ok(PackObject {
             mimetype,
             name,
-            data: EncryptedObject::new(&mut data_reader)?,
+            data: EncryptedObject::new(Cursor::new(data))?,
         })
     }
 
+    /// The object's name, if it's a SHA1 (40 hex digits) as is usual in blob packsets, for
+    /// matching the object up with its [PackIndexObject].
+    pub fn sha1(&self) -> Option<&str> {
+        let is_sha1 = self.name.len() == 40 && self.name.chars().all(|c| c.is_ascii_hexdigit());
+        is_sha1.then_some(self.name.as_str())
+    }
+
+    /// Size of the object's ciphertext, known without decrypting it.
+    pub fn ciphertext_len(&self) -> usize {
+        self.data.ciphertext_len()
+    }
+
     pub fn original(
         &self,
         compression_type: CompressionType,
         master_key: &[u8],
     ) -> Result<Vec<u8>> {
-        let decrypted = self.data.decrypt(master_key)?;
-        let content = CompressionType::decompress(&decrypted, compression_type)?;
-        Ok(content)
+        self.data.decode(master_key, compression_type)
+    }
+}
+
+/// PackSet
+/// -------
+///
+/// All the packs in a `<folder_uuid>-(blobs|trees)` directory. Opening a packset reads
+/// every `<sha1>.index` in it, so that objects can then be found by SHA1 without having
+/// to scan the packs themselves.
+///
+/// Objects too large to be packed are stored on their own as
+/// `/<computer_uuid>/objects/<sha1>`; see [PackSet::with_objects_dir].
+pub struct PackSet {
+    master_keys: Zeroizing<Vec<Vec<u8>>>,
+    objects: HashMap<String, PackLocation>,
+    objects_dir: Option<PathBuf>,
+}
+
+/// Where an object is stored within a packset.
+pub struct PackLocation {
+    pub pack_path: PathBuf,
+    pub offset: usize,
+    pub data_len: usize,
+}
+
+impl PackSet {
+    /// Opens the packset stored at `path`. The master keys are used to decrypt objects
+    /// when resolving them: without at least 2 of them, the packset's indexes can still be
+    /// read but resolving fails with `Error::InvalidKeyOrIvLength`.
+    pub fn open<P: AsRef<Path>>(path: P, master_keys: &[Vec<u8>]) -> Result<PackSet> {
+        PackSet::open_with(path, master_keys, read_index)
+    }
+
+    /// Same as [PackSet::open], looking pack indexes up in `cache` before parsing them.
+    ///
+    /// Indexes missing from the cache are parsed as usual and then added to it, so only
+    /// packs written since the last time the packset was opened get parsed.
+    #[cfg(feature = "rusqlite")]
+    pub fn open_with_cache<P: AsRef<Path>>(
+        path: P,
+        master_keys: &[Vec<u8>],
+        cache: &crate::index_cache::IndexCache,
+    ) -> Result<PackSet> {
+        PackSet::open_with(path, master_keys, |index_path| {
+            cache.get_or_insert_with(index_path, read_index)
+        })
+    }
+
+    /// Opens the packset at `path`, getting the objects in each `<sha1>.index` from
+    /// `objects_in`.
+    fn open_with<P, F>(path: P, master_keys: &[Vec<u8>], mut objects_in: F) -> Result<PackSet>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&Path) -> Result<Vec<PackIndexObject>>,
+    {
+        let mut objects = HashMap::new();
+        for entry in std::fs::read_dir(path)? {
+            let index_path = entry?.path();
+            if index_path.extension() != Some("index".as_ref()) {
+                continue;
+            }
+
+            let pack_path = index_path.with_extension("pack");
+            for object in objects_in(&index_path)? {
+                objects.insert(
+                    object.sha1,
+                    PackLocation {
+                        pack_path: pack_path.clone(),
+                        offset: object.offset,
+                        data_len: object.data_len,
+                    },
+                );
+            }
+        }
+
+        Ok(PackSet {
+            master_keys: Zeroizing::new(master_keys.to_vec()),
+            objects,
+            objects_dir: None,
+        })
+    }
+
+    /// Resolves objects that aren't in any pack from the standalone objects in `path`
+    /// (`/<computer_uuid>/objects`), where Arq stores the ones too large to be packed.
+    pub fn with_objects_dir<P: AsRef<Path>>(mut self, path: P) -> PackSet {
+        self.objects_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Opens the local copy of a folder's trees packset.
+    ///
+    /// Arq caches trees and commits locally so that browsing backups doesn't have to go
+    /// to the destination. The cache keeps the same pack layout under
+    /// `<cache_root>/treepacks/<folder_uuid>-trees`.
+    pub fn open_tree_cache<P: AsRef<Path>>(
+        cache_root: P,
+        folder_uuid: &str,
+        master_keys: &[Vec<u8>],
+    ) -> Result<PackSet> {
+        PackSet::open(
+            cache_root
+                .as_ref()
+                .join("treepacks")
+                .join(format!("{folder_uuid}-trees")),
+            master_keys,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn contains(&self, sha1: &str) -> bool {
+        self.objects.contains_key(sha1)
+    }
+
+    pub fn location(&self, sha1: &str) -> Option<&PackLocation> {
+        self.objects.get(sha1)
+    }
+
+    /// SHA1s of the objects in this packset that aren't in `reachable`, sorted.
+    ///
+    /// `reachable` is everything the folder's commits still reference, as collected by
+    /// walking them and their trees, so these are the objects that could be pruned.
+    pub fn find_orphans(&self, reachable: &HashSet<String>) -> Vec<String> {
+        let mut orphans: Vec<String> = self
+            .objects
+            .keys()
+            .filter(|sha1| !reachable.contains(*sha1))
+            .cloned()
+            .collect();
+        orphans.sort();
+        orphans
+    }
+
+    /// Reads the (still encrypted) object `sha1` from the pack holding it.
+    pub fn object(&self, sha1: &str) -> Result<PackObject> {
+        let location = self
+            .location(sha1)
+            .ok_or_else(|| Error::ObjectNotFound(sha1.to_string()))?;
+        let mut reader = BufReader::new(File::open(&location.pack_path)?);
+        reader.seek(SeekFrom::Start(location.offset as u64))?;
+        PackObject::new(&mut reader)
+    }
+}
+
+impl BlobResolver for PackSet {
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>> {
+        let data = match (self.location(sha1), &self.objects_dir) {
+            (None, Some(objects_dir)) => match File::open(objects_dir.join(sha1)) {
+                Ok(file) => EncryptedObject::new(BufReader::new(file))?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(Error::ObjectNotFound(sha1.to_string()))
+                }
+                Err(err) => return Err(err.into()),
+            },
+            _ => self.object(sha1)?.data,
+        };
+        if self.master_keys.len() < 2 {
+            return Err(Error::InvalidKeyOrIvLength);
+        }
+        data.validate(&self.master_keys[1])?;
+        data.decrypt(&self.master_keys[0])
+    }
+}
+
+/// PackWriter
+/// ----------
+///
+/// Combines objects into packs the way Arq does: objects are accumulated until the pack
+/// reaches a size threshold (10MB by default), at which point the pack is written to the
+/// packset directory as `<sha1>.pack` along with its `<sha1>.index`. Call
+/// [PackWriter::finish] to write out whatever is left once all objects have been added.
+pub struct PackWriter {
+    dir: PathBuf,
+    threshold: usize,
+    body: Vec<u8>,
+    entries: Vec<([u8; 20], u64, u64)>,
+    written: Vec<String>,
+}
+
+/// Size at which Arq stores a pack at the destination.
+pub const DEFAULT_PACK_SIZE_THRESHOLD: usize = 10 * 1024 * 1024;
+
+// signature + version + object count
+const PACK_HEADER_LEN: usize = 16;
+
+impl PackWriter {
+    /// Creates a writer storing packs in `dir` (a `<folder_uuid>-(blobs|trees)` directory).
+    pub fn new<P: AsRef<Path>>(dir: P) -> PackWriter {
+        PackWriter::with_threshold(dir, DEFAULT_PACK_SIZE_THRESHOLD)
+    }
+
+    /// Same as [PackWriter::new], but packs are written once they reach `threshold` bytes.
+    pub fn with_threshold<P: AsRef<Path>>(dir: P, threshold: usize) -> PackWriter {
+        PackWriter {
+            dir: dir.as_ref().to_path_buf(),
+            threshold,
+            body: Vec::new(),
+            entries: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Adds an object to the current pack.
+    ///
+    /// `name` is the object's SHA1 (in hex), which is what the index is keyed by.
+    /// `mimetype` is usually empty. If adding the object makes the pack cross the size
+    /// threshold, the pack is written out and its SHA1 returned.
+    ///
+    /// Encrypt objects with a [crate::object_encryption::ObjectEncryptor] to rotate
+    /// session keys the way Arq does.
+    pub fn add_object(
+        &mut self,
+        name: &str,
+        mimetype: &str,
+        object: &EncryptedObject,
+    ) -> Result<Option<String>> {
+        let sha1 = parse_sha1(name)?;
+        let data = object.to_bytes();
+
+        let offset = (PACK_HEADER_LEN + self.body.len()) as u64;
+        // Each string is preceded by a "present" flag, as PackObject::new reads it.
+        for value in [mimetype, name] {
+            self.body.write_arq_bool(!value.is_empty())?;
+            if !value.is_empty() {
+                self.body.write_arq_string(value)?;
+            }
+        }
+        self.body.write_arq_data(&data)?;
+        self.entries.push((sha1, offset, data.len() as u64));
+
+        if PACK_HEADER_LEN + self.body.len() >= self.threshold {
+            return self.flush().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Writes out the current pack, if it has any objects, and returns the SHA1s of every
+    /// pack written by this writer.
+    pub fn finish(mut self) -> Result<Vec<String>> {
+        if !self.entries.is_empty() {
+            self.flush()?;
+        }
+        Ok(self.written)
+    }
+
+    fn flush(&mut self) -> Result<String> {
+        let mut pack = Vec::with_capacity(PACK_HEADER_LEN + self.body.len() + 20);
+        pack.extend_from_slice(b"PACK");
+        pack.write_arq_u32(2)?;
+        pack.write_arq_u64(self.entries.len() as u64)?;
+        pack.append(&mut self.body);
+        let pack_sha1 = calculate_sha1sum(&pack);
+        pack.extend_from_slice(&pack_sha1);
+
+        self.entries.sort();
+        let sha1s: Vec<[u8; 20]> = self.entries.iter().map(|entry| entry.0).collect();
+        let mut index = vec![255, 116, 79, 99]; // ff 74 4f 63
+        index.write_arq_u32(2)?;
+        for count in PackIndex::build_fanout(&sha1s) {
+            index.extend_from_slice(&count);
+        }
+        for (sha1, offset, data_len) in self.entries.drain(..) {
+            index.write_arq_u64(offset)?;
+            index.write_arq_u64(data_len)?;
+            index.extend_from_slice(&sha1);
+            index.extend_from_slice(&[0; 4]);
+        }
+        let index_sha1 = calculate_sha1sum(&index);
+        index.extend_from_slice(&index_sha1);
+
+        std::fs::create_dir_all(&self.dir)?;
+        let name = convert_to_hex_string(&pack_sha1);
+        std::fs::write(self.dir.join(format!("{name}.pack")), pack)?;
+        std::fs::write(self.dir.join(format!("{name}.index")), index)?;
+        self.written.push(name.clone());
+        Ok(name)
+    }
+}
+
+fn parse_sha1(sha1: &str) -> Result<[u8; 20]> {
+    if sha1.len() != 40 || !sha1.is_ascii() {
+        return Err(Error::ParseError);
+    }
+    let mut bytes = [0u8; 20];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&sha1[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_encryption::content_address;
+    use crate::test_utils::{encrypt, index_bytes, master_keys, pack_bytes, write_pack};
+
+    fn index_object(offset: usize, sha1: &str) -> PackIndexObject {
+        PackIndexObject {
+            offset,
+            data_len: 10,
+            sha1: sha1.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_objects_by_offset() {
+        let index = PackIndex {
+            version: vec![0, 0, 0, 2],
+            fanout: Vec::new(),
+            objects: vec![
+                index_object(300, "00aa"),
+                index_object(16, "10bb"),
+                index_object(150, "ffcc"),
+            ],
+            glacier_archive_id_present: false,
+            glacier_archive_id: Vec::new(),
+            glacier_pack_size: 0,
+        };
+
+        let offsets: Vec<usize> = index
+            .objects_by_offset()
+            .iter()
+            .map(|object| object.offset)
+            .collect();
+        assert_eq!(offsets, vec![16, 150, 300]);
+        assert_eq!(index.objects[0].sha1, "00aa");
+    }
+
+    #[test]
+    fn test_build_fanout() {
+        let mut sha1s = [[0u8; 20]; 5];
+        sha1s[0][0] = 0x00;
+        sha1s[1][0] = 0x00;
+        sha1s[2][0] = 0x10;
+        sha1s[3][0] = 0x7f;
+        sha1s[4][0] = 0xff;
+
+        let fanout = PackIndex::build_fanout(&sha1s);
+        assert_eq!(fanout.len(), 256);
+        assert_eq!(fanout[0x00], [0, 0, 0, 2]);
+        assert_eq!(fanout[0x0f], [0, 0, 0, 2]);
+        assert_eq!(fanout[0x10], [0, 0, 0, 3]);
+        assert_eq!(fanout[0x7e], [0, 0, 0, 3]);
+        assert_eq!(fanout[0x7f], [0, 0, 0, 4]);
+        assert_eq!(fanout[0xfe], [0, 0, 0, 4]);
+        assert_eq!(fanout[0xff], [0, 0, 0, 5]);
+        assert_eq!(PackIndex::build_fanout(&[])[255], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_packset_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = master_keys();
+        let first = write_pack(dir.path(), &[b"first object", b"second object"], &keys);
+        let second = write_pack(dir.path(), &[b"third object"], &keys);
+
+        let packset = PackSet::open(dir.path(), &keys).unwrap();
+        assert_eq!(packset.len(), 3);
+        assert!(packset.contains(&second[0]));
+        assert_eq!(packset.resolve(&first[1]).unwrap(), b"second object");
+        assert_eq!(packset.resolve(&second[0]).unwrap(), b"third object");
+        assert!(matches!(
+            packset.resolve("0000000000000000000000000000000000000000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+
+        // Opened only to read its indexes.
+        let indexes_only = PackSet::open(dir.path(), &[]).unwrap();
+        assert!(indexes_only.contains(&first[0]));
+        assert!(matches!(
+            indexes_only.resolve(&first[0]),
+            Err(Error::InvalidKeyOrIvLength)
+        ));
+    }
+
+    #[test]
+    fn test_packset_resolve_standalone_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = master_keys();
+        let packed = write_pack(&dir.path().join("packs"), &[b"packed object"], &keys);
+
+        let objects_dir = dir.path().join("objects");
+        std::fs::create_dir(&objects_dir).unwrap();
+        let large = vec![7; 4096];
+        let loose = content_address(&large, &keys[2]);
+        std::fs::write(objects_dir.join(&loose), encrypt(&large, &keys)).unwrap();
+
+        let packset = PackSet::open(dir.path().join("packs"), &keys).unwrap();
+        assert!(matches!(
+            packset.resolve(&loose),
+            Err(Error::ObjectNotFound(_))
+        ));
+
+        let packset = packset.with_objects_dir(&objects_dir);
+        assert!(!packset.contains(&loose));
+        assert_eq!(packset.resolve(&loose).unwrap(), large);
+        assert_eq!(packset.resolve(&packed[0]).unwrap(), b"packed object");
+        assert!(matches!(
+            packset.resolve("0000000000000000000000000000000000000000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = master_keys();
+        let sha1s = write_pack(dir.path(), &[b"kept", b"also kept", b"orphan"], &keys);
+        let packset = PackSet::open(dir.path(), &keys).unwrap();
+
+        let mut reachable: HashSet<String> = sha1s[..2].iter().cloned().collect();
+        reachable.insert("0000000000000000000000000000000000000000".to_string());
+        assert_eq!(packset.find_orphans(&reachable), vec![sha1s[2].clone()]);
+
+        reachable.insert(sha1s[2].clone());
+        assert!(packset.find_orphans(&reachable).is_empty());
+    }
+
+    #[test]
+    fn test_pack_writer_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = master_keys();
+        // Each object takes 190 bytes in the pack, so packs are written every
+        // third object.
+        let mut writer = PackWriter::with_threshold(dir.path(), 500);
+
+        let mut sha1s = Vec::new();
+        let mut flushed = Vec::new();
+        for i in 0..7 {
+            let plaintext = format!("object number {i}").into_bytes();
+            let sha1 = content_address(&plaintext, &keys[2]);
+            let object = EncryptedObject::new(Cursor::new(encrypt(&plaintext, &keys))).unwrap();
+            flushed.push(writer.add_object(&sha1, "", &object).unwrap().is_some());
+            sha1s.push(sha1);
+        }
+        assert_eq!(flushed, [false, false, true, false, false, true, false]);
+
+        let packs = writer.finish().unwrap();
+        assert_eq!(packs.len(), 3);
+
+        let first = Pack::new(BufReader::new(
+            File::open(dir.path().join(format!("{}.pack", packs[0]))).unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(first.objects.len(), 3);
+        assert_eq!(first.objects[0].name, sha1s[0]);
+
+        let packset = PackSet::open(dir.path(), &keys).unwrap();
+        assert_eq!(packset.len(), 7);
+        for (i, sha1) in sha1s.iter().enumerate() {
+            assert_eq!(
+                packset.resolve(sha1).unwrap(),
+                format!("object number {i}").into_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_pack_writer_rejects_bad_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = master_keys();
+        let object = EncryptedObject::new(Cursor::new(encrypt(b"data", &keys))).unwrap();
+        let mut writer = PackWriter::new(dir.path());
+        assert!(matches!(
+            writer.add_object("not a sha1", "", &object),
+            Err(Error::ParseError)
+        ));
+        assert!(writer.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ciphertext_len() {
+        let keys = master_keys();
+        let (pack, _) = pack_bytes(&[
+            encrypt(&[7; 15], &keys),
+            encrypt(&[7; 16], &keys),
+            encrypt(&[7; 100], &keys),
+        ]);
+        let pack = Pack::new(Cursor::new(pack)).unwrap();
+
+        let lens: Vec<usize> = pack
+            .objects
+            .iter()
+            .map(PackObject::ciphertext_len)
+            .collect();
+        assert_eq!(lens, vec![16, 32, 112]);
+        assert_eq!(
+            pack.objects[2]
+                .original(CompressionType::None, &keys[0])
+                .unwrap(),
+            vec![7; 100]
+        );
+    }
+
+    #[test]
+    fn test_sha1_reader() {
+        let keys = master_keys();
+        let (pack, _) = pack_bytes(&[encrypt(b"first", &keys), encrypt(b"second", &keys)]);
+        let content_len = pack.len() - 20;
+
+        // A small buffer, so objects straddle refills of it.
+        let mut reader = Sha1Reader::new(BufReader::with_capacity(7, Cursor::new(&pack)));
+        let (_, object_count) = Pack::read_header(&mut reader).unwrap();
+        for _ in 0..object_count {
+            PackObject::new(&mut reader).unwrap();
+        }
+        // Same as hashing the whole pack but its trailer in one go.
+        assert_eq!(
+            reader.digest().to_vec(),
+            calculate_sha1sum(&pack[..content_len])
+        );
+        assert!(reader.verify_trailer().unwrap().is_empty());
+
+        let mut corrupted = pack.clone();
+        corrupted[content_len - 1] ^= 0xff;
+        assert!(matches!(
+            Pack::new(Cursor::new(&corrupted)),
+            Err(Error::ChecksumMismatch)
+        ));
+        assert!(Pack::new(Cursor::new(&pack[..content_len + 10])).is_err());
+    }
+
+    #[test]
+    fn test_pack_index_glacier_info() {
+        let mut index = index_bytes(&[("ab".repeat(20), 12, 10)]);
+        index.truncate(index.len() - 20);
+        index.push(1);
+        index.extend_from_slice(&7u64.to_be_bytes());
+        index.extend_from_slice(b"archive");
+        index.extend_from_slice(&4096u64.to_be_bytes());
+        let sha1 = calculate_sha1sum(&index);
+        index.extend_from_slice(&sha1);
+
+        let index = PackIndex::new(Cursor::new(index)).unwrap();
+        assert_eq!(index.objects.len(), 1);
+        assert!(index.glacier_archive_id_present);
+        assert_eq!(index.glacier_archive_id, b"archive");
+        assert_eq!(index.glacier_pack_size, 4096);
+    }
+
+    #[test]
+    fn test_pack_object_sha1() {
+        let keys = master_keys();
+        let data = || EncryptedObject::new(Cursor::new(encrypt(b"contents", &keys))).unwrap();
+        let sha1 = content_address(b"contents", &keys[2]);
+
+        let named = PackObject {
+            mimetype: String::new(),
+            name: sha1.clone(),
+            data: data(),
+        };
+        assert_eq!(named.sha1(), Some(sha1.as_str()));
+
+        let unnamed = PackObject {
+            mimetype: "application/octet-stream".to_string(),
+            name: String::new(),
+            data: data(),
+        };
+        assert_eq!(unnamed.sha1(), None);
+
+        let not_hex = PackObject {
+            mimetype: String::new(),
+            name: "z".repeat(40),
+            data: data(),
+        };
+        assert_eq!(not_hex.sha1(), None);
+    }
+
+    #[test]
+    fn test_wrong_signatures() {
+        let keys = master_keys();
+        let (mut pack, _) = pack_bytes(&[encrypt(b"contents", &keys)]);
+        pack[0] = b'X';
+        assert!(matches!(
+            Pack::new(Cursor::new(pack)),
+            Err(Error::ParseError)
+        ));
+
+        let mut index = index_bytes(&[("ab".repeat(20), 12, 10)]);
+        index[0] = 0;
+        assert!(matches!(
+            PackIndex::new(Cursor::new(index)),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_empty_pack() {
+        assert!(matches!(
+            Pack::new(Cursor::new(Vec::new())),
+            Err(Error::UnexpectedEof {
+                while_reading: "pack"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_pack_object_with_mimetype_and_name() {
+        let keys = master_keys();
+        let object = encrypt(b"contents", &keys);
+        let sha1 = content_address(b"contents", &keys[2]);
+
+        // Laid out by hand: a "present" flag, then the string with its own "not null" byte,
+        // length and bytes.
+        let mut raw = vec![0x01, 0x01];
+        raw.extend_from_slice(&10u64.to_be_bytes());
+        raw.extend_from_slice(b"text/plain");
+        raw.extend_from_slice(&[0x01, 0x01]);
+        raw.extend_from_slice(&40u64.to_be_bytes());
+        raw.extend_from_slice(sha1.as_bytes());
+        raw.extend_from_slice(&(object.len() as u64).to_be_bytes());
+        raw.extend_from_slice(&object);
+
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&2u64.to_be_bytes());
+        pack.extend_from_slice(&raw);
+        pack.extend_from_slice(&raw);
+        let pack_sha1 = calculate_sha1sum(&pack);
+        pack.extend_from_slice(&pack_sha1);
+
+        let pack = Pack::new(Cursor::new(pack)).unwrap();
+        assert_eq!(pack.objects.len(), 2);
+        for object in &pack.objects {
+            assert_eq!(object.mimetype, "text/plain");
+            assert_eq!(object.name, sha1);
+            assert_eq!(object.data.decrypt(&keys[0]).unwrap(), b"contents");
+        }
+    }
+
+    #[test]
+    fn test_truncated_pack() {
+        let keys = master_keys();
+        let object = encrypt(b"some object", &keys);
+        let (pack, locations) = pack_bytes(&[object.clone(), object]);
+
+        // Cut the second object's data 10 bytes short (plus the trailing SHA1)
+        let (offset, data_len) = locations[1];
+        let data_start = offset as usize + 2 + 8;
+        let truncated = &pack[..data_start + data_len as usize - 10];
+
+        match Pack::new(Cursor::new(truncated)) {
+            Err(Error::TruncatedPack {
+                expected,
+                available,
+            }) => {
+                assert_eq!(expected, data_len);
+                assert_eq!(available as u64, data_len - 10);
+            }
+            Err(err) => panic!("expected Error::TruncatedPack, got {err:?}"),
+            Ok(_) => panic!("expected Error::TruncatedPack"),
+        }
+    }
+
+    #[test]
+    fn test_pack_without_trailer() {
+        let keys = master_keys();
+        let objects = vec![encrypt(b"first", &keys), encrypt(b"second", &keys)];
+        let (pack, locations) = pack_bytes(&objects);
+        let full = Pack::new(Cursor::new(&pack)).unwrap();
+
+        let original = |pack: &Pack| -> Vec<Vec<u8>> {
+            pack.objects
+                .iter()
+                .map(|object| object.original(CompressionType::None, &keys[0]).unwrap())
+                .collect()
+        };
+
+        let body = &pack[..pack.len() - 20];
+        let streamed = Pack::new_without_trailer(Cursor::new(body), 2).unwrap();
+        assert_eq!(streamed.version, full.version);
+        assert_eq!(original(&streamed), original(&full));
+
+        // Only the first object was fetched.
+        let (offset, data_len) = locations[0];
+        let partial = &pack[..offset as usize + 2 + 8 + data_len as usize];
+        let partial = Pack::new_without_trailer(Cursor::new(partial), 1).unwrap();
+        assert_eq!(original(&partial), vec![b"first".to_vec()]);
+
+        assert!(matches!(
+            Pack::new_without_trailer(Cursor::new(body), 3),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_pack_new_lenient() {
+        let keys = master_keys();
+        let objects = vec![
+            encrypt(b"first", &keys),
+            encrypt(b"second", &keys),
+            encrypt(b"third", &keys),
+        ];
+        let (pack, locations) = pack_bytes(&objects);
+        let original = |pack: &Pack| -> Vec<Vec<u8>> {
+            pack.objects
+                .iter()
+                .map(|object| object.original(CompressionType::None, &keys[0]).unwrap())
+                .collect()
+        };
+
+        // Break the second object's "ARQO" header: its data can still be skipped over.
+        let offset = locations[1].0;
+        let mut corrupt = pack.clone();
+        corrupt[offset as usize + 2 + 8] = b'X';
+        let (parsed, failures) = Pack::new_lenient(Cursor::new(&corrupt), None).unwrap();
+        assert_eq!(
+            original(&parsed),
+            vec![b"first".to_vec(), b"third".to_vec()]
+        );
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].position, 1);
+        assert_eq!(failures[0].offset, offset);
+        assert_eq!(failures[0].sha1, None);
+        assert!(matches!(failures[0].error, Error::ParseError));
+
+        // Break its data length instead: only the index can locate the third object.
+        let mut corrupt = pack.clone();
+        let len_start = offset as usize + 2;
+        corrupt[len_start..len_start + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        let (parsed, failures) = Pack::new_lenient(Cursor::new(&corrupt), None).unwrap();
+        assert_eq!(original(&parsed), vec![b"first".to_vec()]);
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0].error, Error::TruncatedPack { .. }));
+
+        let sha1s = ["aa", "bb", "cc"].map(|prefix| prefix.repeat(20));
+        let entries: Vec<(String, u64, u64)> = sha1s
+            .iter()
+            .zip(&locations)
+            .map(|(sha1, (offset, len))| (sha1.clone(), *offset, *len))
+            .collect();
+        let index = PackIndex::new(Cursor::new(index_bytes(&entries))).unwrap();
+        let (parsed, failures) = Pack::new_lenient(Cursor::new(&corrupt), Some(&index)).unwrap();
+        assert_eq!(
+            original(&parsed),
+            vec![b"first".to_vec(), b"third".to_vec()]
+        );
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].position, 1);
+        assert_eq!(failures[0].sha1.as_deref(), Some(sha1s[1].as_str()));
+
+        let (parsed, failures) = Pack::new_lenient(Cursor::new(&pack), Some(&index)).unwrap();
+        assert_eq!(parsed.objects.len(), 3);
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn test_version_number() {
+        let keys = master_keys();
+        let (mut pack, locations) = pack_bytes(&[encrypt(b"versioned", &keys)]);
+        assert_eq!(Pack::new(Cursor::new(&pack)).unwrap().version_number(), 2);
+
+        let mut index = index_bytes(&[(
+            "c0571537d57d9488164303950dfded5cb6cfcd20".to_string(),
+            locations[0].0,
+            locations[0].1,
+        )]);
+        assert_eq!(
+            PackIndex::new(Cursor::new(&index))
+                .unwrap()
+                .version_number(),
+            2
+        );
+
+        pack[4..8].copy_from_slice(&3u32.to_be_bytes());
+        assert!(matches!(
+            Pack::new(Cursor::new(&pack)),
+            Err(Error::UnsupportedVersion {
+                while_reading: "pack",
+                version: 3
+            })
+        ));
+
+        index[4..8].copy_from_slice(&1u32.to_be_bytes());
+        let err = PackIndex::new(Cursor::new(&index)).err().unwrap();
+        assert_eq!(err.to_string(), "unsupported pack index version 1");
     }
 }
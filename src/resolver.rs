@@ -0,0 +1,119 @@
+//! Resolving objects
+//! -----------------
+//!
+//! Commits point at trees, trees point at other trees and at the blobs holding file
+//! contents, always by SHA1. Where those objects live (a packset on disk, a local cache, a
+//! remote store) is up to the caller, so anything that needs to follow a SHA1 does so
+//! through a [BlobResolver].
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+
+/// Looks up objects by their SHA1.
+pub trait BlobResolver {
+    /// Returns the decrypted content of the object identified by `sha1`.
+    ///
+    /// The content is returned as stored, which means it may still need to be
+    /// decompressed by the caller. If the object can't be found,
+    /// `Error::ObjectNotFound` should be returned.
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>>;
+}
+
+impl<T: BlobResolver + ?Sized> BlobResolver for &T {
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>> {
+        (**self).resolve(sha1)
+    }
+}
+
+/// Objects already held in memory, keyed by SHA1.
+impl BlobResolver for HashMap<String, Vec<u8>> {
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>> {
+        self.get(sha1)
+            .cloned()
+            .ok_or_else(|| Error::ObjectNotFound(sha1.to_string()))
+    }
+}
+
+/// Looks objects up in `cache` first, and only goes to `remote` for the ones it doesn't
+/// have.
+///
+/// This is how Arq keeps browsing fast: trees are read from a local cache (see
+/// [crate::packset::PackSet::open_tree_cache]) and only fetched from the destination
+/// when missing.
+pub struct CachedResolver<C, R> {
+    cache: C,
+    remote: R,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+}
+
+impl<C: BlobResolver, R: BlobResolver> CachedResolver<C, R> {
+    pub fn new(cache: C, remote: R) -> Self {
+        CachedResolver {
+            cache,
+            remote,
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Number of objects found in the cache so far.
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// Number of objects that had to be looked up in the remote so far.
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+}
+
+impl<C: BlobResolver, R: BlobResolver> BlobResolver for CachedResolver<C, R> {
+    fn resolve(&self, sha1: &str) -> Result<Vec<u8>> {
+        match self.cache.resolve(sha1) {
+            Err(Error::ObjectNotFound(_)) => {
+                self.misses.set(self.misses.get() + 1);
+                self.remote.resolve(sha1)
+            }
+            Ok(content) => {
+                self.hits.set(self.hits.get() + 1);
+                Ok(content)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packset::PackSet;
+    use crate::test_utils::{master_keys, write_pack};
+
+    #[test]
+    fn test_cached_resolver_prefers_cache() {
+        let keys = master_keys();
+        let cache_root = tempfile::tempdir().unwrap();
+        let remote_dir = tempfile::tempdir().unwrap();
+
+        let cache_dir = cache_root.path().join("treepacks").join("FOLDER-trees");
+        let cached = write_pack(&cache_dir, &[b"cached tree"], &keys);
+        let remote = write_pack(remote_dir.path(), &[b"cached tree", b"remote tree"], &keys);
+        assert_eq!(cached[0], remote[0]);
+
+        let resolver = CachedResolver::new(
+            PackSet::open_tree_cache(cache_root.path(), "FOLDER", &keys).unwrap(),
+            PackSet::open(remote_dir.path(), &keys).unwrap(),
+        );
+
+        assert_eq!(resolver.resolve(&cached[0]).unwrap(), b"cached tree");
+        assert_eq!((resolver.hits(), resolver.misses()), (1, 0));
+        assert_eq!(resolver.resolve(&remote[1]).unwrap(), b"remote tree");
+        assert_eq!((resolver.hits(), resolver.misses()), (1, 1));
+        assert!(matches!(
+            resolver.resolve("0000000000000000000000000000000000000000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,775 @@
+//! Browsing a backup
+//! -----------------
+//!
+//! A [Snapshot] is the state of a folder as recorded by a single commit. It gives
+//! path-based access to the files and directories in it, resolving trees and blobs only
+//! when they're needed.
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+use crate::error::{Error, Result};
+use crate::resolver::BlobResolver;
+use crate::tree::{Commit, Node, Tree};
+use crate::utils::relative_path;
+
+/// A read-only, path addressable view of the backup recorded by a commit.
+pub struct Snapshot<R> {
+    pub commit: Commit,
+    root: Tree,
+    resolver: R,
+    ignored: Vec<PathBuf>,
+}
+
+/// An entry returned by [Snapshot::read_dir].
+#[derive(Clone, PartialEq, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub node: Node,
+}
+
+/// What [Snapshot::restore_dir] restored.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    /// Files and symbolic links written.
+    pub files: usize,
+    pub directories: usize,
+    /// Paths (in the backup) of the files and directories that couldn't be restored. A
+    /// node whose name isn't a plain file name is reported under its parent directory,
+    /// with `Error::InvalidNodeName`.
+    pub errors: Vec<(PathBuf, Error)>,
+    /// Paths (in the backup) of the restored files and directories that have xattrs or an
+    /// ACL. Restoring those is out of scope: the standard library has no API for them, so
+    /// they're left to the caller, e.g. by applying [crate::tree::Node::xattrs].
+    pub metadata_not_applied: Vec<PathBuf>,
+}
+
+/// The contents of a file in a snapshot.
+pub struct File {
+    pub node: Node,
+    contents: Cursor<Vec<u8>>,
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+impl<R: BlobResolver> Snapshot<R> {
+    /// Creates the snapshot for `commit`, resolving its root tree.
+    pub fn new(commit: Commit, resolver: R) -> Result<Self> {
+        let content = resolver.resolve(&commit.tree_sha1)?;
+        let root = Tree::new(&content, commit.tree_compression_type.clone())?;
+        Ok(Snapshot {
+            commit,
+            root,
+            resolver,
+            ignored: Vec::new(),
+        })
+    }
+
+    /// Hides the given paths (and everything under them) as if they weren't in the
+    /// snapshot, typically [crate::folder::Folder::ignored_paths].
+    pub fn with_ignored_paths(mut self, ignored: Vec<PathBuf>) -> Self {
+        self.ignored = ignored;
+        self
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignored.iter().any(|ignored| path.starts_with(ignored))
+    }
+
+    /// The tree of the folder that was backed up.
+    pub fn root(&self) -> &Tree {
+        &self.root
+    }
+
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+
+    /// Returns the node at `path` (relative to the folder that was backed up).
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Node> {
+        let path = path.as_ref();
+        let (parent, name) = split_path(path)?;
+        if self.is_ignored(&parent.join(&name)) {
+            return Err(Error::PathNotFound(path.to_path_buf()));
+        }
+        let tree = self.tree_at(&parent)?;
+        tree.nodes
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Error::PathNotFound(path.to_path_buf()))
+    }
+
+    /// Lists the entries of the directory at `path`, in name order.
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        let path = relative_path(path.as_ref());
+        let tree = self.tree_at(&path)?;
+
+        let mut entries: Vec<DirEntry> = tree
+            .nodes
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                path: path.join(name),
+                node: node.clone(),
+            })
+            .filter(|entry| !self.is_ignored(&entry.path))
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Same as [Snapshot::read_dir], as `(name, node)` pairs.
+    ///
+    /// Fails with `Error::NotADirectory` if a component of `path` is a file, and with
+    /// `Error::PathNotFound` if it doesn't exist.
+    pub fn list_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<(String, Node)>> {
+        Ok(self
+            .read_dir(path)?
+            .into_iter()
+            .map(|entry| (entry.name, entry.node))
+            .collect())
+    }
+
+    /// Opens the file at `path`, reassembling its contents.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File> {
+        let node = self.metadata(&path)?;
+        if node.is_tree {
+            return Err(Error::IsADirectory(path.as_ref().to_path_buf()));
+        }
+        let contents = node.reassemble(&self.resolver)?;
+        Ok(File {
+            node,
+            contents: Cursor::new(contents),
+        })
+    }
+
+    /// Restores the file at `backup_path` to `dest`, with the mode and mtime recorded in
+    /// its node. An existing file or symbolic link at `dest` is replaced (a link is removed,
+    /// not followed).
+    ///
+    /// Symbolic links are recreated as links (keeping the link's own mode and mtime is
+    /// left to the platform). Fails with `Error::IsADirectory` for directories, which are
+    /// restored with [Snapshot::restore_dir].
+    pub fn restore_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        backup_path: P,
+        dest: Q,
+    ) -> Result<()> {
+        let node = self.metadata(&backup_path)?;
+        if node.is_tree {
+            return Err(Error::IsADirectory(backup_path.as_ref().to_path_buf()));
+        }
+        match std::fs::remove_file(dest.as_ref()) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        self.restore_node(&node, dest.as_ref())
+    }
+
+    /// Restores the directory at `backup_path` (`""` for the whole snapshot) and
+    /// everything under it (minus ignored paths) into `dest`, which is created if needed
+    /// (and otherwise left as is).
+    ///
+    /// Directories and files get the mode and mtime recorded in the backup, symbolic links
+    /// are recreated as links and devices are left out. Existing files aren't overwritten.
+    /// A file or directory that can't be restored (including one that already exists, or
+    /// whose name would put it outside `dest`) is listed in the report's `errors` and the
+    /// rest of the restore carries on. Xattrs and ACLs aren't applied, see
+    /// [RestoreReport::metadata_not_applied].
+    pub fn restore_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        backup_path: P,
+        dest: Q,
+    ) -> Result<RestoreReport> {
+        let backup_path = relative_path(backup_path.as_ref());
+        let tree = self.tree_at(&backup_path)?;
+        let dest = dest.as_ref();
+        std::fs::create_dir_all(dest)?;
+
+        let mut report = RestoreReport::default();
+        self.restore_tree(&tree, &backup_path, dest, &mut report);
+        Ok(report)
+    }
+
+    fn restore_tree(
+        &self,
+        tree: &Tree,
+        backup_path: &Path,
+        dest: &Path,
+        report: &mut RestoreReport,
+    ) {
+        let mut names: Vec<&String> = tree.nodes.keys().collect();
+        names.sort();
+        for name in names {
+            if !is_plain_name(name) {
+                report.errors.push((
+                    backup_path.to_path_buf(),
+                    Error::InvalidNodeName(name.clone()),
+                ));
+                continue;
+            }
+            let node = &tree.nodes[name];
+            let path = backup_path.join(name);
+            if self.is_ignored(&path) || node.is_device() {
+                continue;
+            }
+            if node.xattrs_blob_key.is_some() || node.acl_blob_key.is_some() {
+                report.metadata_not_applied.push(path.clone());
+            }
+
+            let target = dest.join(name);
+            if node.is_tree {
+                let subtree = std::fs::create_dir(&target)
+                    .map_err(Error::from)
+                    .and_then(|_| node.subtree(&self.resolver));
+                match subtree {
+                    Ok(Some(subtree)) => {
+                        self.restore_tree(&subtree, &path, &target, report);
+                        match set_metadata(&target, node.mode, node.mtime_sec, node.mtime_nsec) {
+                            Ok(()) => report.directories += 1,
+                            Err(err) => report.errors.push((path, err)),
+                        }
+                    }
+                    Ok(None) => report
+                        .errors
+                        .push((path.clone(), Error::NotADirectory(path))),
+                    Err(err) => report.errors.push((path, err)),
+                }
+            } else {
+                match self.restore_node(node, &target) {
+                    Ok(()) => report.files += 1,
+                    Err(err) => report.errors.push((path, err)),
+                }
+            }
+        }
+    }
+
+    /// Writes the file or symbolic link `node` to `dest`, which mustn't exist yet.
+    fn restore_node(&self, node: &Node, dest: &Path) -> Result<()> {
+        let contents = node.reassemble(&self.resolver)?;
+        if node.is_symlink() {
+            let target = String::from_utf8(contents)
+                .map_err(|err| Error::ConversionError(err.utf8_error()))?;
+            create_symlink(Path::new(&target), dest)?;
+            return Ok(());
+        }
+
+        let mut file = std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(dest)?;
+        file.write_all(&contents)?;
+        apply_metadata(&file, node.mode, node.mtime_sec, node.mtime_nsec)
+    }
+
+    /// A SHA1 summarising the whole snapshot (minus ignored paths), to compare snapshots
+    /// for equality without comparing their trees node by node.
+    ///
+    /// Every node's path, type, size, mode, owner and mtime is folded in, in walk order,
+    /// along with the SHA1s of its data, xattrs and ACL blobs. Blob SHA1s are salted with
+    /// the backup set's keys, so only snapshots from the same backup set can be compared.
+    pub fn content_digest(&self) -> Result<[u8; 20]> {
+        let mut hasher = Sha1::new();
+        self.root
+            .walk_ignoring(&self.resolver, &self.ignored, |path, node| {
+                let path = path.to_string_lossy();
+                hasher.update((path.len() as u64).to_be_bytes());
+                hasher.update(path.as_bytes());
+                hasher.update([node.is_tree as u8]);
+                hasher.update(node.data_size.to_be_bytes());
+                hasher.update(node.mode.to_be_bytes());
+                hasher.update(node.uid.to_be_bytes());
+                hasher.update(node.gid.to_be_bytes());
+                hasher.update(node.mtime_sec.to_be_bytes());
+                hasher.update(node.mtime_nsec.to_be_bytes());
+
+                // A directory's subtree SHA1 changes with anything under it, which is
+                // folded in separately.
+                let data_sha1s = if node.is_tree {
+                    Vec::new()
+                } else {
+                    node.data_sha1s()
+                };
+                let blob_sha1s = data_sha1s.into_iter().chain(
+                    [&node.xattrs_blob_key, &node.acl_blob_key]
+                        .into_iter()
+                        .flatten()
+                        .map(|blob_key| blob_key.sha1.as_str()),
+                );
+                for sha1 in blob_sha1s {
+                    hasher.update((sha1.len() as u64).to_be_bytes());
+                    hasher.update(sha1.as_bytes());
+                }
+                hasher.update([0xff]);
+                Ok(())
+            })?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Writes every file and directory in the snapshot (minus ignored paths) to `writer`
+    /// as a tar archive, with the mode, mtime and owner recorded in their nodes.
+    ///
+    /// Symbolic links are archived as links; devices and other special files are left out.
+    #[cfg(feature = "tar")]
+    pub fn export_tar<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let mut builder = tar::Builder::new(writer);
+        self.root
+            .walk_ignoring(&self.resolver, &self.ignored, |path, node| {
+                let mut header = tar::Header::new_gnu();
+                header.set_mode((node.mode & 0o7777) as u32);
+                header.set_mtime(node.mtime_sec.max(0) as u64);
+                header.set_uid(node.uid.max(0) as u64);
+                header.set_gid(node.gid.max(0) as u64);
+
+                if node.is_tree {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, std::io::empty())?;
+                } else if node.is_symlink() {
+                    let target = String::from_utf8(node.reassemble(&self.resolver)?)
+                        .map_err(|err| Error::ConversionError(err.utf8_error()))?;
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, target)?;
+                } else if !node.is_device() {
+                    let contents = node.reassemble(&self.resolver)?;
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(contents.len() as u64);
+                    builder.append_data(&mut header, path, contents.as_slice())?;
+                }
+                Ok(())
+            })?;
+        builder.finish()?;
+        Ok(())
+    }
+
+    /// Resolves the tree of the directory at `path`.
+    fn tree_at(&self, path: &Path) -> Result<Tree> {
+        let mut tree = self.root.clone();
+        let mut current = PathBuf::new();
+        for component in relative_path(path).iter() {
+            let name = component.to_string_lossy();
+            current.push(component);
+            if self.is_ignored(&current) {
+                return Err(Error::PathNotFound(current));
+            }
+            let node = tree
+                .nodes
+                .get(name.as_ref())
+                .ok_or_else(|| Error::PathNotFound(current.clone()))?;
+            tree = node
+                .subtree(&self.resolver)?
+                .ok_or_else(|| Error::NotADirectory(current.clone()))?;
+        }
+        Ok(tree)
+    }
+}
+
+/// Sets the mtime and (on unix) the permission bits of `mode` on the file or directory at
+/// `path`.
+fn set_metadata(path: &Path, mode: i32, mtime_sec: i64, mtime_nsec: i64) -> Result<()> {
+    let file = if path.is_dir() {
+        std::fs::File::open(path)?
+    } else {
+        std::fs::File::options().write(true).open(path)?
+    };
+    apply_metadata(&file, mode, mtime_sec, mtime_nsec)
+}
+
+/// Sets the mode and mtime of the already open `file`.
+fn apply_metadata(file: &std::fs::File, mode: i32, mtime_sec: i64, mtime_nsec: i64) -> Result<()> {
+    let mtime = std::time::UNIX_EPOCH
+        + std::time::Duration::new(
+            mtime_sec.max(0) as u64,
+            mtime_nsec.clamp(0, 999_999_999) as u32,
+        );
+    file.set_modified(mtime)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode((mode & 0o7777) as u32))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symbolic links can only be restored on unix",
+    ))
+}
+
+/// Whether the node name `name` is a single, normal path component, i.e. joining it to a
+/// directory can't lead outside of it.
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(first)), None) if first == name
+    )
+}
+
+/// Splits `path` into its parent directory and file name.
+fn split_path(path: &Path) -> Result<(PathBuf, String)> {
+    let normalized = relative_path(path);
+    match normalized.file_name() {
+        Some(name) => Ok((
+            normalized
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            name.to_string_lossy().into_owned(),
+        )),
+        None => Err(Error::PathNotFound(path.to_path_buf())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_bytes, fixture_objects, node_bytes, tree_bytes};
+    use std::collections::HashMap;
+
+    fn snapshot() -> Snapshot<HashMap<String, Vec<u8>>> {
+        let (_, objects) = fixture_objects();
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        Snapshot::new(commit, objects).unwrap()
+    }
+
+    #[test]
+    fn test_open_file() {
+        let snapshot = snapshot();
+
+        let mut contents = String::new();
+        snapshot
+            .open("sub/c")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "0123456");
+
+        let mut file = snapshot.open("/a").unwrap();
+        assert_eq!(file.node.data_size, 10);
+        file.seek(SeekFrom::Start(8)).unwrap();
+        contents.clear();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "89");
+    }
+
+    #[test]
+    fn test_read_dir() {
+        let snapshot = snapshot();
+
+        let names: Vec<String> = snapshot
+            .read_dir("/")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["a", "sub"]);
+
+        let entries = snapshot.read_dir("sub").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, PathBuf::from("sub/b"));
+        assert_eq!(entries[1].node.data_size, 7);
+    }
+
+    #[test]
+    fn test_list_dir() {
+        let snapshot = snapshot();
+
+        let entries = snapshot.list_dir("/sub").unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["b", "c"]);
+        assert_eq!(entries[0].1.data_size, 5);
+        assert_eq!(snapshot.list_dir("").unwrap().len(), 2);
+
+        assert!(matches!(
+            snapshot.list_dir("sub/b"),
+            Err(Error::NotADirectory(ref path)) if path == Path::new("sub/b")
+        ));
+        assert!(matches!(
+            snapshot.list_dir("sub/missing"),
+            Err(Error::PathNotFound(ref path)) if path == Path::new("sub/missing")
+        ));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let snapshot = snapshot();
+        assert!(snapshot.metadata("sub").unwrap().is_tree);
+        assert_eq!(snapshot.metadata("sub/b").unwrap().data_size, 5);
+    }
+
+    #[test]
+    fn test_ignored_paths() {
+        let snapshot = snapshot().with_ignored_paths(vec![PathBuf::from("sub")]);
+
+        let names: Vec<String> = snapshot
+            .read_dir("")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["a"]);
+        assert!(matches!(
+            snapshot.metadata("sub"),
+            Err(Error::PathNotFound(_))
+        ));
+        assert!(matches!(
+            snapshot.open("sub/b"),
+            Err(Error::PathNotFound(_))
+        ));
+        assert!(snapshot.open("a").is_ok());
+    }
+
+    #[test]
+    fn test_content_digest() {
+        let digest = snapshot().content_digest().unwrap();
+        assert_eq!(snapshot().content_digest().unwrap(), digest);
+
+        let (_, mut objects) = fixture_objects();
+        objects.insert(
+            "subtree".to_string(),
+            tree_bytes(&[
+                ("b", node_bytes(false, &["bbbb"], 5, 0o100644)),
+                ("c", node_bytes(false, &["dddd"], 7, 0o100644)),
+            ]),
+        );
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        let changed = Snapshot::new(commit, objects).unwrap();
+        assert_ne!(changed.content_digest().unwrap(), digest);
+
+        let ignoring = snapshot().with_ignored_paths(vec![PathBuf::from("sub/c")]);
+        assert_ne!(ignoring.content_digest().unwrap(), digest);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_export_tar() {
+        let mut archive = Vec::new();
+        snapshot().export_tar(&mut archive).unwrap();
+
+        let mut entries = Vec::new();
+        for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_str().unwrap().to_string();
+            let header = entry.header().clone();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            assert_eq!(header.mtime().unwrap(), 1_550_000_000);
+            assert_eq!(header.uid().unwrap(), 501);
+            assert_eq!(header.entry_type().is_dir(), path == "sub");
+            entries.push((path, header.mode().unwrap(), contents));
+        }
+        assert_eq!(
+            entries,
+            vec![
+                ("a".to_string(), 0o644, "0123456789".to_string()),
+                ("sub".to_string(), 0o755, String::new()),
+                ("sub/b".to_string(), 0o644, "01234".to_string()),
+                ("sub/c".to_string(), 0o644, "0123456".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = snapshot();
+
+        let dest = dir.path().join("c");
+        snapshot.restore_file("sub/c", &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"0123456");
+        let metadata = std::fs::metadata(&dest).unwrap();
+        let mtime = metadata
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(mtime.as_secs(), 1_550_000_000);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o644);
+        }
+
+        assert!(matches!(
+            snapshot.restore_file("sub", dir.path().join("sub")),
+            Err(Error::IsADirectory(ref path)) if path == Path::new("sub")
+        ));
+        assert!(matches!(
+            snapshot.restore_file("sub/nope", dir.path().join("nope")),
+            Err(Error::PathNotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_symlink() {
+        let (_, mut objects) = fixture_objects();
+        objects.insert("link".to_string(), b"sub/c".to_vec());
+        objects.insert(
+            "roottree".to_string(),
+            tree_bytes(&[("l", node_bytes(false, &["link"], 5, 0o120755))]),
+        );
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        let snapshot = Snapshot::new(commit, objects).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("l");
+        snapshot.restore_file("l", &dest).unwrap();
+        assert_eq!(std::fs::read_link(&dest).unwrap(), Path::new("sub/c"));
+    }
+
+    #[test]
+    fn test_restore_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = snapshot().restore_dir("", dir.path().join("out")).unwrap();
+        assert_eq!(report.files, 3);
+        assert_eq!(report.directories, 1);
+        assert!(report.errors.is_empty());
+        assert!(report.metadata_not_applied.is_empty());
+
+        let out = dir.path().join("out");
+        let mut restored = Vec::new();
+        for path in ["a", "sub", "sub/b", "sub/c"] {
+            let metadata = std::fs::metadata(out.join(path)).unwrap();
+            let mtime = metadata
+                .modified()
+                .unwrap()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap();
+            assert_eq!(mtime.as_secs(), 1_550_000_000);
+            restored.push((path, metadata.is_dir(), metadata.len()));
+        }
+        assert_eq!(restored[0], ("a", false, 10));
+        assert!(restored[1].1);
+        assert_eq!(restored[2], ("sub/b", false, 5));
+        assert_eq!(restored[3], ("sub/c", false, 7));
+        assert_eq!(std::fs::read(out.join("sub/c")).unwrap(), b"0123456");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(out.join("sub"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o7777, 0o755);
+        }
+
+        // A missing blob fails that file only.
+        let (_, mut objects) = fixture_objects();
+        objects.remove("bbbb");
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        let snapshot = Snapshot::new(commit, objects).unwrap();
+        let report = snapshot
+            .restore_dir("sub", dir.path().join("partial"))
+            .unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, PathBuf::from("sub/b"));
+        assert!(matches!(report.errors[0].1, Error::ObjectNotFound(_)));
+        assert!(dir.path().join("partial/c").exists());
+
+        assert!(matches!(
+            snapshot.restore_dir("a", dir.path().join("a")),
+            Err(Error::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_dir_unsafe_names() {
+        let (_, mut objects) = fixture_objects();
+        objects.insert(
+            "roottree".to_string(),
+            tree_bytes(&[
+                ("a", node_bytes(false, &["aaaa"], 10, 0o100644)),
+                ("../escaped", node_bytes(false, &["aaaa"], 10, 0o100644)),
+                ("/absolute", node_bytes(false, &["aaaa"], 10, 0o100644)),
+                ("..", node_bytes(true, &["subtree"], 0, 0o40755)),
+            ]),
+        );
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        let snapshot = Snapshot::new(commit, objects).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let report = snapshot.restore_dir("", dir.path().join("out")).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(report.directories, 0);
+        let mut rejected: Vec<&str> = report
+            .errors
+            .iter()
+            .map(|(path, err)| match err {
+                Error::InvalidNodeName(name) if path == Path::new("") => name.as_str(),
+                err => panic!("unexpected error {err:?}"),
+            })
+            .collect();
+        rejected.sort();
+        assert_eq!(rejected, ["..", "../escaped", "/absolute"]);
+        assert!(!dir.path().join("escaped").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_dir_existing_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        let victim = dir.path().join("victim");
+        std::os::unix::fs::symlink(&victim, out.join("a")).unwrap();
+
+        let report = snapshot().restore_dir("", &out).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, PathBuf::from("a"));
+        assert!(matches!(
+            report.errors[0].1,
+            Error::IoError(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        assert!(!victim.exists());
+
+        // restore_file replaces the link itself.
+        snapshot().restore_file("a", out.join("a")).unwrap();
+        assert!(!victim.exists());
+        assert_eq!(std::fs::read(out.join("a")).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_bad_paths() {
+        let snapshot = snapshot();
+        assert!(matches!(snapshot.open("sub"), Err(Error::IsADirectory(_))));
+        assert!(matches!(
+            snapshot.read_dir("a"),
+            Err(Error::NotADirectory(_))
+        ));
+        assert!(matches!(
+            snapshot.metadata("sub/nope"),
+            Err(Error::PathNotFound(_))
+        ));
+        assert!(matches!(
+            snapshot.read_dir("nope/deeper"),
+            Err(Error::PathNotFound(ref path)) if path == Path::new("nope")
+        ));
+    }
+}
@@ -0,0 +1,392 @@
+//! Helpers to build raw Arq structures in unit tests and benchmarks (through the
+//! `test-utils` feature).
+use std::collections::HashMap;
+use std::path::Path;
+
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::compression::CompressionType;
+use crate::object_encryption::{calculate_sha1sum, content_address};
+use crate::packset::PackIndex;
+use crate::utils::convert_to_hex_string;
+
+pub fn push_bool(buf: &mut Vec<u8>, value: bool) {
+    buf.push(value as u8);
+}
+
+pub fn push_string(buf: &mut Vec<u8>, value: &str) {
+    if value.is_empty() {
+        buf.push(0);
+    } else {
+        buf.push(1);
+        buf.extend_from_slice(&(value.len() as u64).to_be_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// A BlobKey; `None` writes the null key Arq uses for absent xattrs/acls.
+pub fn push_blob_key(buf: &mut Vec<u8>, sha1: Option<&str>) {
+    push_string(buf, sha1.unwrap_or(""));
+    push_bool(buf, false);
+    buf.extend_from_slice(&1u32.to_be_bytes());
+    push_string(buf, "");
+    buf.extend_from_slice(&0u64.to_be_bytes());
+    buf.push(0);
+}
+
+/// A v22 node. For tree nodes `data_sha1s` holds the SHA1 of the subtree.
+pub fn node_bytes(is_tree: bool, data_sha1s: &[&str], data_size: u64, mode: i32) -> Vec<u8> {
+    node_bytes_for_version(22, is_tree, data_sha1s, data_size, mode)
+}
+
+/// A node as laid out in a tree of the given `version`. Older versions get gzip-compressed
+/// data and a "thumb" thumbnail SHA1 with its key stretched.
+pub fn node_bytes_for_version(
+    version: u32,
+    is_tree: bool,
+    data_sha1s: &[&str],
+    data_size: u64,
+    mode: i32,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    push_bool(&mut buf, is_tree);
+    if version >= 18 {
+        push_bool(&mut buf, false);
+    }
+    if version >= 19 {
+        for _ in 0..3 {
+            buf.extend_from_slice(&0i32.to_be_bytes());
+        }
+    } else {
+        push_bool(&mut buf, true);
+        push_bool(&mut buf, false);
+        push_bool(&mut buf, false);
+    }
+    buf.extend_from_slice(&(data_sha1s.len() as i32).to_be_bytes());
+    for sha1 in data_sha1s {
+        push_blob_key(&mut buf, Some(sha1));
+    }
+    buf.extend_from_slice(&data_size.to_be_bytes());
+    if version <= 18 {
+        push_string(&mut buf, "thumb");
+        if version >= 14 {
+            push_bool(&mut buf, true);
+        }
+        push_string(&mut buf, "");
+        if version >= 14 {
+            push_bool(&mut buf, false);
+        }
+    }
+    push_blob_key(&mut buf, None);
+    buf.extend_from_slice(&0u64.to_be_bytes());
+    push_blob_key(&mut buf, None);
+    buf.extend_from_slice(&501i32.to_be_bytes()); // uid
+    buf.extend_from_slice(&20i32.to_be_bytes()); // gid
+    buf.extend_from_slice(&mode.to_be_bytes());
+    buf.extend_from_slice(&1_550_000_000i64.to_be_bytes()); // mtime_sec
+    buf.extend_from_slice(&0i64.to_be_bytes()); // mtime_nsec
+    buf.extend_from_slice(&0i64.to_be_bytes()); // flags
+    buf.extend_from_slice(&0i32.to_be_bytes()); // finder_flags
+    buf.extend_from_slice(&0i32.to_be_bytes()); // extended_finder_flags
+    push_string(&mut buf, ""); // finder_file_type
+    push_string(&mut buf, ""); // finder_file_creator
+    push_bool(&mut buf, false);
+    buf.extend_from_slice(&0i32.to_be_bytes()); // st_dev
+    buf.extend_from_slice(&0i32.to_be_bytes()); // st_ino
+    buf.extend_from_slice(&1u32.to_be_bytes()); // st_nlink
+    buf.extend_from_slice(&0i32.to_be_bytes()); // st_rdev
+    for _ in 0..5 {
+        buf.extend_from_slice(&0i64.to_be_bytes()); // ctime, create_time, st_blocks
+    }
+    buf.extend_from_slice(&4096u32.to_be_bytes()); // st_blksize
+    buf
+}
+
+/// An uncompressed v22 tree holding `nodes`.
+pub fn tree_bytes(nodes: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    tree_bytes_for_version(22, nodes)
+}
+
+/// A tree as laid out in the given `version` (15 or later). Versions up to 18 get
+/// gzip-compressed xattrs and uncompressed ACLs, and versions up to 16 an
+/// `aggregate_size_on_disk` of 8192.
+pub fn tree_bytes_for_version(version: u32, nodes: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    tree_bytes_with_compression(version, CompressionType::None, CompressionType::None, nodes)
+}
+
+/// Like [tree_bytes_for_version], with the given xattrs and ACL compression types for
+/// versions 19 and later (older ones only store a gzip flag).
+pub fn tree_bytes_with_compression(
+    version: u32,
+    xattrs_compression_type: CompressionType,
+    acl_compression_type: CompressionType,
+    nodes: &[(&str, Vec<u8>)],
+) -> Vec<u8> {
+    let mut buf = format!("TreeV{version:03}").into_bytes();
+    if version >= 19 {
+        xattrs_compression_type.write(&mut buf).unwrap();
+        acl_compression_type.write(&mut buf).unwrap();
+    } else {
+        push_bool(&mut buf, true);
+        push_bool(&mut buf, false);
+    }
+    push_blob_key(&mut buf, None);
+    buf.extend_from_slice(&0u64.to_be_bytes());
+    push_blob_key(&mut buf, None);
+    buf.extend_from_slice(&501i32.to_be_bytes()); // uid
+    buf.extend_from_slice(&20i32.to_be_bytes()); // gid
+    buf.extend_from_slice(&0o40755i32.to_be_bytes()); // mode
+    for _ in 0..3 {
+        buf.extend_from_slice(&0i64.to_be_bytes()); // mtime, flags
+    }
+    for _ in 0..4 {
+        buf.extend_from_slice(&0i32.to_be_bytes()); // finder flags, st_dev, st_ino
+    }
+    buf.extend_from_slice(&1u32.to_be_bytes()); // st_nlink
+    buf.extend_from_slice(&0i32.to_be_bytes()); // st_rdev
+    for _ in 0..3 {
+        buf.extend_from_slice(&0i64.to_be_bytes()); // ctime, st_blocks
+    }
+    buf.extend_from_slice(&4096u32.to_be_bytes()); // st_blksize
+    if version <= 16 {
+        buf.extend_from_slice(&8192u64.to_be_bytes()); // aggregate_size_on_disk
+    }
+    for _ in 0..2 {
+        buf.extend_from_slice(&0i64.to_be_bytes()); // create_time
+    }
+    if version >= 18 {
+        buf.extend_from_slice(&0u32.to_be_bytes()); // missing_node_count
+    }
+    buf.extend_from_slice(&(nodes.len() as u32).to_be_bytes());
+    for (name, node) in nodes {
+        push_string(&mut buf, name);
+        buf.extend_from_slice(node);
+    }
+    buf
+}
+
+/// A v12 commit pointing at `tree_sha1` (stored uncompressed).
+pub fn commit_bytes(tree_sha1: &str, parent: Option<&str>) -> Vec<u8> {
+    commit_bytes_for_version(12, tree_sha1, parent)
+}
+
+/// A commit as laid out in the given `version` (3 or later). Versions 8 and 9 flag the
+/// tree as gzip-compressed; other ones store it uncompressed.
+pub fn commit_bytes_for_version(version: u32, tree_sha1: &str, parent: Option<&str>) -> Vec<u8> {
+    commit_bytes_with_flags(version, tree_sha1, parent, false, true)
+}
+
+/// Like [commit_bytes_for_version], with the given `has_missing_nodes` (version 8 or
+/// later) and `is_complete` (version 9 or later) flags.
+pub fn commit_bytes_with_flags(
+    version: u32,
+    tree_sha1: &str,
+    parent: Option<&str>,
+    has_missing_nodes: bool,
+    is_complete: bool,
+) -> Vec<u8> {
+    let mut buf = format!("CommitV{version:03}").into_bytes();
+    push_string(&mut buf, "someuser");
+    push_string(&mut buf, "a comment");
+    match parent {
+        Some(parent) => {
+            buf.extend_from_slice(&1u64.to_be_bytes());
+            push_string(&mut buf, parent);
+            if version >= 4 {
+                push_bool(&mut buf, false);
+            }
+        }
+        None => buf.extend_from_slice(&0u64.to_be_bytes()),
+    }
+    push_string(&mut buf, tree_sha1);
+    if version >= 4 {
+        push_bool(&mut buf, false);
+    }
+    if version >= 10 {
+        buf.extend_from_slice(&0i32.to_be_bytes());
+    } else if version >= 8 {
+        push_bool(&mut buf, true);
+    }
+    push_string(&mut buf, "file://myhost/Users/someuser/src");
+    if version <= 7 {
+        push_string(&mut buf, "merge_common_ancestor");
+        if version >= 4 {
+            push_bool(&mut buf, false);
+        }
+    }
+    buf.push(1);
+    buf.extend_from_slice(&1_550_000_000_000u64.to_be_bytes());
+    buf.extend_from_slice(&0u64.to_be_bytes()); // failed files
+    if version >= 8 {
+        push_bool(&mut buf, has_missing_nodes);
+    }
+    if version >= 9 {
+        push_bool(&mut buf, is_complete);
+    }
+    if version >= 5 {
+        buf.extend_from_slice(&0u64.to_be_bytes()); // config plist
+    }
+    push_string(&mut buf, "5.20.0");
+    buf
+}
+
+/// Root tree bytes plus the objects it references (including itself, as "roottree"), for
+/// a small fixture:
+///
+/// ```ascii
+/// /
+/// ├── a        (10 bytes)
+/// └── sub/
+///     ├── b    (5 bytes)
+///     └── c    (7 bytes)
+/// ```
+pub fn fixture_objects() -> (Vec<u8>, HashMap<String, Vec<u8>>) {
+    let sub = tree_bytes(&[
+        ("b", node_bytes(false, &["bbbb"], 5, 0o100644)),
+        ("c", node_bytes(false, &["cccc"], 7, 0o100644)),
+    ]);
+    let root = tree_bytes(&[
+        ("a", node_bytes(false, &["aaaa"], 10, 0o100644)),
+        ("sub", node_bytes(true, &["subtree"], 0, 0o40755)),
+    ]);
+
+    let mut objects = HashMap::new();
+    objects.insert("roottree".to_string(), root.clone());
+    objects.insert("subtree".to_string(), sub);
+    objects.insert("aaaa".to_string(), b"0123456789".to_vec());
+    objects.insert("bbbb".to_string(), b"01234".to_vec());
+    objects.insert("cccc".to_string(), b"0123456".to_vec());
+    (root, objects)
+}
+
+/// `data` compressed the way Arq stores LZ4 objects.
+pub fn lz4_compress(data: &[u8]) -> Vec<u8> {
+    crate::lz4::compress(data).unwrap()
+}
+
+/// Master keys used by all the encrypted fixtures.
+pub fn master_keys() -> Vec<Vec<u8>> {
+    vec![vec![1; 32], vec![2; 32], vec![3; 32]]
+}
+
+fn cbc_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut buf = data.to_vec();
+    buf.resize(data.len() + 16, 0);
+    let len = cbc::Encryptor::<aes::Aes256>::new_from_slices(key, iv)
+        .unwrap()
+        .encrypt_padded_mut::<Pkcs7>(&mut buf, data.len())
+        .unwrap()
+        .len();
+    buf.truncate(len);
+    buf
+}
+
+/// An EncryptedObject ("ARQO" + HMAC + IVs + ciphertext) holding `plaintext`.
+pub fn encrypt(plaintext: &[u8], master_keys: &[Vec<u8>]) -> Vec<u8> {
+    let session_key = [9u8; 32];
+    let data_iv = [4u8; 16];
+    let master_iv = [5u8; 16];
+
+    let ciphertext = cbc_encrypt(&session_key, &data_iv, plaintext);
+    let data_iv_session = cbc_encrypt(
+        &master_keys[0],
+        &master_iv,
+        &[&data_iv[..], &session_key[..]].concat(),
+    );
+    let mut mac = Hmac::<Sha256>::new_from_slice(&master_keys[1]).unwrap();
+    mac.update(&[&master_iv[..], &data_iv_session, &ciphertext].concat());
+
+    [
+        &b"ARQO"[..],
+        &mac.finalize().into_bytes(),
+        &master_iv,
+        &data_iv_session,
+        &ciphertext,
+    ]
+    .concat()
+}
+
+pub fn sha1_bytes(sha1: &str) -> [u8; 20] {
+    let mut bytes = [0u8; 20];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&sha1[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    bytes
+}
+
+/// A pack holding `objects` (already encrypted) with null mimetypes and names, plus the
+/// offset and data length of each object.
+pub fn pack_bytes(objects: &[Vec<u8>]) -> (Vec<u8>, Vec<(u64, u64)>) {
+    let mut buf = b"PACK".to_vec();
+    buf.extend_from_slice(&2u32.to_be_bytes());
+    buf.extend_from_slice(&(objects.len() as u64).to_be_bytes());
+
+    let mut locations = Vec::new();
+    for object in objects {
+        locations.push((buf.len() as u64, object.len() as u64));
+        push_bool(&mut buf, false);
+        push_bool(&mut buf, false);
+        buf.extend_from_slice(&(object.len() as u64).to_be_bytes());
+        buf.extend_from_slice(object);
+    }
+    let sha1 = calculate_sha1sum(&buf);
+    buf.extend_from_slice(&sha1);
+    (buf, locations)
+}
+
+/// A pack index for objects identified by (sha1, offset, data length).
+pub fn index_bytes(entries: &[(String, u64, u64)]) -> Vec<u8> {
+    let mut entries = entries.to_vec();
+    entries.sort();
+    let sha1s: Vec<[u8; 20]> = entries
+        .iter()
+        .map(|(sha1, _, _)| sha1_bytes(sha1))
+        .collect();
+
+    let mut buf = vec![0xff, 0x74, 0x4f, 0x63];
+    buf.extend_from_slice(&2u32.to_be_bytes());
+    for count in PackIndex::build_fanout(&sha1s) {
+        buf.extend_from_slice(&count);
+    }
+    for ((_, offset, data_len), sha1) in entries.iter().zip(sha1s) {
+        buf.extend_from_slice(&offset.to_be_bytes());
+        buf.extend_from_slice(&data_len.to_be_bytes());
+        buf.extend_from_slice(&sha1);
+        buf.extend_from_slice(&[0; 4]);
+    }
+    let sha1 = calculate_sha1sum(&buf);
+    buf.extend_from_slice(&sha1);
+    buf
+}
+
+/// Writes a single pack (and its index) holding `plaintexts` into `dir`, returning the
+/// content address of each object.
+pub fn write_pack(dir: &Path, plaintexts: &[&[u8]], master_keys: &[Vec<u8>]) -> Vec<String> {
+    let sha1s: Vec<String> = plaintexts
+        .iter()
+        .map(|plaintext| content_address(plaintext, &master_keys[2]))
+        .collect();
+    let encrypted: Vec<Vec<u8>> = plaintexts
+        .iter()
+        .map(|plaintext| encrypt(plaintext, master_keys))
+        .collect();
+
+    let (pack, locations) = pack_bytes(&encrypted);
+    let entries: Vec<(String, u64, u64)> = sha1s
+        .iter()
+        .zip(locations)
+        .map(|(sha1, (offset, len))| (sha1.clone(), offset, len))
+        .collect();
+
+    std::fs::create_dir_all(dir).unwrap();
+    let pack_sha1 = convert_to_hex_string(&calculate_sha1sum(&pack));
+    std::fs::write(dir.join(format!("{pack_sha1}.pack")), pack).unwrap();
+    std::fs::write(
+        dir.join(format!("{pack_sha1}.index")),
+        index_bytes(&entries),
+    )
+    .unwrap();
+    sha1s
+}
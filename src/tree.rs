@@ -14,14 +14,428 @@
 //!
 //! All commits, trees and blobs are stored as EncryptedObjects.
 use std;
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Seek, Write};
+use std::path::{Path, PathBuf};
 
 use crate::blob;
 use crate::compression::CompressionType;
 use crate::date::Date;
-use crate::error::Result;
-use crate::type_utils::ArqRead;
+use crate::error::{Error, Result};
+use crate::resolver::BlobResolver;
+use crate::type_utils::{read_header, ArqRead, ArqWrite, MAX_PREALLOCATION};
+use crate::utils::percent_decode;
+
+// File type bits of `mode` (see stat(2))
+const S_IFMT: i32 = 0o170000;
+const S_IFCHR: i32 = 0o020000;
+const S_IFBLK: i32 = 0o060000;
+const S_IFLNK: i32 = 0o120000;
+
+/// The tree version written by current versions of Arq.
+pub const CURRENT_TREE_VERSION: u32 = 22;
+
+/// Tree format versions, as found in the `TreeVNNN` header.
+///
+/// Fields come and go between versions; parsing goes through the methods below rather than
+/// comparing version numbers. Versions this library doesn't know about are `Unknown` and
+/// treated like the closest known one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreeVersion {
+    V12,
+    V13,
+    V14,
+    V15,
+    V16,
+    V17,
+    V18,
+    V19,
+    V20,
+    V21,
+    V22,
+    Unknown(u32),
+}
+
+impl From<u32> for TreeVersion {
+    fn from(version: u32) -> Self {
+        match version {
+            12 => TreeVersion::V12,
+            13 => TreeVersion::V13,
+            14 => TreeVersion::V14,
+            15 => TreeVersion::V15,
+            16 => TreeVersion::V16,
+            17 => TreeVersion::V17,
+            18 => TreeVersion::V18,
+            19 => TreeVersion::V19,
+            20 => TreeVersion::V20,
+            21 => TreeVersion::V21,
+            22 => TreeVersion::V22,
+            version => TreeVersion::Unknown(version),
+        }
+    }
+}
+
+impl TreeVersion {
+    pub fn number(self) -> u32 {
+        match self {
+            TreeVersion::V12 => 12,
+            TreeVersion::V13 => 13,
+            TreeVersion::V14 => 14,
+            TreeVersion::V15 => 15,
+            TreeVersion::V16 => 16,
+            TreeVersion::V17 => 17,
+            TreeVersion::V18 => 18,
+            TreeVersion::V19 => 19,
+            TreeVersion::V20 => 20,
+            TreeVersion::V21 => 21,
+            TreeVersion::V22 => 22,
+            TreeVersion::Unknown(version) => version,
+        }
+    }
+
+    /// Whether compression is stored as a `CompressionType` (version 19 and later) rather
+    /// than a "gzip compressed" flag.
+    fn stores_compression_type(self) -> bool {
+        match self {
+            TreeVersion::V12
+            | TreeVersion::V13
+            | TreeVersion::V14
+            | TreeVersion::V15
+            | TreeVersion::V16
+            | TreeVersion::V17
+            | TreeVersion::V18 => false,
+            TreeVersion::V19 | TreeVersion::V20 | TreeVersion::V21 | TreeVersion::V22 => true,
+            TreeVersion::Unknown(version) => version > 22,
+        }
+    }
+
+    /// Whether nodes have a `tree_contains_missing_items` flag and trees a list of missing
+    /// nodes (version 18 and later).
+    fn has_missing_items_flag(self) -> bool {
+        match self {
+            TreeVersion::V18 => true,
+            version => version.stores_compression_type(),
+        }
+    }
+
+    /// Whether trees have an `aggregate_size_on_disk` (versions 11 to 16).
+    fn has_aggregate_size_on_disk(self) -> bool {
+        match self {
+            TreeVersion::V12
+            | TreeVersion::V13
+            | TreeVersion::V14
+            | TreeVersion::V15
+            | TreeVersion::V16 => true,
+            TreeVersion::Unknown(version) => version == 11,
+            _ => false,
+        }
+    }
+
+    /// Whether trees have a creation time (version 15 and later).
+    fn has_create_time(self) -> bool {
+        match self {
+            TreeVersion::V12 | TreeVersion::V13 | TreeVersion::V14 => false,
+            TreeVersion::Unknown(version) => version > 22,
+            _ => true,
+        }
+    }
+
+    /// Whether nodes have thumbnail and preview SHA1s (version 18 and earlier).
+    fn has_thumbnails(self) -> bool {
+        !self.stores_compression_type()
+    }
+
+    /// Whether the thumbnail and preview SHA1s have "key stretched" flags (versions 14
+    /// to 18).
+    fn has_thumbnail_stretched_flags(self) -> bool {
+        match self {
+            TreeVersion::V14
+            | TreeVersion::V15
+            | TreeVersion::V16
+            | TreeVersion::V17
+            | TreeVersion::V18 => true,
+            TreeVersion::V12
+            | TreeVersion::V13
+            | TreeVersion::V19
+            | TreeVersion::V20
+            | TreeVersion::V21
+            | TreeVersion::V22
+            | TreeVersion::Unknown(_) => false,
+        }
+    }
+}
+
+/// Commit format versions, as found in the `CommitVNNN` header.
+///
+/// All of them are parsed (12 being the one written by current versions of Arq); see
+/// [Commit] for what changed between versions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommitVersion {
+    V3,
+    V4,
+    V5,
+    V6,
+    V7,
+    V8,
+    V9,
+    V10,
+    V11,
+    V12,
+    Unknown(u32),
+}
+
+impl From<u32> for CommitVersion {
+    fn from(version: u32) -> Self {
+        match version {
+            3 => CommitVersion::V3,
+            4 => CommitVersion::V4,
+            5 => CommitVersion::V5,
+            6 => CommitVersion::V6,
+            7 => CommitVersion::V7,
+            8 => CommitVersion::V8,
+            9 => CommitVersion::V9,
+            10 => CommitVersion::V10,
+            11 => CommitVersion::V11,
+            12 => CommitVersion::V12,
+            version => CommitVersion::Unknown(version),
+        }
+    }
+}
+
+impl CommitVersion {
+    pub fn number(self) -> u32 {
+        match self {
+            CommitVersion::V3 => 3,
+            CommitVersion::V4 => 4,
+            CommitVersion::V5 => 5,
+            CommitVersion::V6 => 6,
+            CommitVersion::V7 => 7,
+            CommitVersion::V8 => 8,
+            CommitVersion::V9 => 9,
+            CommitVersion::V10 => 10,
+            CommitVersion::V11 => 11,
+            CommitVersion::V12 => 12,
+            CommitVersion::Unknown(version) => version,
+        }
+    }
+
+    /// Whether parent commits and trees have "encryption key stretched" flags (version 4
+    /// and later).
+    fn has_key_stretched_flags(self) -> bool {
+        match self {
+            CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V8
+            | CommitVersion::V9
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12 => true,
+            CommitVersion::V3 => false,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+
+    /// Whether the tree's compression is stored as a "gzip compressed" flag (versions 8
+    /// and 9) rather than a `CompressionType` (version 10 and later). Earlier trees
+    /// aren't compressed.
+    fn has_tree_is_compressed_flag(self) -> bool {
+        match self {
+            CommitVersion::V8 | CommitVersion::V9 => true,
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12
+            | CommitVersion::Unknown(_) => false,
+        }
+    }
+
+    /// Whether the tree's compression is stored as a `CompressionType` (version 10 and
+    /// later).
+    fn stores_tree_compression_type(self) -> bool {
+        match self {
+            CommitVersion::V10 | CommitVersion::V11 | CommitVersion::V12 => true,
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V8
+            | CommitVersion::V9 => false,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+
+    /// Whether commits have the (unused) merge common ancestor (version 7 and earlier).
+    fn has_merge_common_ancestor(self) -> bool {
+        match self {
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7 => true,
+            CommitVersion::V8
+            | CommitVersion::V9
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12 => false,
+            CommitVersion::Unknown(version) => version < 3,
+        }
+    }
+
+    /// Whether commits have a list of failed files (version 3 and later).
+    fn has_failed_files(self) -> bool {
+        match self {
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V8
+            | CommitVersion::V9
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12 => true,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+
+    /// Whether commits have a `has_missing_nodes` flag (version 8 and later).
+    fn has_missing_nodes_flag(self) -> bool {
+        match self {
+            CommitVersion::V8
+            | CommitVersion::V9
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12 => true,
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7 => false,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+
+    /// Whether commits have an `is_complete` flag (version 9 and later).
+    fn has_is_complete_flag(self) -> bool {
+        match self {
+            CommitVersion::V9 | CommitVersion::V10 | CommitVersion::V11 | CommitVersion::V12 => {
+                true
+            }
+            CommitVersion::V3
+            | CommitVersion::V4
+            | CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V8 => false,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+
+    /// Whether commits have a copy of the folder's configuration (version 5 and later).
+    fn has_config_plist(self) -> bool {
+        match self {
+            CommitVersion::V5
+            | CommitVersion::V6
+            | CommitVersion::V7
+            | CommitVersion::V8
+            | CommitVersion::V9
+            | CommitVersion::V10
+            | CommitVersion::V11
+            | CommitVersion::V12 => true,
+            CommitVersion::V3 | CommitVersion::V4 => false,
+            CommitVersion::Unknown(version) => version > 12,
+        }
+    }
+}
+
+/// Reads a compression setting for a tree of the given `version`.
+///
+/// Tree versions 12 to 18 only record whether the data is (gzip) compressed, while later
+/// versions store the compression type.
+fn read_compression<R: ArqRead>(reader: &mut R, version: TreeVersion) -> Result<CompressionType> {
+    if version.stores_compression_type() {
+        reader.read_arq_compression_type()
+    } else if reader.read_arq_bool()? {
+        Ok(CompressionType::Gzip)
+    } else {
+        Ok(CompressionType::None)
+    }
+}
+
+/// Whether `err` means the input ended too early, wherever it was tagged with an offset.
+fn is_eof(err: &Error) -> bool {
+    match err {
+        Error::ParseAt { kind, .. } => is_eof(kind),
+        Error::IoError(err) => err.kind() == std::io::ErrorKind::UnexpectedEof,
+        Error::UnexpectedEof { .. } => true,
+        _ => false,
+    }
+}
+
+/// Wraps a reader, keeping track of how far into it parsing got so that errors can be
+/// tagged with the offset of the read that failed.
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+    last_read_at: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            position: 0,
+            last_read_at: 0,
+        }
+    }
+
+    /// Tags `err` with the offset the last read started at. Errors that already carry an
+    /// offset are passed through untouched.
+    fn tag(&self, err: Error) -> Error {
+        match err {
+            Error::ParseAt { .. } => err,
+            _ => Error::ParseAt {
+                offset: self.last_read_at,
+                kind: Box::new(err),
+            },
+        }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.last_read_at = self.position;
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.last_read_at = self.position;
+        self.inner.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.last_read_at = self.position;
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
 
 /// Node
 ///
@@ -82,10 +496,10 @@ use crate::type_utils::ArqRead;
 ///   are "de-duplicated".
 ///
 /// - "<acl_blob_key>" is the SHA1 of the blob containing the result of acl_to_text() on
-/// the file's ACL. Note this means the ACLs are "de-duplicated".
+///   the file's ACL. Note this means the ACLs are "de-duplicated".
 ///
 /// - "create_time_sec" and "create_time_nsec" contain the value of the ATTR_CMN_CRTIME
-/// attribute of the file
+///   attribute of the file
 ///
 ///
 /// XAttrSet Format
@@ -101,6 +515,7 @@ use crate::type_utils::ArqRead;
 ///         [Data:xattr_data]
 ///     )
 /// ```
+#[derive(Clone, PartialEq, Debug)]
 pub struct Node {
     pub is_tree: bool,
     pub tree_contains_missing_items: bool,
@@ -109,6 +524,14 @@ pub struct Node {
     pub acl_compression_type: CompressionType,
     pub data_blob_keys: Vec<blob::BlobKey>,
     pub data_size: u64,
+    /// Only present for Tree version 18 or earlier (never used by Arq)
+    pub thumbnail_sha1: Option<String>,
+    /// Only present for Tree version 14 to 18
+    pub is_thumbnail_encryption_key_stretched: Option<bool>,
+    /// Only present for Tree version 18 or earlier (never used by Arq)
+    pub preview_sha1: Option<String>,
+    /// Only present for Tree version 14 to 18
+    pub is_preview_encryption_key_stretched: Option<bool>,
     pub xattrs_blob_key: Option<blob::BlobKey>,
     pub xattrs_size: u64,
     pub acl_blob_key: Option<blob::BlobKey>,
@@ -136,25 +559,56 @@ pub struct Node {
 }
 
 impl Node {
-    pub fn new<R: ArqRead + BufRead>(mut reader: R) -> Result<Node> {
+    /// Parses a node laid out as in the current (version 22) tree format.
+    pub fn new<R: ArqRead + BufRead>(reader: R) -> Result<Node> {
+        Node::new_with_version(reader, CURRENT_TREE_VERSION)
+    }
+
+    /// Parses a node belonging to a tree of the given `version`.
+    pub fn new_with_version<R: ArqRead + BufRead>(reader: R, version: u32) -> Result<Node> {
+        let mut reader = CountingReader::new(reader);
+        Node::read(&mut reader, TreeVersion::from(version)).map_err(|err| reader.tag(err))
+    }
+
+    fn read<R: BufRead>(reader: &mut CountingReader<R>, version: TreeVersion) -> Result<Node> {
         let is_tree = reader.read_arq_bool()?;
-        let tree_contains_missing_items = reader.read_arq_bool()?;
-        let data_compression_type = reader.read_arq_compression_type()?;
-        let xattrs_compression_type = reader.read_arq_compression_type()?;
-        let acl_compression_type = reader.read_arq_compression_type()?;
+        let tree_contains_missing_items = if version.has_missing_items_flag() {
+            reader.read_arq_bool()?
+        } else {
+            false
+        };
+        let data_compression_type = read_compression(reader, version)?;
+        let xattrs_compression_type = read_compression(reader, version)?;
+        let acl_compression_type = read_compression(reader, version)?;
         let mut data_blob_keys_count = reader.read_arq_i32()?;
 
         let mut data_blob_keys = Vec::new();
         while data_blob_keys_count > 0 {
-            if let Some(data_blob_key) = blob::BlobKey::new(&mut reader)? {
+            if let Some(data_blob_key) = blob::BlobKey::new(&mut *reader)? {
                 data_blob_keys.push(data_blob_key);
                 data_blob_keys_count -= 1;
             }
         }
         let data_size = reader.read_arq_u64()?;
-        let xattrs_blob_key = blob::BlobKey::new(&mut reader)?;
+
+        let mut thumbnail_sha1 = None;
+        let mut is_thumbnail_encryption_key_stretched = None;
+        let mut preview_sha1 = None;
+        let mut is_preview_encryption_key_stretched = None;
+        if version.has_thumbnails() {
+            thumbnail_sha1 = Some(reader.read_arq_string()?);
+            if version.has_thumbnail_stretched_flags() {
+                is_thumbnail_encryption_key_stretched = Some(reader.read_arq_bool()?);
+            }
+            preview_sha1 = Some(reader.read_arq_string()?);
+            if version.has_thumbnail_stretched_flags() {
+                is_preview_encryption_key_stretched = Some(reader.read_arq_bool()?);
+            }
+        }
+
+        let xattrs_blob_key = blob::BlobKey::new(&mut *reader)?;
         let xattrs_size = reader.read_arq_u64()?;
-        let acl_blob_key = blob::BlobKey::new(&mut reader)?;
+        let acl_blob_key = blob::BlobKey::new(&mut *reader)?;
         let uid = reader.read_arq_i32()?;
         let gid = reader.read_arq_i32()?;
         let mode = reader.read_arq_i32()?;
@@ -185,6 +639,10 @@ impl Node {
             acl_compression_type,
             data_blob_keys,
             data_size,
+            thumbnail_sha1,
+            is_thumbnail_encryption_key_stretched,
+            preview_sha1,
+            is_preview_encryption_key_stretched,
             xattrs_blob_key,
             xattrs_size,
             acl_blob_key,
@@ -211,6 +669,474 @@ impl Node {
             st_blksize,
         })
     }
+
+    /// Writes the node laid out as in the current (version 22) tree format, which is what
+    /// [Node::new] reads back. Fields only found in older versions are left out.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_arq_bool(self.is_tree)?;
+        writer.write_arq_bool(self.tree_contains_missing_items)?;
+        writer.write_arq_compression_type(&self.data_compression_type)?;
+        writer.write_arq_compression_type(&self.xattrs_compression_type)?;
+        writer.write_arq_compression_type(&self.acl_compression_type)?;
+        writer.write_arq_i32(self.data_blob_keys.len() as i32)?;
+        for blob_key in &self.data_blob_keys {
+            blob::BlobKey::write(Some(blob_key), writer)?;
+        }
+        writer.write_arq_u64(self.data_size)?;
+        blob::BlobKey::write(self.xattrs_blob_key.as_ref(), writer)?;
+        writer.write_arq_u64(self.xattrs_size)?;
+        blob::BlobKey::write(self.acl_blob_key.as_ref(), writer)?;
+        writer.write_arq_i32(self.uid)?;
+        writer.write_arq_i32(self.gid)?;
+        writer.write_arq_i32(self.mode)?;
+        writer.write_arq_i64(self.mtime_sec)?;
+        writer.write_arq_i64(self.mtime_nsec)?;
+        writer.write_arq_i64(self.flags)?;
+        writer.write_arq_i32(self.finder_flags)?;
+        writer.write_arq_i32(self.extended_finder_flags)?;
+        writer.write_arq_string(&self.finder_file_type)?;
+        writer.write_arq_string(&self.finder_file_creator)?;
+        writer.write_arq_bool(self.is_file_extension_hidden)?;
+        writer.write_arq_i32(self.st_dev)?;
+        writer.write_arq_i32(self.st_ino)?;
+        writer.write_arq_u32(self.st_nlink)?;
+        writer.write_arq_i32(self.st_rdev)?;
+        writer.write_arq_i64(self.ctime_sec)?;
+        writer.write_arq_i64(self.ctime_nsec)?;
+        writer.write_arq_i64(self.create_time_sec)?;
+        writer.write_arq_i64(self.create_time_nsec)?;
+        writer.write_arq_i64(self.st_blocks)?;
+        writer.write_arq_u32(self.st_blksize)?;
+        Ok(())
+    }
+
+    /// The node's key fields as JSON, see [Tree::to_json_value].
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let data_compression_type = match self.data_compression_type {
+            CompressionType::None => "None",
+            CompressionType::Gzip => "Gzip",
+            CompressionType::LZ4 => "LZ4",
+        };
+        serde_json::json!({
+            "is_tree": self.is_tree,
+            "data_sha1s": self.data_sha1s(),
+            "data_size": self.data_size,
+            "data_compression_type": data_compression_type,
+            "xattrs_sha1": self.xattrs_blob_key.as_ref().map(|key| &key.sha1),
+            "acl_sha1": self.acl_blob_key.as_ref().map(|key| &key.sha1),
+            "uid": self.uid,
+            "gid": self.gid,
+            "mode": self.mode,
+            "mtime_sec": self.mtime_sec,
+            "mtime_nsec": self.mtime_nsec,
+            "ctime_sec": self.ctime_sec,
+            "ctime_nsec": self.ctime_nsec,
+            "create_time_sec": self.create_time_sec,
+            "create_time_nsec": self.create_time_nsec,
+            "st_ino": self.st_ino,
+            "st_nlink": self.st_nlink,
+        })
+    }
+
+    /// Whether this node is a character or block device.
+    pub fn is_device(&self) -> bool {
+        matches!(self.mode & S_IFMT, S_IFCHR | S_IFBLK)
+    }
+
+    /// Whether this node is a symbolic link, whose data is the path it points to.
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    /// Whether the file had other hard links pointing at it when it was backed up.
+    pub fn is_hardlinked(&self) -> bool {
+        !self.is_tree && self.st_nlink > 1
+    }
+
+    /// Major and minor device numbers, for device nodes.
+    ///
+    /// `st_rdev` is decoded the way macOS encodes a `dev_t`: the major number in the top
+    /// 8 bits and the minor number in the lower 24.
+    pub fn device_ids(&self) -> Option<(u32, u32)> {
+        if !self.is_device() {
+            return None;
+        }
+        let rdev = self.st_rdev as u32;
+        Some((rdev >> 24, rdev & 0x00ff_ffff))
+    }
+
+    /// The node's Finder type and creator codes and flags, for re-applying them as the
+    /// [FINDER_INFO_XATTR] xattr on restore.
+    ///
+    /// Returns `None` if there's nothing to apply (no codes and no flags), or if a code
+    /// isn't four bytes long.
+    pub fn finder_info(&self) -> Option<FinderInfo> {
+        let info = FinderInfo {
+            file_type: four_char_code(&self.finder_file_type)?,
+            file_creator: four_char_code(&self.finder_file_creator)?,
+            finder_flags: self.finder_flags as u16,
+            extended_finder_flags: self.extended_finder_flags as u16,
+        };
+        if info == FinderInfo::default() {
+            return None;
+        }
+        Some(info)
+    }
+
+    /// `finder_flags` decoded. Only the low 16 bits are used by the Finder.
+    pub fn finder_flags_decoded(&self) -> FinderFlags {
+        FinderFlags::from_bits_retain(self.finder_flags as u16)
+    }
+
+    /// `extended_finder_flags` decoded. Only the low 16 bits are used by the Finder.
+    pub fn extended_finder_flags_decoded(&self) -> ExtendedFinderFlags {
+        ExtendedFinderFlags::from_bits_retain(self.extended_finder_flags as u16)
+    }
+
+    /// SHA1s of the node's data blobs, in order.
+    pub fn data_sha1s(&self) -> Vec<&str> {
+        self.data_blob_keys
+            .iter()
+            .map(|blob_key| blob_key.sha1.as_str())
+            .collect()
+    }
+
+    /// A reader over the node's contents that resolves its blobs as they're reached, for
+    /// files too large to [reassemble](Node::reassemble) in memory.
+    ///
+    /// The first blob is resolved straight away, so a missing object is reported here.
+    /// Running out of blobs before `data_size` bytes fails the read with an
+    /// `ErrorKind::InvalidData` I/O error.
+    pub fn reader<'a, B: BlobResolver>(&'a self, resolver: &'a B) -> Result<ConcatReader<'a, B>> {
+        let mut reader = ConcatReader {
+            node: self,
+            resolver,
+            ends: Vec::new(),
+            index: 0,
+            current: Vec::new(),
+            position: 0,
+        };
+        if !self.data_blob_keys.is_empty() {
+            reader.load(0)?;
+        }
+        Ok(reader)
+    }
+
+    /// Reassembles the contents of a file node from its data blobs.
+    ///
+    /// Each blob is resolved, decompressed according to `data_compression_type` and
+    /// appended in order. Arq records a single compression type per node and compresses
+    /// every blob of a file the same way, so there's nothing per-blob to look at; what can
+    /// go wrong is a blob decompressing to something other than what was backed up, which
+    /// is caught by checking the reassembled length against `data_size` and reported as
+    /// `Error::SizeMismatch`.
+    ///
+    /// This isn't meaningful for tree nodes, whose only blob is the subtree (see
+    /// [Node::subtree]).
+    pub fn reassemble<B: BlobResolver>(&self, resolver: &B) -> Result<Vec<u8>> {
+        // data_size comes straight from the tree, so it only bounds the preallocation.
+        let capacity = self.data_size.min(MAX_PREALLOCATION as u64) as usize;
+        let mut contents = Vec::with_capacity(capacity);
+        for blob_key in &self.data_blob_keys {
+            let blob = resolver.resolve(&blob_key.sha1)?;
+            contents.extend(CompressionType::decompress(
+                &blob,
+                self.data_compression_type.clone(),
+            )?);
+            if contents.len() as u64 > self.data_size {
+                break;
+            }
+        }
+
+        if contents.len() as u64 != self.data_size {
+            return Err(Error::SizeMismatch {
+                expected: self.data_size,
+                actual: contents.len() as u64,
+            });
+        }
+        Ok(contents)
+    }
+
+    /// Resolves and parses the tree this node points to.
+    ///
+    /// For nodes with `is_tree` set, the first data blob key holds the SHA1 of the subtree.
+    /// Returns `Ok(None)` for file nodes.
+    pub fn subtree<B: BlobResolver>(&self, resolver: &B) -> Result<Option<Tree>> {
+        Ok(self.read_subtree(resolver)?.map(|(tree, _)| tree))
+    }
+
+    /// Resolves and parses the node's extended attributes, decompressing them according
+    /// to `xattrs_compression_type`. Returns `Ok(None)` if the node has none.
+    pub fn xattrs<B: BlobResolver>(&self, resolver: &B) -> Result<Option<XAttrSet>> {
+        let blob_key = match &self.xattrs_blob_key {
+            Some(blob_key) => blob_key,
+            None => return Ok(None),
+        };
+        let blob = resolver.resolve(&blob_key.sha1)?;
+        let content = CompressionType::decompress(&blob, self.xattrs_compression_type.clone())?;
+        Ok(Some(XAttrSet::new(std::io::Cursor::new(content))?))
+    }
+
+    /// Same as [Node::subtree], also returning the size of the object that was read.
+    fn read_subtree<B: BlobResolver>(&self, resolver: &B) -> Result<Option<(Tree, u64)>> {
+        if !self.is_tree {
+            return Ok(None);
+        }
+        let sha1 = match self.data_blob_keys.first() {
+            Some(blob_key) => &blob_key.sha1,
+            None => return Err(Error::ParseError),
+        };
+        let content = resolver.resolve(sha1)?;
+        let tree = Tree::new(&content, self.data_compression_type.clone())?;
+        Ok(Some((tree, content.len() as u64)))
+    }
+}
+
+/// A node's extended attributes, laid out as described under "XAttrSet Format" in
+/// [Node].
+///
+/// Values are kept as raw bytes: plenty of xattrs (`com.apple.FinderInfo`,
+/// `com.apple.ResourceFork`, ...) aren't text.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct XAttrSet {
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl XAttrSet {
+    pub fn new<R: ArqRead>(mut reader: R) -> Result<XAttrSet> {
+        let header = read_header(&mut reader, 12, "xattr set")?;
+        if header != XATTR_SET_HEADER {
+            return Err(Error::ParseError);
+        }
+        let mut count = reader.read_arq_u64()?;
+        let mut xattrs = BTreeMap::new();
+        while count > 0 {
+            let name = reader.read_arq_string()?;
+            let value = reader.read_arq_data()?;
+            xattrs.insert(name, value);
+            count -= 1;
+        }
+        Ok(XAttrSet { xattrs })
+    }
+
+    /// Writes the set in the format [XAttrSet::new] reads, names in sorted order.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(XATTR_SET_HEADER)?;
+        writer.write_arq_u64(self.xattrs.len() as u64)?;
+        for (name, value) in &self.xattrs {
+            writer.write_arq_string(name)?;
+            writer.write_arq_data(value)?;
+        }
+        Ok(())
+    }
+}
+
+const XATTR_SET_HEADER: &[u8] = b"XAttrSetV002";
+
+bitflags::bitflags! {
+    /// The known bits of a node's `finder_flags`, as defined in the Carbon `Finder.h`.
+    ///
+    /// Unknown bits are kept, so converting back gives the original value.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct FinderFlags: u16 {
+        const IS_ON_DESK = 0x0001;
+        /// The 3-bit label color, not a single flag.
+        const COLOR = 0x000e;
+        const IS_SHARED = 0x0040;
+        const HAS_NO_INITS = 0x0080;
+        const HAS_BEEN_INITED = 0x0100;
+        const HAS_CUSTOM_ICON = 0x0400;
+        const IS_STATIONERY = 0x0800;
+        const NAME_LOCKED = 0x1000;
+        const HAS_BUNDLE = 0x2000;
+        const IS_INVISIBLE = 0x4000;
+        const IS_ALIAS = 0x8000;
+    }
+}
+
+bitflags::bitflags! {
+    /// The known bits of a node's `extended_finder_flags`, as defined in the Carbon
+    /// `Finder.h`.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct ExtendedFinderFlags: u16 {
+        const HAS_ROUTING_INFO = 0x0004;
+        const OBJECT_IS_BUSY = 0x0080;
+        const HAS_CUSTOM_BADGE = 0x0100;
+        /// The other extended flags should be ignored.
+        const ARE_INVALID = 0x8000;
+    }
+}
+
+/// Name of the extended attribute macOS keeps a file's [FinderInfo] in.
+pub const FINDER_INFO_XATTR: &str = "com.apple.FinderInfo";
+
+/// Classic Mac OS Finder metadata of a file, see [Node::finder_info].
+///
+/// Absent type or creator codes are all zeros, which is how the Finder stores them too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FinderInfo {
+    pub file_type: [u8; 4],
+    pub file_creator: [u8; 4],
+    pub finder_flags: u16,
+    pub extended_finder_flags: u16,
+}
+
+impl FinderInfo {
+    /// The 32 bytes of the [FINDER_INFO_XATTR] xattr: a `FileInfo` (type, creator, flags
+    /// and the unset location and reserved fields) followed by an `ExtendedFileInfo`,
+    /// whose only set field is the extended flags at offset 24.
+    pub fn to_xattr(&self) -> [u8; 32] {
+        let mut xattr = [0u8; 32];
+        xattr[..4].copy_from_slice(&self.file_type);
+        xattr[4..8].copy_from_slice(&self.file_creator);
+        xattr[8..10].copy_from_slice(&self.finder_flags.to_be_bytes());
+        xattr[24..26].copy_from_slice(&self.extended_finder_flags.to_be_bytes());
+        xattr
+    }
+}
+
+/// Decodes a four character type or creator code, an empty string meaning none.
+fn four_char_code(code: &str) -> Option<[u8; 4]> {
+    if code.is_empty() {
+        return Some([0; 4]);
+    }
+    code.as_bytes().try_into().ok()
+}
+
+/// Reads a file's contents blob by blob, see [Node::reader].
+///
+/// Only one blob is held (decompressed) at a time. Blobs are resolved as the read position
+/// reaches them; seeking backwards resolves the blob holding the new position again.
+pub struct ConcatReader<'a, B> {
+    node: &'a Node,
+    resolver: &'a B,
+    /// Offset in the file where each blob loaded so far ends
+    ends: Vec<u64>,
+    /// Index of the blob in `current`
+    index: usize,
+    current: Vec<u8>,
+    position: u64,
+}
+
+impl<'a, B: BlobResolver> ConcatReader<'a, B> {
+    /// Offset in the file where blob `index` starts. Only valid for blobs already loaded
+    /// once (or the one right after).
+    fn start_of(&self, index: usize) -> u64 {
+        if index == 0 {
+            0
+        } else {
+            self.ends[index - 1]
+        }
+    }
+
+    /// Resolves and decompresses blob `index`, recording where it ends.
+    fn load(&mut self, index: usize) -> Result<()> {
+        let blob_key = &self.node.data_blob_keys[index];
+        let blob = self.resolver.resolve(&blob_key.sha1)?;
+        self.current = CompressionType::decompress(&blob, self.node.data_compression_type.clone())?;
+        self.index = index;
+        if index == self.ends.len() {
+            let end = self.start_of(index) + self.current.len() as u64;
+            self.ends.push(end);
+        }
+        Ok(())
+    }
+
+    /// Makes `current` the blob holding `position`. Returns false at the end of the file.
+    fn seek_blob(&mut self) -> Result<bool> {
+        let in_current = self.index < self.ends.len()
+            && self.start_of(self.index) <= self.position
+            && self.position < self.ends[self.index];
+        if in_current {
+            return Ok(true);
+        }
+
+        if let Some(index) = self.ends.iter().position(|end| self.position < *end) {
+            self.load(index)?;
+            return Ok(true);
+        }
+        while self.ends.len() < self.node.data_blob_keys.len() {
+            self.load(self.ends.len())?;
+            if self.position < self.ends[self.index] {
+                return Ok(true);
+            }
+        }
+
+        let total = self.ends.last().copied().unwrap_or(0);
+        if total != self.node.data_size {
+            return Err(Error::SizeMismatch {
+                expected: self.node.data_size,
+                actual: total,
+            });
+        }
+        Ok(false)
+    }
+}
+
+impl<B: BlobResolver> std::io::Read for ConcatReader<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let found = self.seek_blob().map_err(|err| match err {
+            Error::IoError(err) => err,
+            err => std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()),
+        })?;
+        if !found || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let offset = (self.position - self.start_of(self.index)) as usize;
+        let available = &self.current[offset..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<B: BlobResolver> Seek for ConcatReader<'_, B> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => self.node.data_size.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Progress of a traversal, reported after each node is visited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Progress {
+    /// Nodes visited so far
+    pub nodes: u64,
+    /// Bytes of tree objects read through the resolver so far
+    pub bytes_read: u64,
+}
+
+/// State shared by all the levels of a traversal.
+struct Walk<'a> {
+    skip_missing: bool,
+    /// Paths skipped, along with everything under them
+    ignored: &'a [PathBuf],
+    progress: Progress,
+    on_progress: Option<&'a mut dyn FnMut(Progress)>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(skip_missing: bool, on_progress: Option<&'a mut dyn FnMut(Progress)>) -> Self {
+        Walk {
+            skip_missing,
+            ignored: &[],
+            progress: Progress::default(),
+            on_progress,
+        }
+    }
 }
 
 /// Tree
@@ -223,8 +1149,6 @@ impl Node {
 ///     [CompressionType:xattrs_compression_type] /* present for Tree version >= 19; indicates Gzip compression or none */
 ///     [Bool:acl_is_compressed] /* present for Tree versions 12-18 */
 ///     [CompressionType:acl_compression_type] /* present for Tree version >= 19; indicates Gzip compression or none */
-///     [Int32:xattrs_compression_type] /* present for Tree version >= 20; older Trees are gzip compression type */
-///     [Int32:acl_compression_type] /* present for Tree version >= 20; older Trees are gzip compression type */
 ///     [BlobKey:xattrs_blob_key] /* null if directory has no xattrs */
 ///     [UInt64:xattrs_size]
 ///     [BlobKey:acl_blob_key] /* null if directory has no acl */
@@ -257,6 +1181,12 @@ impl Node {
 ///         [Node]
 ///     )   /* repeat <node_count> times */
 /// ```
+///
+/// Arq's description of the format also lists `[Int32:xattrs_compression_type]` and
+/// `[Int32:acl_compression_type]` fields for Tree version 20 and later, but trees
+/// written by Arq don't have them: they're the `CompressionType` fields above, which
+/// are serialized as an Int32.
+#[derive(Clone, PartialEq, Debug)]
 pub struct Tree {
     pub version: u32,
     pub xattrs_compression_type: CompressionType,
@@ -282,6 +1212,8 @@ pub struct Tree {
     pub create_time_nsec: i64,
     pub st_blocks: i64,
     pub st_blksize: u32,
+    /// Only present for versions 11 to 16.
+    pub aggregate_size_on_disk: Option<u64>,
     pub missing_nodes: Vec<String>,
     pub nodes: HashMap<String, Node>,
 }
@@ -297,17 +1229,37 @@ impl Tree {
     /// assert_eq!(tree.version, 22);
     /// ```
     pub fn new(compressed_content: &[u8], compression_type: CompressionType) -> Result<Tree> {
-        let content = CompressionType::decompress(compressed_content, compression_type)?;
-        let mut reader = BufReader::new(std::io::Cursor::new(content));
-        let tree_header = reader.read_bytes(8)?;
-        assert_eq!(tree_header[..5], [84, 114, 101, 101, 86]);
+        if compressed_content.is_empty() {
+            return Err(Error::UnexpectedEof {
+                while_reading: "tree",
+            });
+        }
+        let labeled_uncompressed = compression_type == CompressionType::None;
+        let mut content = CompressionType::decompress(compressed_content, compression_type)?;
+        // Some trees are gzipped even though their node or commit says they aren't.
+        if labeled_uncompressed && !content.starts_with(b"TreeV") {
+            content = CompressionType::decompress_auto(&content)?;
+        }
+        let mut reader = CountingReader::new(BufReader::new(std::io::Cursor::new(content)));
+        let tree_header = read_header(&mut reader, 8, "tree")?;
+        if tree_header[..5] != [84, 114, 101, 101, 86] {
+            return Err(Error::ParseError);
+        }
         let version = std::str::from_utf8(&tree_header[5..])?.parse::<u32>()?;
 
-        let xattrs_compression_type = reader.read_arq_compression_type()?;
-        let acl_compression_type = reader.read_arq_compression_type()?;
-        let xattrs_blob_key = blob::BlobKey::new(&mut reader)?;
+        Tree::read(&mut reader, version).map_err(|err| match err {
+            Error::TruncatedTree { .. } => err,
+            _ => reader.tag(err),
+        })
+    }
+
+    fn read<R: BufRead>(reader: &mut CountingReader<R>, version: u32) -> Result<Tree> {
+        let tree_version = TreeVersion::from(version);
+        let xattrs_compression_type = read_compression(reader, tree_version)?;
+        let acl_compression_type = read_compression(reader, tree_version)?;
+        let xattrs_blob_key = blob::BlobKey::new(&mut *reader)?;
         let xattrs_size = reader.read_arq_u64()?; //TODO(nlopes): what is this used for?
-        let acl_blob_key = blob::BlobKey::new(&mut reader)?;
+        let acl_blob_key = blob::BlobKey::new(&mut *reader)?;
         let uid = reader.read_arq_i32()?;
         let gid = reader.read_arq_i32()?;
         let mode = reader.read_arq_i32()?;
@@ -324,9 +1276,21 @@ impl Tree {
         let ctime_nsec = reader.read_arq_i64()?;
         let st_blocks = reader.read_arq_i64()?;
         let st_blksize = reader.read_arq_u32()?;
-        let create_time_sec = reader.read_arq_i64()?;
-        let create_time_nsec = reader.read_arq_i64()?;
-        let mut missing_node_count = reader.read_arq_u32()?;
+        let aggregate_size_on_disk = if tree_version.has_aggregate_size_on_disk() {
+            Some(reader.read_arq_u64()?)
+        } else {
+            None
+        };
+        let (create_time_sec, create_time_nsec) = if tree_version.has_create_time() {
+            (reader.read_arq_i64()?, reader.read_arq_i64()?)
+        } else {
+            (0, 0)
+        };
+        let mut missing_node_count = if tree_version.has_missing_items_flag() {
+            reader.read_arq_u32()?
+        } else {
+            0
+        };
 
         let mut missing_nodes = Vec::new();
         while missing_node_count > 0 {
@@ -335,15 +1299,27 @@ impl Tree {
             missing_node_count -= 1;
         }
 
-        let mut node_count = reader.read_arq_u32()?;
+        let node_count = reader.read_arq_u32()?;
         let mut nodes = HashMap::new();
-        while node_count > 0 {
-            let node_name = reader.read_arq_string()?;
-            assert!(!node_name.is_empty());
-
-            let node = Node::new(&mut reader)?;
-            nodes.insert(node_name, node);
-            node_count -= 1;
+        for parsed in 0..node_count {
+            let node = reader.read_arq_string().and_then(|node_name| {
+                if node_name.is_empty() {
+                    return Err(Error::ParseError);
+                }
+                Ok((node_name, Node::read(reader, tree_version)?))
+            });
+            match node {
+                Ok((node_name, node)) => {
+                    nodes.insert(node_name, node);
+                }
+                Err(err) if is_eof(&err) => {
+                    return Err(Error::TruncatedTree {
+                        expected: node_count,
+                        got: parsed,
+                    })
+                }
+                Err(err) => return Err(err),
+            }
         }
 
         Ok(Tree {
@@ -369,12 +1345,261 @@ impl Tree {
             ctime_nsec,
             st_blocks,
             st_blksize,
+            aggregate_size_on_disk,
             create_time_sec,
             create_time_nsec,
             missing_nodes,
             nodes,
         })
     }
+
+    /// The tree's format version as a [TreeVersion].
+    pub fn tree_version(&self) -> TreeVersion {
+        TreeVersion::from(self.version)
+    }
+
+    /// Writes the tree, uncompressed, in the current (version 22) tree format, which is
+    /// what [Tree::new] reads back with `CompressionType::None`.
+    ///
+    /// Nodes are written in name order. A tree without missing nodes or without nodes
+    /// (an empty directory) gets zero counts.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(b"TreeV022")?;
+        writer.write_arq_compression_type(&self.xattrs_compression_type)?;
+        writer.write_arq_compression_type(&self.acl_compression_type)?;
+        blob::BlobKey::write(self.xattrs_blob_key.as_ref(), writer)?;
+        writer.write_arq_u64(self.xattrs_size)?;
+        blob::BlobKey::write(self.acl_blob_key.as_ref(), writer)?;
+        writer.write_arq_i32(self.uid)?;
+        writer.write_arq_i32(self.gid)?;
+        writer.write_arq_i32(self.mode)?;
+        writer.write_arq_i64(self.mtime_sec)?;
+        writer.write_arq_i64(self.mtime_nsec)?;
+        writer.write_arq_i64(self.flags)?;
+        writer.write_arq_i32(self.finder_flags)?;
+        writer.write_arq_i32(self.extended_finder_flags)?;
+        writer.write_arq_i32(self.st_dev)?;
+        writer.write_arq_i32(self.st_ino)?;
+        writer.write_arq_u32(self.st_nlink)?;
+        writer.write_arq_i32(self.st_rdev)?;
+        writer.write_arq_i64(self.ctime_sec)?;
+        writer.write_arq_i64(self.ctime_nsec)?;
+        writer.write_arq_i64(self.st_blocks)?;
+        writer.write_arq_u32(self.st_blksize)?;
+        writer.write_arq_i64(self.create_time_sec)?;
+        writer.write_arq_i64(self.create_time_nsec)?;
+
+        writer.write_arq_u32(self.missing_nodes.len() as u32)?;
+        for name in &self.missing_nodes {
+            writer.write_arq_string(name)?;
+        }
+
+        let mut names: Vec<&String> = self.nodes.keys().collect();
+        names.sort();
+        writer.write_arq_u32(names.len() as u32)?;
+        for name in names {
+            writer.write_arq_string(name)?;
+            self.nodes[name].write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// `finder_flags` decoded, see [Node::finder_flags_decoded].
+    pub fn finder_flags_decoded(&self) -> FinderFlags {
+        FinderFlags::from_bits_retain(self.finder_flags as u16)
+    }
+
+    /// `extended_finder_flags` decoded, see [Node::extended_finder_flags_decoded].
+    pub fn extended_finder_flags_decoded(&self) -> ExtendedFinderFlags {
+        ExtendedFinderFlags::from_bits_retain(self.extended_finder_flags as u16)
+    }
+
+    /// Same as [Tree::write], into a new buffer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// The tree's key fields as JSON, for scripting and ad-hoc queries.
+    ///
+    /// The shape is stable: the tree's own fields at the top level and its nodes in a
+    /// `nodes` object keyed by name (see [Node::to_json_value]). Subtrees aren't followed;
+    /// directories list the SHA1 of their tree in `data_sha1s`.
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let nodes: serde_json::Map<String, serde_json::Value> = self
+            .nodes
+            .iter()
+            .map(|(name, node)| (name.clone(), node.to_json_value()))
+            .collect();
+        serde_json::json!({
+            "version": self.version,
+            "uid": self.uid,
+            "gid": self.gid,
+            "mode": self.mode,
+            "mtime_sec": self.mtime_sec,
+            "mtime_nsec": self.mtime_nsec,
+            "missing_nodes": self.missing_nodes,
+            "nodes": nodes,
+        })
+    }
+
+    /// Visits every node in this tree and, recursively, in all its subtrees.
+    ///
+    /// Nodes are visited depth-first, in name order, with their path relative to this
+    /// tree. Directories are visited before their contents.
+    pub fn walk<B, F>(&self, resolver: &B, mut f: F) -> Result<()>
+    where
+        B: BlobResolver,
+        F: FnMut(&Path, &Node) -> Result<()>,
+    {
+        self.walk_inner(resolver, Path::new(""), &mut Walk::new(false, None), &mut f)
+    }
+
+    /// Same as [Tree::walk], calling `on_progress` after each node is visited.
+    pub fn walk_with_progress<B, F>(
+        &self,
+        resolver: &B,
+        mut f: F,
+        on_progress: &mut dyn FnMut(Progress),
+    ) -> Result<()>
+    where
+        B: BlobResolver,
+        F: FnMut(&Path, &Node) -> Result<()>,
+    {
+        let mut walk = Walk::new(false, Some(on_progress));
+        self.walk_inner(resolver, Path::new(""), &mut walk, &mut f)
+    }
+
+    /// Same as [Tree::walk], skipping the nodes at `ignored` paths and, for directories,
+    /// everything under them.
+    ///
+    /// This is what Arq leaves out of backups, see [crate::folder::Folder::ignored_paths].
+    pub fn walk_ignoring<B, F>(&self, resolver: &B, ignored: &[PathBuf], mut f: F) -> Result<()>
+    where
+        B: BlobResolver,
+        F: FnMut(&Path, &Node) -> Result<()>,
+    {
+        let mut walk = Walk::new(false, None);
+        walk.ignored = ignored;
+        self.walk_inner(resolver, Path::new(""), &mut walk, &mut f)
+    }
+
+    /// The first node, in [Tree::walk] order, that `predicate` matches, along with its
+    /// path.
+    ///
+    /// The search stops there: subtrees after the match are never resolved.
+    pub fn find<B, F>(&self, resolver: &B, predicate: F) -> Result<Option<(PathBuf, Node)>>
+    where
+        B: BlobResolver,
+        F: Fn(&Path, &Node) -> bool,
+    {
+        self.find_inner(resolver, Path::new(""), &predicate)
+    }
+
+    fn find_inner<B, F>(
+        &self,
+        resolver: &B,
+        parent: &Path,
+        predicate: &F,
+    ) -> Result<Option<(PathBuf, Node)>>
+    where
+        B: BlobResolver,
+        F: Fn(&Path, &Node) -> bool,
+    {
+        let mut names: Vec<&String> = self.nodes.keys().collect();
+        names.sort();
+
+        for name in names {
+            let node = &self.nodes[name];
+            let path = parent.join(name);
+            if predicate(&path, node) {
+                return Ok(Some((path, node.clone())));
+            }
+            if let Some(subtree) = node.subtree(resolver)? {
+                if let Some(found) = subtree.find_inner(resolver, &path, predicate)? {
+                    return Ok(Some(found));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn walk_inner<B, F>(
+        &self,
+        resolver: &B,
+        parent: &Path,
+        walk: &mut Walk,
+        f: &mut F,
+    ) -> Result<()>
+    where
+        B: BlobResolver,
+        F: FnMut(&Path, &Node) -> Result<()>,
+    {
+        let mut names: Vec<&String> = self.nodes.keys().collect();
+        names.sort();
+
+        for name in names {
+            let node = &self.nodes[name];
+            let path = parent.join(name);
+            if walk.ignored.iter().any(|ignored| path.starts_with(ignored)) {
+                continue;
+            }
+            f(&path, node)?;
+
+            let subtree = match node.read_subtree(resolver) {
+                Ok(subtree) => subtree,
+                Err(Error::ObjectNotFound(_)) if walk.skip_missing => None,
+                Err(err) => return Err(err),
+            };
+
+            walk.progress.nodes += 1;
+            if let Some((_, size)) = subtree {
+                walk.progress.bytes_read += size;
+            }
+            if let Some(on_progress) = walk.on_progress.as_mut() {
+                on_progress(walk.progress);
+            }
+
+            if let Some((subtree, _)) = subtree {
+                subtree.walk_inner(resolver, &path, walk, f)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sum of `data_size` for every file in this tree and its subtrees.
+    ///
+    /// Fails with `Error::ObjectNotFound` if a subtree can't be resolved.
+    pub fn total_size<B: BlobResolver>(&self, resolver: &B) -> Result<u64> {
+        self.sum_sizes(resolver, &mut Walk::new(false, None))
+    }
+
+    /// Same as [Tree::total_size], but subtrees that can't be resolved count as empty.
+    pub fn total_size_skipping_missing<B: BlobResolver>(&self, resolver: &B) -> Result<u64> {
+        self.sum_sizes(resolver, &mut Walk::new(true, None))
+    }
+
+    /// Same as [Tree::total_size], calling `on_progress` after each node is visited.
+    pub fn total_size_with_progress<B: BlobResolver>(
+        &self,
+        resolver: &B,
+        on_progress: &mut dyn FnMut(Progress),
+    ) -> Result<u64> {
+        self.sum_sizes(resolver, &mut Walk::new(false, Some(on_progress)))
+    }
+
+    fn sum_sizes<B: BlobResolver>(&self, resolver: &B, walk: &mut Walk) -> Result<u64> {
+        let mut total = 0;
+        self.walk_inner(resolver, Path::new(""), walk, &mut |_, node| {
+            if !node.is_tree {
+                total += node.data_size;
+            }
+            Ok(())
+        })?;
+        Ok(total)
+    }
 }
 
 pub type ParentCommits = HashMap<String, bool>;
@@ -409,7 +1634,7 @@ pub type FailedFile = (String, String);
 ///     )   /* repeat num_failed_files times */
 ///     [Bool:has_missing_nodes] /* only present for Commit version 8 or later */
 ///     [Bool:is_complete] /* only present for Commit version 9 or later */
-///     [Data:config_plist_xml] /* a copy of the XML file as described above */
+///     [Data:config_plist_xml] /* a copy of the XML file as described above; only present for Commit version 5 or later */
 ///     [String:arq_version] /* the version of the Arq app that created this Commit */
 /// ```
 ///
@@ -422,6 +1647,9 @@ pub type FailedFile = (String, String);
 /// created (the filename is a timestamp). It's a plist containing the previous and current
 /// Commit SHA1s, the SHA1 of the pack file containing the new Commit, and whether the new
 /// Commit is a "rewrite" (because the user deleted a backup record for instance).
+///
+/// Both are read through [crate::bucketdata::BucketData].
+#[derive(Clone, PartialEq, Debug)]
 pub struct Commit {
     pub version: u32,
     pub author: String,
@@ -435,42 +1663,256 @@ pub struct Commit {
     pub failed_files: Vec<FailedFile>,
     pub has_missing_nodes: bool,
     pub is_complete: bool,
-    pub config_plist_xml: Vec<u8>,
+    /// Only present for Commit version 5 or later.
+    pub config_plist_xml: Option<Vec<u8>>,
     pub arq_version: String,
 }
 
+/// Whether a commit can be trusted as a full snapshot of its folder, see
+/// [Commit::status].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommitStatus {
+    Complete,
+    /// The backup was interrupted (or is still running), so the tree only has some of the
+    /// folder's files.
+    Incomplete,
+    /// The backup finished, but some of the tree's nodes couldn't be stored.
+    HasMissingNodes,
+}
+
+impl CommitStatus {
+    fn new(is_complete: bool, has_missing_nodes: bool) -> CommitStatus {
+        if !is_complete {
+            CommitStatus::Incomplete
+        } else if has_missing_nodes {
+            CommitStatus::HasMissingNodes
+        } else {
+            CommitStatus::Complete
+        }
+    }
+}
+
+/// A [Commit] without its `config_plist_xml` and `arq_version`, as read by
+/// [Commit::new_header_only].
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommitHeader {
+    pub version: u32,
+    pub author: String,
+    pub comment: String,
+    pub parent_commits: ParentCommits,
+    pub tree_sha1: String,
+    pub tree_encryption_key_stretched: bool,
+    pub tree_compression_type: CompressionType,
+    pub folder_path: String,
+    pub creation_date: Date,
+    pub failed_files: Vec<FailedFile>,
+    pub has_missing_nodes: bool,
+    pub is_complete: bool,
+}
+
 impl Commit {
+    /// SHA1s of the objects referenced by this commit's tree but not by `parent`'s.
+    ///
+    /// That's trees, data blobs and xattrs/ACL blobs, each listed once, in depth-first
+    /// order. All of `parent`'s tree has to be read, but only the parts of this commit's
+    /// tree that aren't shared with it are.
+    pub fn new_objects<B: BlobResolver>(
+        &self,
+        parent: &Commit,
+        resolver: &B,
+    ) -> Result<Vec<String>> {
+        let mut known = HashSet::new();
+        collect_objects(
+            &parent.tree_sha1,
+            parent.tree_compression_type.clone(),
+            resolver,
+            &mut known,
+            &mut Vec::new(),
+        )?;
+
+        let mut objects = Vec::new();
+        collect_objects(
+            &self.tree_sha1,
+            self.tree_compression_type.clone(),
+            resolver,
+            &mut known,
+            &mut objects,
+        )?;
+        Ok(objects)
+    }
+
+    /// SHA1s of every object this commit's tree references: the tree itself, its
+    /// subtrees, and the data, xattrs and ACL blobs of their nodes. The commit's own SHA1
+    /// isn't included.
+    ///
+    /// Collected over every commit still kept, these are the objects
+    /// [crate::packset::PackSet::find_orphans] must not report.
+    pub fn reachable_sha1s<B: BlobResolver>(&self, resolver: &B) -> Result<HashSet<String>> {
+        let mut reachable = HashSet::new();
+        collect_objects(
+            &self.tree_sha1,
+            self.tree_compression_type.clone(),
+            resolver,
+            &mut reachable,
+            &mut Vec::new(),
+        )?;
+        Ok(reachable)
+    }
+
+    /// Splits `folder_path` (`file://<hostname><path_to_folder>`) into the hostname and
+    /// the (percent-decoded) path of the folder.
+    pub fn parsed_folder_path(&self) -> Result<(String, PathBuf)> {
+        let location = self
+            .folder_path
+            .strip_prefix("file://")
+            .ok_or(Error::ParseError)?;
+        let (host, path) = match location.find('/') {
+            Some(index) => location.split_at(index),
+            None => (location, "/"),
+        };
+        let path = percent_decode(path).ok_or(Error::ParseError)?;
+        Ok((host.to_string(), PathBuf::from(path)))
+    }
+
+    /// The commit's format version as a [CommitVersion].
+    pub fn commit_version(&self) -> CommitVersion {
+        CommitVersion::from(self.version)
+    }
+
+    /// Parses `arq_version` (e.g. `"5.20.0"`) into its major, minor and patch numbers.
+    ///
+    /// A missing minor or patch number counts as 0 and anything after the numbers (like
+    /// `"-beta"`) is ignored. Returns `None` if the version is empty or doesn't start with
+    /// a number.
+    ///
+    /// Commits and trees carry their own format version in their header, which is what
+    /// parsing goes by; this is only a hint for behaviour tied to the app that wrote them.
+    pub fn arq_version_parsed(&self) -> Option<(u32, u32, u32)> {
+        let version = self
+            .arq_version
+            .split(|c: char| c.is_whitespace() || c == '-' || c == '+')
+            .next()?;
+        let mut parts = version.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(part) => part.parse().ok()?,
+            None => 0,
+        };
+        let patch = match parts.next() {
+            Some(part) => part.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((major, minor, patch))
+    }
+
+    /// Whether the commit is a complete snapshot. An incomplete commit is reported as
+    /// such even if it also has missing nodes.
+    pub fn status(&self) -> CommitStatus {
+        CommitStatus::new(self.is_complete, self.has_missing_nodes)
+    }
+
     pub fn is_commit(content: &[u8]) -> bool {
         content[..10] == [67, 111, 109, 109, 105, 116, 86, 48, 49, 50] // CommitV012
     }
 
     pub fn new<R: ArqRead>(mut reader: R) -> Result<Commit> {
-        let header = reader.read_bytes(10)?;
-        assert_eq!(header[..7], [67, 111, 109, 109, 105, 116, 86]); // CommitV
+        let header = CommitHeader::read(&mut reader)?;
+        let config_plist_xml = if CommitVersion::from(header.version).has_config_plist() {
+            Some(reader.read_arq_data()?)
+        } else {
+            None
+        };
+        let arq_version = reader.read_arq_string()?;
+
+        Ok(Commit {
+            version: header.version,
+            author: header.author,
+            comment: header.comment,
+            parent_commits: header.parent_commits,
+            tree_sha1: header.tree_sha1,
+            tree_encryption_key_stretched: header.tree_encryption_key_stretched,
+            tree_compression_type: header.tree_compression_type,
+            folder_path: header.folder_path,
+            creation_date: header.creation_date,
+            failed_files: header.failed_files,
+            has_missing_nodes: header.has_missing_nodes,
+            is_complete: header.is_complete,
+            config_plist_xml,
+            arq_version,
+        })
+    }
+
+    /// Reads a commit up to (but not including) `config_plist_xml`, which can be large
+    /// and isn't needed to list a folder's history.
+    pub fn new_header_only<R: ArqRead>(mut reader: R) -> Result<CommitHeader> {
+        CommitHeader::read(&mut reader)
+    }
+}
+
+impl CommitHeader {
+    /// See [Commit::status].
+    pub fn status(&self) -> CommitStatus {
+        CommitStatus::new(self.is_complete, self.has_missing_nodes)
+    }
+
+    fn read<R: ArqRead>(reader: &mut R) -> Result<CommitHeader> {
+        let header = read_header(reader, 10, "commit")?;
+        // CommitV
+        if header[..7] != [67, 111, 109, 109, 105, 116, 86] {
+            return Err(Error::ParseError);
+        }
         let version = std::str::from_utf8(&header[7..])?.parse::<u32>()?;
+        let commit_version = CommitVersion::from(version);
 
         let author = reader.read_arq_string()?;
         let comment = reader.read_arq_string()?;
 
         let mut num_parent_commits = reader.read_arq_u64()?;
-        assert!(num_parent_commits == 0 || num_parent_commits == 1);
+        if num_parent_commits > 1 {
+            return Err(Error::ParseError);
+        }
 
         let mut parent_commits: ParentCommits = HashMap::new();
         while num_parent_commits > 0 {
             let sha1 = reader.read_arq_string()?;
-            let encryption_key_stretched = reader.read_arq_bool()?;
+            let encryption_key_stretched =
+                commit_version.has_key_stretched_flags() && reader.read_arq_bool()?;
 
             parent_commits.insert(sha1, encryption_key_stretched);
             num_parent_commits -= 1;
         }
 
         let tree_sha1 = reader.read_arq_string()?;
-        let tree_encryption_key_stretched = reader.read_arq_bool()?;
-        let tree_compression_type = reader.read_arq_compression_type()?;
+        let tree_encryption_key_stretched =
+            commit_version.has_key_stretched_flags() && reader.read_arq_bool()?;
+        let tree_compression_type = if commit_version.has_tree_is_compressed_flag() {
+            if reader.read_arq_bool()? {
+                CompressionType::Gzip
+            } else {
+                CompressionType::None
+            }
+        } else if commit_version.stores_tree_compression_type() {
+            reader.read_arq_compression_type()?
+        } else {
+            CompressionType::None
+        };
         let folder_path = reader.read_arq_string()?;
+        if commit_version.has_merge_common_ancestor() {
+            reader.read_arq_string()?;
+            if commit_version.has_key_stretched_flags() {
+                reader.read_arq_bool()?;
+            }
+        }
         let creation_date = reader.read_arq_date()?;
 
-        let mut num_failed_files = reader.read_arq_u64()?;
+        let mut num_failed_files = if commit_version.has_failed_files() {
+            reader.read_arq_u64()?
+        } else {
+            0
+        };
         let mut failed_files = Vec::new();
         while num_failed_files > 0 {
             let relative_path = reader.read_arq_string()?;
@@ -480,12 +1922,16 @@ impl Commit {
             num_failed_files -= 1;
         }
 
-        let has_missing_nodes = reader.read_arq_bool()?;
-        let is_complete = reader.read_arq_bool()?;
-        let config_plist_xml = reader.read_arq_data()?;
-        let arq_version = reader.read_arq_string()?;
+        let has_missing_nodes =
+            commit_version.has_missing_nodes_flag() && reader.read_arq_bool()?;
+        // Commits from before the flag existed were only written once complete.
+        let is_complete = if commit_version.has_is_complete_flag() {
+            reader.read_arq_bool()?
+        } else {
+            true
+        };
 
-        Ok(Commit {
+        Ok(CommitHeader {
             version,
             author,
             comment,
@@ -498,8 +1944,942 @@ impl Commit {
             failed_files,
             has_missing_nodes,
             is_complete,
-            config_plist_xml,
-            arq_version,
         })
     }
 }
+
+/// Adds the tree `tree_sha1` and everything it references to `found`, skipping (along with
+/// their contents, for trees) any objects already in `known`.
+fn collect_objects<B: BlobResolver>(
+    tree_sha1: &str,
+    compression_type: CompressionType,
+    resolver: &B,
+    known: &mut HashSet<String>,
+    found: &mut Vec<String>,
+) -> Result<()> {
+    if !known.insert(tree_sha1.to_string()) {
+        return Ok(());
+    }
+    found.push(tree_sha1.to_string());
+
+    let tree = Tree::new(&resolver.resolve(tree_sha1)?, compression_type)?;
+    let mut names: Vec<&String> = tree.nodes.keys().collect();
+    names.sort();
+    for name in names {
+        let node = &tree.nodes[name];
+        if node.is_tree {
+            let subtree = node.data_blob_keys.first().ok_or(Error::ParseError)?;
+            collect_objects(
+                &subtree.sha1,
+                node.data_compression_type.clone(),
+                resolver,
+                known,
+                found,
+            )?;
+        } else {
+            for blob_key in &node.data_blob_keys {
+                if known.insert(blob_key.sha1.clone()) {
+                    found.push(blob_key.sha1.clone());
+                }
+            }
+        }
+        for blob_key in node.xattrs_blob_key.iter().chain(&node.acl_blob_key) {
+            if known.insert(blob_key.sha1.clone()) {
+                found.push(blob_key.sha1.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lz4;
+    use crate::resolver::CachedResolver;
+    use crate::test_utils::{
+        commit_bytes, commit_bytes_for_version, commit_bytes_with_flags, fixture_objects,
+        node_bytes, node_bytes_for_version, push_string, tree_bytes, tree_bytes_for_version,
+        tree_bytes_with_compression,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_fixture_tree() {
+        // A v22 tree written by Arq, the same as in the crate docs.
+        let tree = Tree::new(
+            include_bytes!("../fixtures/tree-v22-lz4"),
+            CompressionType::LZ4,
+        )
+        .unwrap();
+        assert_eq!(tree.version, 22);
+        assert_eq!(tree.mode, 0o40755);
+        assert_eq!(tree.st_ino, 38621631);
+        assert_eq!(tree.nodes.len(), 2);
+
+        let file = &tree.nodes["somefile"];
+        assert!(!file.is_tree);
+        assert_eq!(file.data_size, 12);
+        assert_eq!(file.mode, 0o100644);
+        assert_eq!(
+            file.data_blob_keys[0].sha1,
+            "da8a00357643d481b5b46c9dc9c41277b35b9e85"
+        );
+        assert!(file.data_blob_keys[0].is_encryption_key_stretched);
+
+        let folder = &tree.nodes["top_folder"];
+        assert!(folder.is_tree);
+        assert_eq!(
+            folder.data_blob_keys[0].sha1,
+            "c0571537d57d9488164303950dfded5cb6cfcd20"
+        );
+
+        // Reading the tree uses up exactly what Arq wrote after the header: v20+ trees have
+        // no Int32 compression type fields besides the two read as `CompressionType`s.
+        let raw = lz4::decompress(include_bytes!("../fixtures/tree-v22-lz4")).unwrap();
+        let mut reader = CountingReader::new(Cursor::new(&raw[8..]));
+        Tree::read(&mut reader, 22).unwrap();
+        assert_eq!(reader.position, raw.len() as u64 - 8);
+    }
+
+    #[test]
+    fn test_walk() {
+        let (root, objects) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+
+        let mut paths = Vec::new();
+        tree.walk(&objects, |path, _| {
+            paths.push(path.to_str().unwrap().to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(paths, vec!["a", "sub", "sub/b", "sub/c"]);
+    }
+
+    #[test]
+    fn test_find() {
+        let (root, objects) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+
+        // Counts every object resolved, as none are in the (empty) cache.
+        let resolver = CachedResolver::new(HashMap::new(), &objects);
+        let (path, node) = tree
+            .find(&resolver, |_, node| node.data_size == 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, Path::new("a"));
+        assert_eq!(node.data_sha1s(), vec!["aaaa"]);
+        assert_eq!(resolver.misses(), 0);
+
+        let (path, _) = tree
+            .find(&resolver, |path, _| path.ends_with("b"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, Path::new("sub/b"));
+        assert_eq!(resolver.misses(), 1);
+
+        assert!(tree.find(&objects, |_, _| false).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_walk_ignoring() {
+        let (root, objects) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+
+        let mut paths = Vec::new();
+        tree.walk_ignoring(&objects, &[PathBuf::from("sub")], |path, _| {
+            paths.push(path.to_str().unwrap().to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(paths, vec!["a"]);
+
+        paths.clear();
+        tree.walk_ignoring(
+            &objects,
+            &[PathBuf::from("sub/b"), PathBuf::from("su")],
+            |path, _| {
+                paths.push(path.to_str().unwrap().to_string());
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(paths, vec!["a", "sub", "sub/c"]);
+    }
+
+    #[test]
+    fn test_total_size() {
+        let (root, objects) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+        assert_eq!(tree.total_size(&objects).unwrap(), 22);
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let (root, objects) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+
+        let mut reports = Vec::new();
+        let total = tree
+            .total_size_with_progress(&objects, &mut |progress| reports.push(progress))
+            .unwrap();
+        assert_eq!(total, 22);
+        assert_eq!(reports.len(), 4);
+        assert_eq!(
+            reports[3],
+            Progress {
+                nodes: 4,
+                bytes_read: objects["subtree"].len() as u64,
+            }
+        );
+
+        let mut calls = 0;
+        tree.walk_with_progress(&objects, |_, _| Ok(()), &mut |_| calls += 1)
+            .unwrap();
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_total_size_with_missing_subtree() {
+        let (_, objects) = fixture_objects();
+        let root = tree_bytes(&[
+            ("a", node_bytes(false, &["aaaa"], 10, 0o100644)),
+            ("gone", node_bytes(true, &["notthere"], 0, 0o40755)),
+            ("sub", node_bytes(true, &["subtree"], 0, 0o40755)),
+        ]);
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+
+        assert!(matches!(
+            tree.total_size(&objects),
+            Err(Error::ObjectNotFound(ref sha1)) if sha1 == "notthere"
+        ));
+        assert_eq!(tree.total_size_skipping_missing(&objects).unwrap(), 22);
+    }
+
+    #[test]
+    fn test_v18_node() {
+        let bytes = node_bytes_for_version(18, false, &["aaaa", "bbbb"], 10, 0o100644);
+        let node = Node::new_with_version(Cursor::new(&bytes), 18).unwrap();
+        assert_eq!(node.data_blob_keys.len(), 2);
+        assert_eq!(node.data_size, 10);
+        assert_eq!(node.data_compression_type, CompressionType::Gzip);
+        assert_eq!(node.thumbnail_sha1.as_deref(), Some("thumb"));
+        assert_eq!(node.is_thumbnail_encryption_key_stretched, Some(true));
+        assert_eq!(node.preview_sha1.as_deref(), Some(""));
+        assert_eq!(node.is_preview_encryption_key_stretched, Some(false));
+        // Fields after the thumbnail/preview ones are still aligned
+        assert_eq!(node.mode, 0o100644);
+        assert_eq!(node.st_blksize, 4096);
+
+        let node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 10, 0o100644))).unwrap();
+        assert_eq!(node.thumbnail_sha1, None);
+        assert_eq!(node.is_preview_encryption_key_stretched, None);
+    }
+
+    #[test]
+    fn test_gzipped_tree_labeled_uncompressed() {
+        use flate2::write::GzEncoder;
+
+        let raw = tree_bytes(&[("a", node_bytes(false, &["aaaa"], 10, 0o100644))]);
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&raw).unwrap();
+        let gzipped = gzip.finish().unwrap();
+
+        let tree = Tree::new(&gzipped, CompressionType::None).unwrap();
+        assert_eq!(tree, Tree::new(&raw, CompressionType::None).unwrap());
+        assert_eq!(tree.nodes["a"].data_size, 10);
+
+        // Only trees labeled uncompressed get the gunzip fallback.
+        assert!(matches!(
+            Tree::new(&lz4::compress(&gzipped).unwrap(), CompressionType::LZ4),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_v18_tree() {
+        let node = node_bytes_for_version(18, false, &["aaaa"], 10, 0o100644);
+        let bytes = tree_bytes_for_version(18, &[("a", node)]);
+        let tree = Tree::new(&bytes, CompressionType::None).unwrap();
+        assert_eq!(tree.version, 18);
+        assert_eq!(tree.xattrs_compression_type, CompressionType::Gzip);
+        assert_eq!(tree.acl_compression_type, CompressionType::None);
+        assert_eq!(tree.uid, 501);
+        assert_eq!(tree.st_blksize, 4096);
+        assert_eq!(tree.nodes["a"].data_size, 10);
+        assert_eq!(tree.nodes["a"].thumbnail_sha1.as_deref(), Some("thumb"));
+    }
+
+    #[test]
+    fn test_v16_tree() {
+        let node = node_bytes_for_version(16, false, &["aaaa"], 10, 0o100644);
+        let bytes = tree_bytes_for_version(16, &[("a", node)]);
+        let tree = Tree::new(&bytes, CompressionType::None).unwrap();
+        assert_eq!(tree.version, 16);
+        assert_eq!(tree.st_blksize, 4096);
+        assert_eq!(tree.aggregate_size_on_disk, Some(8192));
+        assert_eq!(tree.create_time_sec, 0);
+        assert!(tree.missing_nodes.is_empty());
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.nodes["a"].data_size, 10);
+        assert_eq!(tree.nodes["a"].mode, 0o100644);
+
+        let bytes = tree_bytes_for_version(22, &[]);
+        let tree = Tree::new(&bytes, CompressionType::None).unwrap();
+        assert_eq!(tree.aggregate_size_on_disk, None);
+    }
+
+    #[test]
+    fn test_v20_tree() {
+        let node = node_bytes_for_version(20, false, &["aaaa"], 10, 0o100644);
+        let bytes = tree_bytes_with_compression(
+            20,
+            CompressionType::LZ4,
+            CompressionType::None,
+            &[("a", node)],
+        );
+        let tree = Tree::new(&bytes, CompressionType::None).unwrap();
+        assert_eq!(tree.version, 20);
+        assert_eq!(tree.xattrs_compression_type, CompressionType::LZ4);
+        assert_eq!(tree.acl_compression_type, CompressionType::None);
+        assert_eq!(tree.uid, 501);
+        assert_eq!(tree.st_blksize, 4096);
+        assert_eq!(tree.nodes["a"].data_size, 10);
+        assert_eq!(tree.nodes["a"].mode, 0o100644);
+    }
+
+    #[test]
+    fn test_node_write_round_trip() {
+        let raw = node_bytes(false, &["aaaa", "bbbb"], 10, 0o100644);
+        let node = Node::new(Cursor::new(&raw)).unwrap();
+        assert_eq!(node.xattrs_blob_key, None);
+        assert_eq!(node.acl_blob_key, None);
+
+        let mut written = Vec::new();
+        node.write(&mut written).unwrap();
+        assert_eq!(written, raw);
+    }
+
+    #[test]
+    fn test_clone_and_compare_node() {
+        let node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 10, 0o100644))).unwrap();
+        let mut cloned = node.clone();
+        assert_eq!(node, cloned);
+
+        cloned.data_size = 11;
+        assert_ne!(node, cloned);
+    }
+
+    #[test]
+    fn test_device_node() {
+        let mut node = Node::new(Cursor::new(node_bytes(false, &[], 0, 0o020620))).unwrap();
+        node.st_rdev = (16 << 24) | 3;
+        assert!(node.is_device());
+        assert!(!node.is_hardlinked());
+        assert_eq!(node.device_ids(), Some((16, 3)));
+
+        node.mode = 0o060660;
+        assert!(node.is_device());
+
+        node.mode = 0o100644;
+        assert!(!node.is_device());
+        assert_eq!(node.device_ids(), None);
+    }
+
+    #[test]
+    fn test_hardlinked_node() {
+        let mut node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 1, 0o100644))).unwrap();
+        assert!(!node.is_hardlinked());
+
+        node.st_nlink = 2;
+        assert!(node.is_hardlinked());
+        assert!(!node.is_device());
+    }
+
+    #[test]
+    fn test_parsed_folder_path() {
+        let mut commit = Commit::new(Cursor::new(commit_bytes("subtree", None))).unwrap();
+        commit.folder_path = "file://myhost/Users/stefan/src".to_string();
+        assert_eq!(
+            commit.parsed_folder_path().unwrap(),
+            ("myhost".to_string(), PathBuf::from("/Users/stefan/src"))
+        );
+
+        commit.folder_path = "file://myhost/Users/stefan/my%20src".to_string();
+        assert_eq!(
+            commit.parsed_folder_path().unwrap().1,
+            PathBuf::from("/Users/stefan/my src")
+        );
+
+        commit.folder_path = "file://myhost".to_string();
+        assert_eq!(
+            commit.parsed_folder_path().unwrap(),
+            ("myhost".to_string(), PathBuf::from("/"))
+        );
+
+        commit.folder_path = "/Users/stefan/src".to_string();
+        assert!(matches!(
+            commit.parsed_folder_path(),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_tree_to_bytes() {
+        let (root, _) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+        let bytes = tree.to_bytes().unwrap();
+        assert_eq!(bytes, root);
+        assert_eq!(Tree::new(&bytes, CompressionType::None).unwrap(), tree);
+    }
+
+    #[test]
+    fn test_empty_tree_to_bytes() {
+        let mut tree = Tree::new(&tree_bytes(&[]), CompressionType::None).unwrap();
+        assert!(tree.nodes.is_empty());
+        assert!(tree.missing_nodes.is_empty());
+
+        let bytes = tree.to_bytes().unwrap();
+        // Both counts are written, as zero, at the very end.
+        assert_eq!(bytes[bytes.len() - 8..], [0; 8]);
+        assert_eq!(Tree::new(&bytes, CompressionType::None).unwrap(), tree);
+
+        tree.missing_nodes.push("gone".to_string());
+        let bytes = tree.to_bytes().unwrap();
+        assert_eq!(Tree::new(&bytes, CompressionType::None).unwrap(), tree);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_tree_to_json_value() {
+        let (root, _) = fixture_objects();
+        let json = Tree::new(&root, CompressionType::None)
+            .unwrap()
+            .to_json_value();
+
+        assert_eq!(json["version"], 22);
+        assert_eq!(json["mode"], 0o40755);
+        assert_eq!(json["nodes"]["a"]["data_size"], 10);
+        assert_eq!(json["nodes"]["a"]["data_sha1s"][0], "aaaa");
+        assert_eq!(json["nodes"]["a"]["xattrs_sha1"], serde_json::Value::Null);
+        assert_eq!(json["nodes"]["sub"]["is_tree"], true);
+        assert_eq!(
+            json["nodes"]["sub"]["data_sha1s"],
+            serde_json::json!(["subtree"])
+        );
+        assert_eq!(json["nodes"]["sub"]["data_compression_type"], "None");
+        assert_eq!(json["missing_nodes"], serde_json::json!([]));
+
+        let mut node = Tree::new(&root, CompressionType::None).unwrap().nodes["a"].clone();
+        node.data_compression_type = CompressionType::LZ4;
+        assert_eq!(node.to_json_value()["data_compression_type"], "LZ4");
+        node.data_compression_type = CompressionType::Gzip;
+        assert_eq!(node.to_json_value()["data_compression_type"], "Gzip");
+    }
+
+    #[test]
+    fn test_versions() {
+        let (root, _) = fixture_objects();
+        let tree = Tree::new(&root, CompressionType::None).unwrap();
+        assert_eq!(tree.tree_version(), TreeVersion::V22);
+
+        let mut old = root.clone();
+        old[5..8].copy_from_slice(b"019");
+        let tree = Tree::new(&old, CompressionType::None).unwrap();
+        assert_eq!(tree.tree_version(), TreeVersion::V19);
+
+        for (number, version) in [
+            (12, TreeVersion::V12),
+            (18, TreeVersion::V18),
+            (21, TreeVersion::V21),
+            (11, TreeVersion::Unknown(11)),
+            (23, TreeVersion::Unknown(23)),
+        ] {
+            assert_eq!(TreeVersion::from(number), version);
+            assert_eq!(version.number(), number);
+        }
+        assert!(TreeVersion::Unknown(23).stores_compression_type());
+        assert!(!TreeVersion::V18.stores_compression_type());
+        assert!(TreeVersion::V18.has_missing_items_flag());
+        assert!(!TreeVersion::V17.has_missing_items_flag());
+        assert!(TreeVersion::V14.has_thumbnail_stretched_flags());
+        assert!(!TreeVersion::V13.has_thumbnail_stretched_flags());
+
+        let commit = Commit::new(Cursor::new(commit_bytes("subtree", None))).unwrap();
+        assert_eq!(commit.commit_version(), CommitVersion::V12);
+        assert_eq!(CommitVersion::from(3), CommitVersion::V3);
+        assert_eq!(CommitVersion::from(13), CommitVersion::Unknown(13));
+        assert_eq!(CommitVersion::V10.number(), 10);
+    }
+
+    #[test]
+    fn test_v9_commit() {
+        let bytes = commit_bytes_for_version(9, "subtree", Some("parent"));
+        let commit = Commit::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(commit.version, 9);
+        assert_eq!(commit.commit_version(), CommitVersion::V9);
+        assert_eq!(commit.tree_sha1, "subtree");
+        assert_eq!(commit.tree_compression_type, CompressionType::Gzip);
+        assert_eq!(commit.folder_path, "file://myhost/Users/someuser/src");
+        assert_eq!(
+            commit.creation_date.milliseconds_since_epoch,
+            1_550_000_000_000
+        );
+        assert!(commit.failed_files.is_empty());
+        assert!(commit.is_complete);
+        assert_eq!(commit.arq_version, "5.20.0");
+
+        let bytes = commit_bytes_for_version(8, "subtree", None);
+        let commit = Commit::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(commit.tree_compression_type, CompressionType::Gzip);
+        assert_eq!(commit.arq_version, "5.20.0");
+    }
+
+    #[test]
+    fn test_commit_without_config_plist() {
+        let bytes = commit_bytes_for_version(4, "subtree", Some("parent"));
+        let commit = Commit::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(commit.version, 4);
+        assert_eq!(commit.parent_commits.get("parent"), Some(&false));
+        assert_eq!(commit.tree_sha1, "subtree");
+        assert_eq!(commit.tree_compression_type, CompressionType::None);
+        assert_eq!(commit.folder_path, "file://myhost/Users/someuser/src");
+        assert_eq!(
+            commit.creation_date.milliseconds_since_epoch,
+            1_550_000_000_000
+        );
+        assert!(commit.is_complete);
+        assert_eq!(commit.config_plist_xml, None);
+        assert_eq!(commit.arq_version, "5.20.0");
+
+        let bytes = commit_bytes_for_version(7, "subtree", None);
+        let commit = Commit::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(commit.config_plist_xml, Some(Vec::new()));
+        assert_eq!(commit.arq_version, "5.20.0");
+    }
+
+    #[test]
+    fn test_commit_status() {
+        let mut commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+        assert_eq!(commit.status(), CommitStatus::Complete);
+
+        for (is_complete, has_missing_nodes, status) in [
+            (true, false, CommitStatus::Complete),
+            (true, true, CommitStatus::HasMissingNodes),
+            (false, false, CommitStatus::Incomplete),
+            (false, true, CommitStatus::Incomplete),
+        ] {
+            let raw = commit_bytes_with_flags(12, "roottree", None, has_missing_nodes, is_complete);
+            let header = Commit::new_header_only(Cursor::new(&raw)).unwrap();
+            assert_eq!(header.status(), status);
+            commit = Commit::new(Cursor::new(&raw)).unwrap();
+            assert_eq!(commit.status(), status);
+        }
+    }
+
+    #[test]
+    fn test_commit_header_only() {
+        let bytes = commit_bytes("subtree", Some("parent"));
+        let commit = Commit::new(Cursor::new(&bytes)).unwrap();
+        let header = Commit::new_header_only(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(header.version, commit.version);
+        assert_eq!(header.author, commit.author);
+        assert_eq!(header.comment, commit.comment);
+        assert_eq!(header.parent_commits, commit.parent_commits);
+        assert_eq!(header.tree_sha1, commit.tree_sha1);
+        assert_eq!(header.tree_compression_type, commit.tree_compression_type);
+        assert_eq!(header.folder_path, commit.folder_path);
+        assert_eq!(header.creation_date, commit.creation_date);
+        assert_eq!(header.failed_files, commit.failed_files);
+        assert_eq!(header.is_complete, commit.is_complete);
+
+        // Without the (empty) config plist and the arq version string.
+        let truncated = &bytes[..bytes.len() - 8 - (1 + 8 + commit.arq_version.len())];
+        assert_eq!(
+            Commit::new_header_only(Cursor::new(truncated)).unwrap(),
+            header
+        );
+    }
+
+    #[test]
+    fn test_arq_version_parsed() {
+        let mut commit = Commit::new(Cursor::new(commit_bytes("subtree", None))).unwrap();
+        assert_eq!(commit.arq_version_parsed(), Some((5, 20, 0)));
+
+        for (version, expected) in [
+            ("7.1", Some((7, 1, 0))),
+            ("6", Some((6, 0, 0))),
+            ("7.2.3-beta", Some((7, 2, 3))),
+            ("", None),
+            ("unknown", None),
+            ("5.x.1", None),
+            ("1.2.3.4", None),
+        ] {
+            commit.arq_version = version.to_string();
+            assert_eq!(commit.arq_version_parsed(), expected, "{version}");
+        }
+    }
+
+    #[test]
+    fn test_xattr_set_round_trip() {
+        let finder_info = vec![
+            b'T', b'E', b'X', b'T', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut xattrs = XAttrSet::default();
+        xattrs
+            .xattrs
+            .insert("com.apple.FinderInfo".to_string(), finder_info.clone());
+        xattrs
+            .xattrs
+            .insert("user.comment".to_string(), b"hello".to_vec());
+
+        let mut raw = Vec::new();
+        xattrs.write(&mut raw).unwrap();
+        assert_eq!(&raw[..12], b"XAttrSetV002");
+        let parsed = XAttrSet::new(Cursor::new(&raw)).unwrap();
+        assert_eq!(parsed, xattrs);
+        assert_eq!(parsed.xattrs["com.apple.FinderInfo"], finder_info);
+        assert_eq!(parsed.xattrs["user.comment"], b"hello");
+
+        let mut node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 10, 0o100644))).unwrap();
+        let objects: HashMap<String, Vec<u8>> = HashMap::new();
+        assert_eq!(node.xattrs(&objects).unwrap(), None);
+        let mut blob_key = node.data_blob_keys[0].clone();
+        blob_key.sha1 = "xattrs".to_string();
+        node.xattrs_blob_key = Some(blob_key);
+        let objects = HashMap::from([("xattrs".to_string(), raw)]);
+        assert_eq!(node.xattrs(&objects).unwrap(), Some(xattrs));
+
+        assert!(matches!(
+            XAttrSet::new(Cursor::new(b"XAttrSetV001\0\0\0\0\0\0\0\0")),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_finder_info() {
+        let mut node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 10, 0o100644))).unwrap();
+        assert_eq!(node.finder_info(), None);
+
+        node.finder_file_type = "TEXT".to_string();
+        node.finder_file_creator = "ttxt".to_string();
+        node.finder_flags = 0x4000;
+        let info = node.finder_info().unwrap();
+        assert_eq!(&info.file_type, b"TEXT");
+        assert_eq!(&info.file_creator, b"ttxt");
+        let xattr = info.to_xattr();
+        assert_eq!(&xattr[..10], b"TEXTttxt\x40\x00");
+        assert!(xattr[10..].iter().all(|byte| *byte == 0));
+
+        node.finder_file_creator = String::new();
+        assert_eq!(node.finder_info().unwrap().file_creator, [0; 4]);
+
+        node.finder_file_type = "TEXTS".to_string();
+        assert_eq!(node.finder_info(), None);
+    }
+
+    #[test]
+    fn test_finder_flags_decoded() {
+        let mut node = Node::new(Cursor::new(node_bytes(false, &["aaaa"], 10, 0o100644))).unwrap();
+        assert!(node.finder_flags_decoded().is_empty());
+
+        node.finder_flags = 0x4400;
+        let flags = node.finder_flags_decoded();
+        assert_eq!(
+            flags,
+            FinderFlags::IS_INVISIBLE | FinderFlags::HAS_CUSTOM_ICON
+        );
+        assert!(!flags.contains(FinderFlags::IS_ALIAS));
+
+        // Label color 2 and a bit Finder.h doesn't name.
+        node.finder_flags = 0x0024;
+        let flags = node.finder_flags_decoded();
+        assert_eq!((flags & FinderFlags::COLOR).bits() >> 1, 2);
+        assert_eq!(flags.bits(), 0x0024);
+
+        node.extended_finder_flags = 0x0100;
+        assert_eq!(
+            node.extended_finder_flags_decoded(),
+            ExtendedFinderFlags::HAS_CUSTOM_BADGE
+        );
+    }
+
+    #[test]
+    fn test_data_sha1s() {
+        let node = Node::new(Cursor::new(node_bytes(
+            false,
+            &["blob0", "blob1", "blob2"],
+            31,
+            0o100644,
+        )))
+        .unwrap();
+        assert_eq!(node.data_sha1s(), vec!["blob0", "blob1", "blob2"]);
+    }
+
+    #[test]
+    fn test_node_reader() {
+        use std::io::{Read, SeekFrom};
+
+        let chunks: [&[u8]; 3] = [b"first chunk, ", b"second chunk, ", b"last"];
+        let mut objects = HashMap::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            objects.insert(format!("blob{i}"), lz4::compress(chunk).unwrap());
+        }
+        let mut node = Node::new(Cursor::new(node_bytes(
+            false,
+            &["blob0", "blob1", "blob2"],
+            31,
+            0o100644,
+        )))
+        .unwrap();
+        node.data_compression_type = CompressionType::LZ4;
+
+        let mut reader = node.reader(&objects).unwrap();
+        let mut contents = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            let len = reader.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            contents.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(contents, node.reassemble(&objects).unwrap());
+
+        let mut rest = String::new();
+        reader.seek(SeekFrom::Start(6)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"chu");
+        reader.seek(SeekFrom::End(-4)).unwrap();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "last");
+        assert_eq!(reader.seek(SeekFrom::Current(-18)).unwrap(), 13);
+        rest.clear();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "second chunk, last");
+        assert!(reader.seek(SeekFrom::Current(-32)).is_err());
+
+        node.data_size = 40;
+        let mut reader = node.reader(&objects).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        objects.remove("blob0");
+        assert!(matches!(
+            node.reader(&objects),
+            Err(Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_reassemble_multiple_blobs() {
+        let chunks: [&[u8]; 3] = [b"first chunk, ", b"second chunk, ", b"last"];
+        let mut objects = HashMap::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            objects.insert(format!("blob{i}"), lz4::compress(chunk).unwrap());
+        }
+
+        let mut node = Node::new(Cursor::new(node_bytes(
+            false,
+            &["blob0", "blob1", "blob2"],
+            31,
+            0o100644,
+        )))
+        .unwrap();
+        node.data_compression_type = CompressionType::LZ4;
+        assert_eq!(
+            node.reassemble(&objects).unwrap(),
+            b"first chunk, second chunk, last"
+        );
+
+        node.data_size = 30;
+        assert!(matches!(
+            node.reassemble(&objects),
+            Err(Error::SizeMismatch {
+                expected: 30,
+                actual: 31
+            })
+        ));
+
+        objects.insert("blob1".to_string(), lz4::compress(b"short").unwrap());
+        node.data_size = 31;
+        assert!(matches!(
+            node.reassemble(&objects),
+            Err(Error::SizeMismatch {
+                expected: 31,
+                actual: 22
+            })
+        ));
+
+        // A corrupt size fails the size check instead of the allocation.
+        node.data_size = u64::MAX;
+        assert!(matches!(
+            node.reassemble(&objects),
+            Err(Error::SizeMismatch {
+                expected: u64::MAX,
+                actual: 22
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reachable_sha1s() {
+        let (_, mut objects) = fixture_objects();
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+
+        let reachable = commit.reachable_sha1s(&objects).unwrap();
+        assert_eq!(reachable.len(), 5);
+        for sha1 in ["roottree", "subtree", "aaaa", "bbbb", "cccc"] {
+            assert!(reachable.contains(sha1), "{sha1}");
+        }
+
+        objects.remove("subtree");
+        assert!(matches!(
+            commit.reachable_sha1s(&objects),
+            Err(Error::ObjectNotFound(ref sha1)) if sha1 == "subtree"
+        ));
+    }
+
+    #[test]
+    fn test_new_objects() {
+        let (_, mut objects) = fixture_objects();
+        let parent = Commit::new(Cursor::new(commit_bytes("roottree", None))).unwrap();
+
+        // Same as the fixture, but with sub/c changed.
+        objects.insert(
+            "subtree2".to_string(),
+            tree_bytes(&[
+                ("b", node_bytes(false, &["bbbb"], 5, 0o100644)),
+                ("c", node_bytes(false, &["cccc2"], 8, 0o100644)),
+            ]),
+        );
+        objects.insert(
+            "roottree2".to_string(),
+            tree_bytes(&[
+                ("a", node_bytes(false, &["aaaa"], 10, 0o100644)),
+                ("sub", node_bytes(true, &["subtree2"], 0, 0o40755)),
+            ]),
+        );
+        objects.insert("cccc2".to_string(), b"01234567".to_vec());
+        let commit = Commit::new(Cursor::new(commit_bytes("roottree2", Some("parent")))).unwrap();
+
+        assert_eq!(
+            commit.new_objects(&parent, &objects).unwrap(),
+            vec!["roottree2", "subtree2", "cccc2"]
+        );
+        assert!(parent.new_objects(&parent, &objects).unwrap().is_empty());
+        assert_eq!(
+            parent.new_objects(&commit, &objects).unwrap(),
+            vec!["roottree", "subtree", "cccc"]
+        );
+    }
+
+    #[test]
+    fn test_empty_input() {
+        for compression_type in [CompressionType::None, CompressionType::LZ4] {
+            assert!(matches!(
+                Tree::new(&[], compression_type),
+                Err(Error::UnexpectedEof {
+                    while_reading: "tree"
+                })
+            ));
+        }
+        assert!(matches!(
+            Commit::new(Cursor::new(Vec::new())),
+            Err(Error::UnexpectedEof {
+                while_reading: "commit"
+            })
+        ));
+        assert!(matches!(
+            Commit::new(Cursor::new(b"Commit".to_vec())),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_tree_reports_offset() {
+        let root = tree_bytes(&[("a", node_bytes(false, &["aaaa"], 10, 0o100644))]);
+
+        // mtime_sec starts 68 bytes in: header (8), compression types (8), the null xattrs
+        // blob key (16), xattrs_size (8), the null acl blob key (16), uid/gid/mode (12).
+        let err = Tree::new(&root[..70], CompressionType::None).err().unwrap();
+        assert!(matches!(err, Error::ParseAt { offset: 68, .. }));
+        assert!(format!("{err}").ends_with("at offset 68"));
+
+        // Errors other than running out of input inside a node keep their offset. The
+        // first node's name takes 10 bytes, then its first blob key starts 18 bytes into the
+        // node (after the flags, compression types and key count), with the SHA1's
+        // characters 9 bytes further. The offset is that of the characters, as the read
+        // that failed.
+        let node_start = tree_bytes(&[]).len() + 10;
+        let mut corrupt = root.clone();
+        corrupt[node_start + 27] = 0xff;
+        let err = Tree::new(&corrupt, CompressionType::None).err().unwrap();
+        match err {
+            Error::ParseAt { offset, kind } => {
+                assert_eq!(offset, (node_start + 27) as u64);
+                assert!(matches!(*kind, Error::ConversionError(_)));
+            }
+            _ => panic!("expected Error::ParseAt"),
+        }
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        let mut tree = tree_bytes(&[("a", node_bytes(false, &["aaaa"], 10, 0o100644))]);
+        tree[0] = b'X';
+        assert!(matches!(
+            Tree::new(&tree, CompressionType::None),
+            Err(Error::ParseError)
+        ));
+
+        let unnamed = tree_bytes(&[("", node_bytes(false, &["aaaa"], 10, 0o100644))]);
+        assert!(matches!(
+            Tree::new(&unnamed, CompressionType::None),
+            Err(Error::ParseAt { .. })
+        ));
+
+        let mut commit = commit_bytes("roottree", None);
+        commit[0] = b'X';
+        assert!(matches!(
+            Commit::new(Cursor::new(commit)),
+            Err(Error::ParseError)
+        ));
+
+        // Commits have at most one parent.
+        let mut commit = b"CommitV012".to_vec();
+        push_string(&mut commit, "someuser");
+        push_string(&mut commit, "a comment");
+        commit.extend_from_slice(&2u64.to_be_bytes());
+        assert!(matches!(
+            Commit::new(Cursor::new(commit)),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_tree() {
+        let root = tree_bytes(&[
+            ("a", node_bytes(false, &["aaaa"], 10, 0o100644)),
+            ("b", node_bytes(false, &["bbbb"], 5, 0o100644)),
+        ]);
+        let second_node = root.len() - (node_bytes(false, &["bbbb"], 5, 0o100644).len() + 9);
+
+        // Cut at the second node's name, then in the middle of the node itself.
+        for end in [second_node, second_node + 20] {
+            let err = Tree::new(&root[..end], CompressionType::None)
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err,
+                Error::TruncatedTree {
+                    expected: 2,
+                    got: 1
+                }
+            ));
+            assert_eq!(err.to_string(), "tree declares 2 nodes but ends after 1");
+        }
+    }
+}
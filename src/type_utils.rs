@@ -1,14 +1,49 @@
-use byteorder::{NetworkEndian, ReadBytesExt};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use std;
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
 
 use crate::compression::CompressionType;
 use crate::date::Date;
-use crate::error::Result;
+use crate::error::{Error, Result};
+
+/// Largest buffer allocated up front for a length read from the input, e.g. by
+/// [ArqRead::read_bytes].
+pub(crate) const MAX_PREALLOCATION: usize = 1024 * 1024;
+
+/// Reads the first `count` bytes of an object, telling empty input apart from truncated
+/// input.
+///
+/// Returns `Error::UnexpectedEof` if there's nothing at all to read; running out part of
+/// the way through is an `ErrorKind::UnexpectedEof` I/O error, as with
+/// [ArqRead::read_bytes].
+pub(crate) fn read_header<R: ArqRead>(
+    reader: &mut R,
+    count: usize,
+    while_reading: &'static str,
+) -> Result<Vec<u8>> {
+    let mut header = match reader.read_bytes(1) {
+        Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+            return Err(Error::UnexpectedEof { while_reading })
+        }
+        result => result?,
+    };
+    header.extend(reader.read_bytes(count - 1)?);
+    Ok(header)
+}
 
+/// Reads the primitive types used in Arq's binary formats.
+///
+/// Implemented for every [Read], including network-backed readers that hand data back a
+/// few bytes at a time: reads loop until the requested amount is available, retrying
+/// `ErrorKind::Interrupted`, and only fail with `ErrorKind::UnexpectedEof` if the source
+/// really runs out.
 pub trait ArqRead {
+    /// Reads exactly `count` bytes, however many underlying reads that takes.
     fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>>;
     fn read_arq_string(&mut self) -> Result<String>;
+    /// Reads a string field as raw bytes, without checking that they're valid UTF-8. A
+    /// null string reads as empty.
+    fn read_arq_string_bytes(&mut self) -> Result<Vec<u8>>;
     fn read_arq_bool(&mut self) -> Result<bool>;
     fn read_arq_u32(&mut self) -> Result<u32>;
     fn read_arq_i32(&mut self) -> Result<i32>;
@@ -24,20 +59,35 @@ where
     T: Read,
 {
     fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>> {
-        let mut buffer = vec![0; count];
-        self.read_exact(&mut buffer[..])?;
+        if count <= MAX_PREALLOCATION {
+            let mut buffer = vec![0; count];
+            self.read_exact(&mut buffer[..])?;
+            return Ok(buffer);
+        }
+
+        // Lengths come from the input, so a corrupt one mustn't allocate before we know
+        // there's that much to read.
+        let mut buffer = Vec::with_capacity(MAX_PREALLOCATION);
+        self.by_ref().take(count as u64).read_to_end(&mut buffer)?;
+        if buffer.len() < count {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
         Ok(buffer)
     }
 
     fn read_arq_string(&mut self) -> Result<String> {
+        let data_bytes = self.read_arq_string_bytes()?;
+        Ok(std::str::from_utf8(&data_bytes)?.to_string())
+    }
+
+    fn read_arq_string_bytes(&mut self) -> Result<Vec<u8>> {
         let present = self.read_bytes(1)?;
 
         Ok(if present[0] == 0x01 {
             let strlen = self.read_u64::<NetworkEndian>()?;
-            let data_bytes = self.read_bytes(strlen as usize)?;
-            std::str::from_utf8(&data_bytes)?.to_string()
+            self.read_bytes(strlen as usize)?
         } else {
-            String::new()
+            Vec::new()
         })
     }
 
@@ -77,6 +127,72 @@ where
     }
 }
 
+/// Writes the primitive types used in Arq's binary formats, laid out the way [ArqRead]
+/// reads them back.
+///
+/// Implemented for every [Write].
+pub trait ArqWrite {
+    /// Writes a string. Empty strings are written as null, which is what they're read
+    /// back from.
+    fn write_arq_string(&mut self, value: &str) -> Result<()>;
+    fn write_arq_bool(&mut self, value: bool) -> Result<()>;
+    fn write_arq_u32(&mut self, value: u32) -> Result<()>;
+    fn write_arq_i32(&mut self, value: i32) -> Result<()>;
+    fn write_arq_u64(&mut self, value: u64) -> Result<()>;
+    fn write_arq_i64(&mut self, value: i64) -> Result<()>;
+    fn write_arq_compression_type(&mut self, value: &CompressionType) -> Result<()>;
+    fn write_arq_data(&mut self, value: &[u8]) -> Result<()>;
+    fn write_arq_date(&mut self, value: &Date) -> Result<()>;
+}
+
+impl<T: Write> ArqWrite for T {
+    fn write_arq_string(&mut self, value: &str) -> Result<()> {
+        if value.is_empty() {
+            self.write_all(&[0x00])?;
+        } else {
+            self.write_all(&[0x01])?;
+            self.write_arq_u64(value.len() as u64)?;
+            self.write_all(value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_arq_bool(&mut self, value: bool) -> Result<()> {
+        self.write_all(&[value as u8])?;
+        Ok(())
+    }
+
+    fn write_arq_u32(&mut self, value: u32) -> Result<()> {
+        Ok(self.write_u32::<NetworkEndian>(value)?)
+    }
+
+    fn write_arq_i32(&mut self, value: i32) -> Result<()> {
+        Ok(self.write_i32::<NetworkEndian>(value)?)
+    }
+
+    fn write_arq_u64(&mut self, value: u64) -> Result<()> {
+        Ok(self.write_u64::<NetworkEndian>(value)?)
+    }
+
+    fn write_arq_i64(&mut self, value: i64) -> Result<()> {
+        Ok(self.write_i64::<NetworkEndian>(value)?)
+    }
+
+    fn write_arq_compression_type(&mut self, value: &CompressionType) -> Result<()> {
+        value.write(self)
+    }
+
+    fn write_arq_data(&mut self, value: &[u8]) -> Result<()> {
+        self.write_arq_u64(value.len() as u64)?;
+        self.write_all(value)?;
+        Ok(())
+    }
+
+    fn write_arq_date(&mut self, value: &Date) -> Result<()> {
+        value.write(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,13 +210,67 @@ mod tests {
         assert_eq!(empty, reader.read_bytes(0).unwrap());
     }
 
+    /// Hands out at most `chunk` bytes per read, interrupting every other call.
+    struct ChunkedReader {
+        data: Cursor<Vec<u8>>,
+        chunk: usize,
+        interrupt: bool,
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let len = buf.len().min(self.chunk);
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_read_bytes_in_chunks() {
+        let mut reader = ChunkedReader {
+            data: Cursor::new(vec![1, 0, 0, 0, 0, 0, 0, 0, 4, 65, 72, 66, 72, 9]),
+            chunk: 3,
+            interrupt: false,
+        };
+
+        assert_eq!(reader.read_arq_string().unwrap(), "AHBH");
+        assert_eq!(reader.read_bytes(1).unwrap(), vec![9]);
+        match reader.read_bytes(1) {
+            Err(Error::IoError(err)) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof)
+            }
+            other => panic!("expected an EOF error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_header() {
+        assert_eq!(
+            read_header(&mut Cursor::new(vec![1, 2, 3]), 2, "test").unwrap(),
+            vec![1, 2]
+        );
+        assert!(matches!(
+            read_header(&mut Cursor::new(vec![]), 2, "test"),
+            Err(Error::UnexpectedEof {
+                while_reading: "test"
+            })
+        ));
+        assert!(matches!(
+            read_header(&mut Cursor::new(vec![1]), 2, "test"),
+            Err(Error::IoError(ref err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+
     #[test]
     fn test_read_arq_u32() {
         let mut reader = Cursor::new(vec![0, 0, 0, 2, 255, 255, 255, 255]);
         let mut n = reader.read_arq_u32().unwrap();
         assert_eq!(n, 2);
         n = reader.read_arq_u32().unwrap();
-        assert_eq!(n, std::u32::MAX);
+        assert_eq!(n, u32::MAX);
     }
 
     #[test]
@@ -134,7 +304,7 @@ mod tests {
         assert_eq!(n, -72057594037927937);
 
         n = reader.read_arq_i64().unwrap();
-        assert_eq!(n, std::i64::MAX);
+        assert_eq!(n, i64::MAX);
     }
 
     #[test]
@@ -150,6 +320,21 @@ mod tests {
         let mut ct_lz4_reader = Cursor::new(vec![0, 0, 0, 2]);
         ct = ct_lz4_reader.read_arq_compression_type().unwrap();
         assert_eq!(ct, CompressionType::LZ4);
+
+        let mut ct_unknown_reader = Cursor::new(vec![0, 0, 0, 3]);
+        assert!(matches!(
+            ct_unknown_reader.read_arq_compression_type(),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    fn test_read_bytes_longer_than_input() {
+        let mut reader = Cursor::new(vec![1, 2, 3]);
+        match reader.read_bytes(usize::MAX) {
+            Err(Error::IoError(err)) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+            _ => panic!("expected an UnexpectedEof error"),
+        }
     }
 
     #[test]
@@ -174,6 +359,24 @@ mod tests {
         assert_eq!(ct, "AHBH");
     }
 
+    #[test]
+    fn test_read_arq_string_bytes() {
+        let mut reader = Cursor::new(vec![
+            1, 0, 0, 0, 0, 0, 0, 0, 4, 65, 72, 66, 72, // "AHBH"
+            1, 0, 0, 0, 0, 0, 0, 0, 3, 65, 0xff, 0xfe, // not UTF-8
+            0,    // null
+        ]);
+        assert_eq!(reader.read_arq_string_bytes().unwrap(), b"AHBH");
+        assert_eq!(reader.read_arq_string_bytes().unwrap(), [65, 0xff, 0xfe]);
+        assert!(reader.read_arq_string_bytes().unwrap().is_empty());
+
+        let mut reader = Cursor::new(vec![1, 0, 0, 0, 0, 0, 0, 0, 3, 65, 0xff, 0xfe]);
+        assert!(matches!(
+            reader.read_arq_string(),
+            Err(Error::ConversionError(_))
+        ));
+    }
+
     #[test]
     fn test_read_arq_data() {
         let empty: Vec<u8> = vec![];
@@ -199,4 +402,53 @@ mod tests {
         ct = reader_with_date.read_arq_date().unwrap();
         assert_eq!(format!("{}", ct), "1987-05-17 17:29:45 UTC");
     }
+
+    #[test]
+    fn test_write_arq_primitives() {
+        let mut buf = Vec::new();
+        buf.write_arq_string("AHBH").unwrap();
+        buf.write_arq_string("").unwrap();
+        buf.write_arq_bool(true).unwrap();
+        buf.write_arq_bool(false).unwrap();
+        buf.write_arq_u32(u32::MAX).unwrap();
+        buf.write_arq_i32(-16777217).unwrap();
+        buf.write_arq_u64(4278190079).unwrap();
+        buf.write_arq_i64(i64::MIN).unwrap();
+        buf.write_arq_compression_type(&CompressionType::LZ4)
+            .unwrap();
+        buf.write_arq_data(&[1, 2, 3]).unwrap();
+        buf.write_arq_data(&[]).unwrap();
+        buf.write_arq_date(&Date {
+            milliseconds_since_epoch: 548271585000,
+        })
+        .unwrap();
+        buf.write_arq_date(&Date {
+            milliseconds_since_epoch: 0,
+        })
+        .unwrap();
+
+        assert_eq!(buf[..13], [1, 0, 0, 0, 0, 0, 0, 0, 4, 65, 72, 66, 72]);
+
+        let mut reader = Cursor::new(buf);
+        assert_eq!(reader.read_arq_string().unwrap(), "AHBH");
+        assert_eq!(reader.read_arq_string().unwrap(), "");
+        assert!(reader.read_arq_bool().unwrap());
+        assert!(!reader.read_arq_bool().unwrap());
+        assert_eq!(reader.read_arq_u32().unwrap(), u32::MAX);
+        assert_eq!(reader.read_arq_i32().unwrap(), -16777217);
+        assert_eq!(reader.read_arq_u64().unwrap(), 4278190079);
+        assert_eq!(reader.read_arq_i64().unwrap(), i64::MIN);
+        assert_eq!(
+            reader.read_arq_compression_type().unwrap(),
+            CompressionType::LZ4
+        );
+        assert_eq!(reader.read_arq_data().unwrap(), vec![1, 2, 3]);
+        assert!(reader.read_arq_data().unwrap().is_empty());
+        assert_eq!(
+            format!("{}", reader.read_arq_date().unwrap()),
+            "1987-05-17 17:39:45 UTC"
+        );
+        assert_eq!(reader.read_arq_date().unwrap().milliseconds_since_epoch, 0);
+        assert!(reader.read_bytes(1).is_err());
+    }
 }
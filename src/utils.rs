@@ -1,8 +1,49 @@
+use std::path::{Component, Path, PathBuf};
+
 /// Converts an array of u8 into a string of hex.
 pub fn convert_to_hex_string(array: &[u8]) -> String {
     array.iter().map(|a| format!("{:02x}", a)).collect()
 }
 
+/// Decodes %XX escapes (as found in `file://` URLs) into the bytes they stand for.
+///
+/// Returns `None` if an escape is malformed or the result isn't valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Drops root, prefix and `.`/`..` components, so that `/a/./b` and `a/b` both become
+/// `a/b`: paths inside a backup are always relative to the folder's root.
+pub fn relative_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Whether `name` looks like the UUIDs Arq names computers and folders with
+/// (`XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX`, hex digits in either case).
+pub fn is_uuid(name: &str) -> bool {
+    let groups: Vec<&str> = name.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -13,4 +54,27 @@ mod tests {
         assert_eq!(convert_to_hex_string(&data), "0c220b384e5c");
         assert_eq!(convert_to_hex_string(&[]), "");
     }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("/Users/some%20user").unwrap(),
+            "/Users/some user"
+        );
+        assert_eq!(percent_decode("/caf%C3%A9").unwrap(), "/café");
+        assert_eq!(percent_decode("/plain").unwrap(), "/plain");
+        assert_eq!(percent_decode("/bad%2"), None);
+        assert_eq!(percent_decode("/bad%zz"), None);
+        assert_eq!(percent_decode("/bad%ff"), None);
+    }
+
+    #[test]
+    fn test_is_uuid() {
+        assert!(is_uuid("AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D"));
+        assert!(is_uuid("aa16a39f-aedc-42a5-a15b-daa09ea22e1d"));
+        assert!(!is_uuid("AA16A39F-AEDC-42A5-A15B"));
+        assert!(!is_uuid("AA16A39F-AEDC-42A5-A15B-DAA09EA22E1Z"));
+        assert!(!is_uuid("AA16A39FA-EDC-42A5-A15B-DAA09EA22E1D"));
+        assert!(!is_uuid(".DS_Store"));
+    }
 }
@@ -19,3 +19,49 @@ pub fn get_folder_path() -> PathBuf {
 pub fn get_encryptionv3_path() -> PathBuf {
     get_computer_path().join("encryptionv3.dat")
 }
+
+/// The fixture computer's computerinfo, re-encoded as a binary plist (with an extra
+/// "hostName" key).
+pub fn get_binary_computerinfo_path() -> PathBuf {
+    get_fixtures_path().join("computerinfo-binary")
+}
+
+/// Serves the fixtures over HTTP/1.1 (GETs only, no ranges), returning the base URL.
+#[cfg(feature = "reqwest")]
+pub fn serve_fixtures() -> String {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line.split(' ').nth(1).unwrap().to_string();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+            }
+
+            let file = get_fixtures_path().join(path.trim_start_matches('/'));
+            let (status, body) = match std::fs::read(file) {
+                Ok(content) => ("200 OK", content),
+                Err(_) => ("404 Not Found", Vec::new()),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    format!("http://{addr}")
+}
@@ -24,15 +24,38 @@ fn test_load_computer_info() {
     assert_eq!(ci.uuid, "AA16A39F-AEDC-42A5-A15B-DAA09EA22E1D");
 }
 
+#[test]
+fn test_load_binary_computer_info() {
+    use arq::computer::ComputerInfo;
+
+    let reader = std::io::BufReader::new(
+        std::fs::File::open(common::get_binary_computerinfo_path()).unwrap(),
+    );
+    let ci = ComputerInfo::new(reader, common::COMPUTER.to_string()).unwrap();
+    assert_eq!(ci.computer_name, "my-computer-name");
+    assert_eq!(ci.user_name, "my-username");
+    assert_eq!(
+        ci.extra()["hostName"],
+        plist::Value::String("my-host".to_string())
+    );
+}
+
+#[test]
+fn test_list_computers() {
+    use arq::computer::list_computers;
+
+    let computers = list_computers(common::get_computer_path().parent().unwrap()).unwrap();
+    assert_eq!(computers.len(), 1);
+    assert_eq!(computers[0].uuid, common::COMPUTER);
+    assert_eq!(computers[0].computer_name, "my-computer-name");
+}
+
 #[test]
 fn test_loading_encrypted_object_dat() {
     use arq::{folder::Folder, object_encryption::EncryptionDat};
-    use std::io::{BufRead, BufReader};
+    use std::io::BufReader;
 
     let ec = common::get_encryptionv3_path();
-    let mut reader = BufReader::new(std::fs::File::open(&ec).unwrap());
-    let mut buf = Vec::new();
-    let _ = reader.read_until(b'-', &mut buf);
     let reader = BufReader::new(std::fs::File::open(ec).unwrap());
     let ec_dat = EncryptionDat::new(reader, common::ENCRYPTION_PASSWORD).unwrap();
 
@@ -40,6 +63,42 @@ fn test_loading_encrypted_object_dat() {
     let _ = Folder::new(&mut folder, &ec_dat.master_keys).unwrap();
 }
 
+#[test]
+fn test_encryption_v3_dat_known_answer() {
+    use arq::error::Error;
+    use arq::object_encryption::EncryptionDat;
+    use hex_literal::hex;
+
+    // Derived independently from the fixture: PBKDF2-HMAC-SHA1 (200000 rounds) of the
+    // password, an HMAC-SHA256 over IV + encrypted keys, then AES-256-CBC.
+    let content = std::fs::read(common::get_encryptionv3_path()).unwrap();
+    let ec_dat =
+        EncryptionDat::new(std::io::Cursor::new(&content), common::ENCRYPTION_PASSWORD).unwrap();
+    assert_eq!(
+        ec_dat.master_keys,
+        vec![
+            hex!("c799197994f4eae7110a7e9d251bf8dad06c7d7cb4fe71c4da44b250c62dcf34").to_vec(),
+            hex!("e789f9780b4adaf4882cffdce8809f0c5d93f95ff161ec5b6c6432012293c76a").to_vec(),
+            hex!("bb91a23140d262283d9cc0e55aa44ed0da3c8155946e531951ea2b526140b9ee").to_vec(),
+        ]
+    );
+
+    assert!(matches!(
+        EncryptionDat::new(std::io::Cursor::new(&content), "not-evu"),
+        Err(Error::WrongPassword)
+    ));
+
+    // The HMAC covers the IV (bytes 52..68) and the encrypted keys after it.
+    for offset in [52, 67, 68, content.len() - 1] {
+        let mut tampered = content.clone();
+        tampered[offset] ^= 1;
+        assert!(matches!(
+            EncryptionDat::new(std::io::Cursor::new(&tampered), common::ENCRYPTION_PASSWORD),
+            Err(Error::WrongPassword)
+        ));
+    }
+}
+
 #[test]
 fn test_generate_encryption_v3_dat() {
     use arq::object_encryption::EncryptionDat;
@@ -49,3 +108,112 @@ fn test_generate_encryption_v3_dat() {
     )
     .unwrap();
 }
+
+#[test]
+fn test_backupset_stats() {
+    use arq::backupset::BackupSet;
+
+    let backupset = BackupSet::open(common::get_computer_path()).unwrap();
+    assert_eq!(backupset.uuid(), common::COMPUTER);
+    assert_eq!(backupset.computer_info.computer_name, "my-computer-name");
+    assert_eq!(backupset.folder_uuids, vec![common::FOLDER]);
+
+    // The fixtures hold no packs or refs, only the folder itself.
+    let stats = backupset.stats().unwrap();
+    assert_eq!(stats.folders.len(), 1);
+    assert_eq!(stats.folders[0].uuid, common::FOLDER);
+    assert_eq!(stats.folders[0].packs, 0);
+    assert_eq!(stats.folders[0].latest_commit, None);
+    assert_eq!(stats.total_pack_bytes(), 0);
+}
+
+#[test]
+fn test_backupset_validate_all() {
+    use arq::backupset::{BackupSet, CheckKind};
+    use std::collections::HashMap;
+
+    let backupset = BackupSet::open(common::get_computer_path()).unwrap();
+    let report = backupset
+        .validate_all(
+            common::ENCRYPTION_PASSWORD,
+            &HashMap::<String, Vec<u8>>::new(),
+        )
+        .unwrap();
+    assert!(report.is_healthy());
+    assert_eq!(report.count(CheckKind::EncryptionDat), (1, 0));
+    assert_eq!(report.checks.len(), 1);
+}
+
+#[cfg(feature = "reqwest")]
+#[test]
+fn test_backupset_open_remote() {
+    use arq::backupset::BackupSet;
+    use arq::error::Error;
+    use arq::resolver::BlobResolver;
+
+    let base_url = format!("{}/{}", common::serve_fixtures(), common::COMPUTER);
+    let mut backupset = BackupSet::open_remote(&base_url, common::ENCRYPTION_PASSWORD).unwrap();
+    assert_eq!(backupset.uuid(), common::COMPUTER);
+    assert_eq!(backupset.computer_info.computer_name, "my-computer-name");
+    assert!(backupset.folder_uuids.is_empty());
+    backupset.folder_uuids.push(common::FOLDER.to_string());
+
+    let master_keys = backupset
+        .encryption_dat(common::ENCRYPTION_PASSWORD)
+        .unwrap()
+        .into_master_keys();
+    let folder = backupset.folder(common::FOLDER, &master_keys).unwrap();
+    assert_eq!(folder.bucket_uuid, common::FOLDER);
+    assert_eq!(folder.computer_uuid, common::COMPUTER);
+
+    // The fixtures hold no refs or packs.
+    match backupset.master_ref(common::FOLDER) {
+        Err(Error::IoError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+        result => panic!("expected a NotFound error, got {result:?}"),
+    }
+    let cache = tempfile::tempdir().unwrap();
+    let resolver = backupset
+        .remote_resolver(common::FOLDER, &[], &master_keys, cache.path())
+        .unwrap();
+    assert!(matches!(
+        resolver.resolve("0000000000000000000000000000000000000000"),
+        Err(Error::ObjectNotFound(_))
+    ));
+    let blobs = backupset
+        .remote_blobs_resolver(common::FOLDER, &[], &master_keys)
+        .unwrap();
+    assert!(blobs.is_empty());
+    assert!(matches!(
+        backupset.remote_blobs_resolver(common::FOLDER, &["0000"], &master_keys),
+        Err(Error::ObjectNotFound(_))
+    ));
+
+    // There's no directory to list or stat.
+    for result in [
+        backupset.stats().map(drop),
+        backupset.bucketdata(common::FOLDER).map(drop),
+        backupset.blobs_path(common::FOLDER).map(drop),
+        backupset
+            .validate_all(common::ENCRYPTION_PASSWORD, &resolver)
+            .map(drop),
+    ] {
+        match result {
+            Err(Error::IoError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::Unsupported),
+            result => panic!("expected an Unsupported error, got {result:?}"),
+        }
+    }
+
+    assert!(matches!(
+        BackupSet::open_remote(&base_url, "wrong"),
+        Err(Error::WrongPassword)
+    ));
+    let local = BackupSet::open(common::get_computer_path()).unwrap();
+    assert!(matches!(
+        local.remote_resolver(common::FOLDER, &[], &master_keys, cache.path()),
+        Err(Error::PathNotFound(_))
+    ));
+    assert!(matches!(
+        local.remote_blobs_resolver(common::FOLDER, &[], &master_keys),
+        Err(Error::PathNotFound(_))
+    ));
+}